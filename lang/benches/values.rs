@@ -0,0 +1,95 @@
+// Times programs that read large arrays, vectors and strings over and over,
+// next to the same programs made to copy those values on every read, which
+// is what each read cost before values were shared. Run with `cargo bench`.
+
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 3;
+
+// (name, program that only reads, program that copies on every read)
+const CASES: &[(&str, &str, &str)] = &[
+    (
+        "vector passed to a function",
+        "func first(v: [i32]) -> i32 [
+            return v[0];
+        ]
+        func main() [
+            let v: [i32] = {};
+            for i in 0..50000 [ push(v, i); ]
+            let total = 0;
+            for i in 0..1000 [ total = total + first(v); ]
+            print(total);
+        ]",
+        "func first(v: [i32]) -> i32 [
+            v[0] = 0;
+            return v[0];
+        ]
+        func main() [
+            let v: [i32] = {};
+            for i in 0..50000 [ push(v, i); ]
+            let total = 0;
+            for i in 0..1000 [ total = total + first(v); ]
+            print(total);
+        ]",
+    ),
+    (
+        "string bound to a variable",
+        "func main() [
+            let s = \"ab\";
+            for i in 0..16 [ s = s + s; ]
+            let total = 0;
+            for i in 0..5000 [
+                let t = s;
+                if t != \"\" [ total = total + 1; ]
+            ]
+            print(total);
+        ]",
+        "func main() [
+            let s = \"ab\";
+            for i in 0..16 [ s = s + s; ]
+            let total = 0;
+            for i in 0..5000 [
+                let t = s + \"\";
+                if t != \"\" [ total = total + 1; ]
+            ]
+            print(total);
+        ]",
+    ),
+];
+
+// the fastest of a few runs of `lang execute` on `source`
+fn time(name: &str, source: &str) -> Duration {
+    let path = env::temp_dir().join(format!("lang-bench-{}-{}.txt", std::process::id(), name));
+    fs::write(&path, source).expect("cannot write benchmark program");
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let output = Command::new(env!("CARGO_BIN_EXE_lang"))
+            .arg("execute")
+            .arg(&path)
+            .output()
+            .expect("cannot run lang");
+        let elapsed = start.elapsed();
+        assert!(output.status.success(), "{} failed:\n{}", name, String::from_utf8_lossy(&output.stdout));
+        best = best.min(elapsed);
+    }
+    let _ = fs::remove_file(&path);
+    best
+}
+
+fn main() {
+    for (i, (name, shared, copied)) in CASES.iter().enumerate() {
+        let shared = time(&format!("{}-shared", i), shared);
+        let copied = time(&format!("{}-copied", i), copied);
+        println!(
+            "{:<30} shared {:>8.1?}   copied {:>8.1?}   {:.1}x",
+            name,
+            shared,
+            copied,
+            copied.as_secs_f64() / shared.as_secs_f64()
+        );
+    }
+}
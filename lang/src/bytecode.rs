@@ -0,0 +1,936 @@
+// The `vm` engine. Rather than walking the semantic tree node by node, the
+// checked and optimized program is lowered once to a Chunk: one flat list of
+// instructions for the whole program, which the Vm then runs in a loop over
+// a stack of values. Each function's locals sit on that stack under its
+// temporaries, so a call is a jump and a return truncates the stack.
+//
+// The Vm runs programs exactly as the interpreter does, down to where a
+// runtime error is reported and the calls listed with it, so `difftest`
+// can hold the two engines to each other.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::diagnostics::Diagnostic;
+use crate::heap::Block;
+use crate::intern::Symbol;
+use crate::interpreter::{
+    cast, checked_index, default_value, eval_binary, eval_unary, runtime_error, Call, Fault, Halt, Host, MapKey, Value,
+    INTERPRETER_BUILTINS,
+};
+use crate::semantic::{resolve_slots, Ast, BinOp, MTree, NodeId, Slot, Type, UnOp};
+use crate::stack;
+use crate::token::Span;
+
+/// One instruction. Operands index the chunk's constants, types, names or
+/// functions, or give the instruction to jump to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    // push a constant
+    Constant(u32),
+    Load(Slot),
+    // set the variable to the value on top, leaving it there
+    Store(Slot),
+    // pop the value on top into the next program-scope variable, which
+    // until then cannot be read or set
+    Define(u32),
+    Pop,
+    // replace the top n values with an array of them
+    Array(u32),
+    // replace an array and an index with the element there
+    Index,
+    // with an index and a value on top, set that element of the array
+    // variable, leaving the value
+    StoreIndex(Slot),
+    Unary(UnOp),
+    Binary(BinOp),
+    // convert the value on top to a type
+    Cast(u32),
+    Jump(u32),
+    // pop a bool, and jump if it is false
+    JumpIfFalse(u32),
+    // with a counter and its end on top: while the counter is short of the
+    // end, set the variable to it and count on; then pop both and jump
+    ForNext(Slot, u32),
+    // call a function with the top n values as its arguments
+    Call(u32, u32),
+    // call a function in place of the running one
+    TailCall(u32, u32),
+    // call the native function of a name
+    Native(u32, u32),
+    // run one of INTERPRETER_BUILTINS
+    Builtin(u32, u32),
+    // the builtins that change a variable in place; those that can fail
+    // name the variable for the error message
+    VecPush(Slot),
+    VecPop(Slot, u32),
+    MapInsert(Slot),
+    MapRemove(Slot, u32),
+    Print,
+    // return the value on top to the caller
+    Return,
+    // the end of the program-scope code, where main returns to
+    End,
+}
+
+/// A function of the program, as the chunk holds it.
+#[derive(Debug)]
+pub struct Function {
+    pub name: Symbol,
+    pub arity: usize,
+    // slots a call needs, parameters first
+    pub frame: usize,
+    // its first instruction
+    pub entry: usize,
+}
+
+/// A program lowered to instructions. The code that sets up the globals
+/// comes first and ends with `End`; each function's body follows.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    // where in the source each instruction came from
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+    pub types: Vec<Type>,
+    // native functions and variables the instructions mention by name
+    pub names: Vec<Symbol>,
+    pub functions: Vec<Function>,
+    // each enum's variants in order, for casts to int
+    pub enums: HashMap<String, Vec<String>>,
+    // the program-scope variables by slot, for errors that name them
+    pub globals: Vec<Symbol>,
+    // the instructions that run part of an inlined call, and the function
+    // called and where, so a runtime error's trace can name the call
+    pub inlined: Vec<(Range<usize>, Symbol, Span)>,
+}
+
+impl Chunk {
+    fn emit(&mut self, op: Op, span: Span) -> usize {
+        self.code.push(op);
+        self.spans.push(span);
+        self.code.len() - 1
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        self.constants.push(value);
+        (self.constants.len() - 1) as u32
+    }
+
+    fn name(&mut self, name: Symbol) -> u32 {
+        match self.names.iter().position(|n| *n == name) {
+            Some(i) => i as u32,
+            None => {
+                self.names.push(name);
+                (self.names.len() - 1) as u32
+            }
+        }
+    }
+
+    // the inlined calls the instruction at `at` runs inside, outermost first
+    fn inlined_at(&self, at: usize) -> Vec<Call> {
+        let mut calls: Vec<&(Range<usize>, Symbol, Span)> = self.inlined.iter().filter(|(code, _, _)| code.contains(&at)).collect();
+        calls.sort_by_key(|(code, _, _)| (code.start, Reverse(code.end)));
+        calls.into_iter().map(|&(_, function, site)| (function, Some(site))).collect()
+    }
+
+    // the jump at `at` goes to the next instruction emitted
+    fn patch(&mut self, at: usize) {
+        let target = self.code.len() as u32;
+        match &mut self.code[at] {
+            Op::Jump(to) | Op::JumpIfFalse(to) | Op::ForNext(_, to) => *to = target,
+            op => unreachable!("{:?} is not a jump", op),
+        }
+    }
+
+    /// The code as text, an instruction per line: its offset, the source
+    /// line it came from (`|` when that is the line above's, `-` when it
+    /// has none), its name and
+    /// operands, and after a `;` what an index operand refers to. Each
+    /// function starts under a header of its own.
+    pub fn disassemble(&self) -> String {
+        let mut text = String::from("== globals ==\n");
+        let mut line = None;
+        for (offset, op) in self.code.iter().enumerate() {
+            if let Some(function) = self.functions.iter().find(|f| f.entry == offset) {
+                let _ = writeln!(text, "\n== {} ({} param(s), {} slot(s)) ==", function.name, function.arity, function.frame);
+                line = None;
+            }
+            let here = self.spans[offset].line;
+            let shown = match here {
+                0 => "-".to_string(),
+                _ if line == Some(here) => "|".to_string(),
+                _ => here.to_string(),
+            };
+            line = Some(here);
+            let (name, operands, note) = self.describe(*op);
+            let mut row = format!("{:04} {:>4}  {:<14}{}", offset, shown, name, operands);
+            if let Some(note) = note {
+                row = format!("{:<40}; {}", row, note);
+            }
+            let _ = writeln!(text, "{}", row.trim_end());
+        }
+        text
+    }
+
+    // an instruction's name, its operands as written, and what they refer to
+    fn describe(&self, op: Op) -> (&'static str, String, Option<String>) {
+        let slot = |slot: Slot| match slot {
+            Slot::Local(i) => format!("local {}", i),
+            Slot::Global(i) => format!("global {}", i),
+            Slot::Unresolved => "unresolved".to_string(),
+        };
+        let function = |i: u32| self.functions[i as usize].name.to_string();
+        match op {
+            Op::Constant(i) => ("constant", format!("#{}", i), Some(show_constant(&self.constants[i as usize]))),
+            Op::Load(s) => ("load", slot(s), None),
+            Op::Store(s) => ("store", slot(s), None),
+            Op::Define(i) => ("define", slot(Slot::Global(i as usize)), Some(self.globals[i as usize].to_string())),
+            Op::Pop => ("pop", String::new(), None),
+            Op::Array(n) => ("array", n.to_string(), None),
+            Op::Index => ("index", String::new(), None),
+            Op::StoreIndex(s) => ("store_index", slot(s), None),
+            Op::Unary(op) => ("unary", op.to_string(), None),
+            Op::Binary(op) => ("binary", op.to_string(), None),
+            Op::Cast(i) => ("cast", format!("#{}", i), Some(format!("{:?}", self.types[i as usize]))),
+            Op::Jump(to) => ("jump", format!("-> {:04}", to), None),
+            Op::JumpIfFalse(to) => ("jump_if_false", format!("-> {:04}", to), None),
+            Op::ForNext(s, to) => ("for_next", format!("{} -> {:04}", slot(s), to), None),
+            Op::Call(f, argc) => ("call", format!("#{} {}", f, argc), Some(function(f))),
+            Op::TailCall(f, argc) => ("tail_call", format!("#{} {}", f, argc), Some(function(f))),
+            Op::Native(name, argc) => ("native", format!("#{} {}", name, argc), Some(self.names[name as usize].to_string())),
+            Op::Builtin(b, argc) => ("builtin", format!("#{} {}", b, argc), Some(INTERPRETER_BUILTINS[b as usize].to_string())),
+            Op::VecPush(s) => ("vec_push", slot(s), None),
+            Op::VecPop(s, var) => ("vec_pop", slot(s), Some(self.names[var as usize].to_string())),
+            Op::MapInsert(s) => ("map_insert", slot(s), None),
+            Op::MapRemove(s, var) => ("map_remove", slot(s), Some(self.names[var as usize].to_string())),
+            Op::Print => ("print", String::new(), None),
+            Op::Return => ("return", String::new(), None),
+            Op::End => ("end", String::new(), None),
+        }
+    }
+}
+
+// a constant as the source would write it, so strings and chars show quoted
+fn show_constant(value: &Value) -> String {
+    match value {
+        Value::Str(s) => format!("{:?}", s),
+        Value::Char(c) => format!("{:?}", c),
+        other => other.to_string(),
+    }
+}
+
+// a loop being compiled, for the breaks and continues inside it
+struct Loop {
+    label: Option<Symbol>,
+    // where a continue goes
+    start: usize,
+    // breaks to patch once the loop's end is known
+    breaks: Vec<usize>,
+    // values the loop keeps on the stack while it runs: a for loop's
+    // counter and end
+    held: usize,
+}
+
+struct Compiler<'a> {
+    ast: &'a Ast,
+    host: &'a Host,
+    chunk: Chunk,
+    functions: HashMap<Symbol, u32>,
+    // variant name -> enum it belongs to
+    variants: HashMap<String, String>,
+    loops: Vec<Loop>,
+}
+
+/// Lower a checked program to a chunk. `host` tells native functions from
+/// the program's own.
+pub fn compile(mut ast: Ast, host: &Host) -> Result<Chunk, String> {
+    resolve_slots(&mut ast);
+    let mut compiler = Compiler {
+        ast: &ast,
+        host,
+        chunk: Chunk::default(),
+        functions: HashMap::new(),
+        variants: HashMap::new(),
+        loops: Vec::new(),
+    };
+    compiler.program()?;
+    Ok(compiler.chunk)
+}
+
+impl Compiler<'_> {
+    fn program(&mut self) -> Result<(), String> {
+        let ast = self.ast;
+        let MTree::START { funcs } = &ast[ast.root] else { return Err("Expected START node".to_string()) };
+
+        // functions get their places first, so calls can name any of them
+        for &item in funcs {
+            match &ast[item] {
+                MTree::FUNC_DECL { name, params, frame, .. } => {
+                    self.functions.insert(*name, self.chunk.functions.len() as u32);
+                    self.chunk.functions.push(Function { name: *name, arity: params.len(), frame: *frame, entry: 0 });
+                }
+                MTree::ENUM_DECL { name, variants, .. } => {
+                    for v in variants {
+                        self.variants.insert(v.clone(), name.clone());
+                    }
+                    self.chunk.enums.insert(name.clone(), variants.clone());
+                }
+                _ => {}
+            }
+        }
+
+        // globals in order, outside any call
+        for &item in funcs {
+            match &ast[item] {
+                MTree::LET_STMT { .. } => self.statement(item)?,
+                MTree::CONST_DECL { id, slot, expr, span, .. } => {
+                    self.expression(*expr)?;
+                    self.store_new(*id, *slot, *span);
+                }
+                _ => {}
+            }
+        }
+        let end = ast.span(ast.root).unwrap_or_default();
+        self.chunk.emit(Op::End, end);
+
+        for &item in funcs {
+            if let MTree::FUNC_DECL { name, body, span, .. } = &ast[item] {
+                let index = self.functions[name] as usize;
+                self.chunk.functions[index].entry = self.chunk.code.len();
+                self.statement(*body)?;
+                // falling off the end returns nothing
+                let void = self.chunk.constant(Value::Void);
+                self.chunk.emit(Op::Constant(void), *span);
+                self.chunk.emit(Op::Return, *span);
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: NodeId) -> Result<(), String> {
+        let start = self.chunk.code.len();
+        stack::guarded(|| self.statement_node(stmt))?;
+        if let Some((function, site)) = self.ast.inlined(stmt) {
+            self.chunk.inlined.push((start..self.chunk.code.len(), function, site));
+        }
+        Ok(())
+    }
+
+    fn statement_node(&mut self, stmt: NodeId) -> Result<(), String> {
+        let ast = self.ast;
+        let span = ast.span(stmt).unwrap_or_default();
+        match &ast[stmt] {
+            MTree::BLOCK { stmts, .. } => {
+                for &stmt in stmts {
+                    self.statement(stmt)?;
+                }
+            }
+
+            MTree::LET_STMT { id, slot, ty, expr, .. } => {
+                match expr {
+                    Some(expr) => self.expression(*expr)?,
+                    None => {
+                        let value = self.chunk.constant(default_value(ty, &self.chunk.enums));
+                        self.chunk.emit(Op::Constant(value), span);
+                    }
+                }
+                self.store_new(*id, *slot, span);
+            }
+
+            MTree::RTRN_STMT { expr, .. } => {
+                // a call to a program function takes over this call's place
+                if let Some(MTree::CALL { name, args, span: call }) = expr.map(|e| &ast[e])
+                    && let Some(&function) = self.functions.get(name)
+                    && !self.host.is_native(*name)
+                    && !INTERPRETER_BUILTINS.contains(&name.as_str())
+                {
+                    for &arg in args {
+                        self.expression(arg)?;
+                    }
+                    self.chunk.emit(Op::TailCall(function, args.len() as u32), *call);
+                    return Ok(());
+                }
+                match expr {
+                    Some(expr) => self.expression(*expr)?,
+                    None => {
+                        let void = self.chunk.constant(Value::Void);
+                        self.chunk.emit(Op::Constant(void), span);
+                    }
+                }
+                self.chunk.emit(Op::Return, span);
+            }
+
+            MTree::IF_STMT { cond, then_block, else_block, .. } => {
+                self.expression(*cond)?;
+                let skip_then = self.chunk.emit(Op::JumpIfFalse(0), span);
+                self.statement(*then_block)?;
+                match else_block {
+                    Some(else_block) => {
+                        let skip_else = self.chunk.emit(Op::Jump(0), span);
+                        self.chunk.patch(skip_then);
+                        self.statement(*else_block)?;
+                        self.chunk.patch(skip_else);
+                    }
+                    None => self.chunk.patch(skip_then),
+                }
+            }
+
+            MTree::WHILE_STMT { cond, body, label, .. } => {
+                let start = self.chunk.code.len();
+                self.expression(*cond)?;
+                let exit = self.chunk.emit(Op::JumpIfFalse(0), span);
+                self.loop_body(*body, *label, start, 0, span)?;
+                self.chunk.patch(exit);
+            }
+
+            MTree::LOOP_STMT { body, label, .. } => {
+                let start = self.chunk.code.len();
+                self.loop_body(*body, *label, start, 0, span)?;
+            }
+
+            MTree::FOR_STMT { slot, start, end, body, label, .. } => {
+                self.expression(*start)?;
+                self.expression(*end)?;
+                let next = self.chunk.emit(Op::ForNext(*slot, 0), span);
+                self.loop_body(*body, *label, next, 2, span)?;
+                self.chunk.patch(next);
+            }
+
+            MTree::BREAK_STMT { label, .. } => {
+                let target = self.loop_for(*label, "break")?;
+                self.release(target, span);
+                let jump = self.chunk.emit(Op::Jump(0), span);
+                self.loops[target].breaks.push(jump);
+            }
+
+            MTree::CONTINUE_STMT { label, .. } => {
+                let target = self.loop_for(*label, "continue")?;
+                self.release(target, span);
+                let start = self.loops[target].start as u32;
+                self.chunk.emit(Op::Jump(start), span);
+            }
+
+            MTree::PRINT_STMT { expr, .. } => {
+                self.expression(*expr)?;
+                self.chunk.emit(Op::Print, span);
+            }
+
+            // an expression statement, assignments included
+            _ => {
+                self.expression(stmt)?;
+                self.chunk.emit(Op::Pop, span);
+            }
+        }
+        Ok(())
+    }
+
+    // the body of a loop that starts over at `start`, with `held` values of
+    // its own on the stack; a break leaves through code that drops them
+    fn loop_body(&mut self, body: NodeId, label: Option<Symbol>, start: usize, held: usize, span: Span) -> Result<(), String> {
+        self.loops.push(Loop { label, start, breaks: Vec::new(), held });
+        let result = self.statement(body);
+        let the_loop = self.loops.pop().expect("the loop was pushed above");
+        result?;
+        self.chunk.emit(Op::Jump(start as u32), span);
+        // breaks land here, past the jump back; a for loop that runs out
+        // drops its values itself and jumps past these pops
+        for &jump in &the_loop.breaks {
+            self.chunk.patch(jump);
+        }
+        if !the_loop.breaks.is_empty() {
+            for _ in 0..held {
+                self.chunk.emit(Op::Pop, span);
+            }
+        }
+        Ok(())
+    }
+
+    // the loop a break or continue with `label` leaves, innermost first
+    // move the value on top into the variable `id` declares
+    fn store_new(&mut self, id: Symbol, slot: Slot, span: Span) {
+        match slot {
+            Slot::Global(i) => {
+                self.chunk.globals.push(id);
+                self.chunk.emit(Op::Define(i as u32), span);
+            }
+            slot => {
+                self.chunk.emit(Op::Store(slot), span);
+                self.chunk.emit(Op::Pop, span);
+            }
+        }
+    }
+
+    fn loop_for(&self, label: Option<Symbol>, keyword: &str) -> Result<usize, String> {
+        self.loops
+            .iter()
+            .rposition(|l| label.is_none() || l.label == label)
+            .ok_or_else(|| format!("'{}' outside of a loop", keyword))
+    }
+
+    // drop what the loops inside `target` hold, on the way out of them
+    fn release(&mut self, target: usize, span: Span) {
+        let held: usize = self.loops[target + 1..].iter().map(|l| l.held).sum();
+        for _ in 0..held {
+            self.chunk.emit(Op::Pop, span);
+        }
+    }
+
+    fn expression(&mut self, expr: NodeId) -> Result<(), String> {
+        stack::guarded(|| self.expression_node(expr))
+    }
+
+    fn expression_node(&mut self, expr: NodeId) -> Result<(), String> {
+        let ast = self.ast;
+        let span = ast.span(expr).unwrap_or_default();
+        let literal = match &ast[expr] {
+            MTree::LIT_INT { value, .. } => Some(Value::Int(*value)),
+            MTree::LIT_FLOAT { value, .. } => Some(Value::Float(*value)),
+            MTree::LIT_BOOL { value, .. } => Some(Value::Bool(*value)),
+            MTree::LIT_CHAR { value, .. } => Some(Value::Char(*value)),
+            MTree::LIT_STRING { value, .. } => Some(Value::Str(value.as_str().into())),
+            MTree::LIT_NONE { .. } => Some(Value::Option(None)),
+            MTree::ID { name, slot: Slot::Unresolved, .. } => match self.variants.get(name.as_str()) {
+                Some(enum_name) => Some(Value::Enum { enum_name: enum_name.clone(), variant: name.to_string() }),
+                None => return Err(format!("Variable '{}' not found", name)),
+            },
+            _ => None,
+        };
+        if let Some(value) = literal {
+            let constant = self.chunk.constant(value);
+            self.chunk.emit(Op::Constant(constant), span);
+            return Ok(());
+        }
+
+        match &ast[expr] {
+            MTree::ID { slot, .. } => {
+                self.chunk.emit(Op::Load(*slot), span);
+            }
+
+            MTree::ARRAY_LIT { elems, .. } => {
+                for &elem in elems {
+                    self.expression(elem)?;
+                }
+                self.chunk.emit(Op::Array(elems.len() as u32), span);
+            }
+
+            MTree::INDEX { base, index, .. } => {
+                self.expression(*base)?;
+                self.expression(*index)?;
+                self.chunk.emit(Op::Index, span);
+            }
+
+            // only the chosen arm is evaluated
+            MTree::TERNARY { cond, then_expr, else_expr, .. } => {
+                self.expression(*cond)?;
+                let skip_then = self.chunk.emit(Op::JumpIfFalse(0), span);
+                self.expression(*then_expr)?;
+                let skip_else = self.chunk.emit(Op::Jump(0), span);
+                self.chunk.patch(skip_then);
+                self.expression(*else_expr)?;
+                self.chunk.patch(skip_else);
+            }
+
+            MTree::CAST { expr, ty, .. } => {
+                self.expression(*expr)?;
+                self.chunk.types.push(ty.clone());
+                self.chunk.emit(Op::Cast(self.chunk.types.len() as u32 - 1), span);
+            }
+
+            MTree::CALL { name, args, .. } => self.call(*name, args, span)?,
+
+            MTree::UNARY { op, expr, .. } => {
+                self.expression(*expr)?;
+                self.chunk.emit(Op::Unary(*op), span);
+            }
+
+            MTree::EXPR { left, op, right, .. } => {
+                self.expression(*left)?;
+                self.expression(*right)?;
+                self.chunk.emit(Op::Binary(*op), span);
+            }
+
+            MTree::ASSIGN { slot, expr, .. } => {
+                self.expression(*expr)?;
+                self.chunk.emit(Op::Store(*slot), span);
+            }
+
+            MTree::INDEX_ASSIGN { slot, index, expr, .. } => {
+                self.expression(*index)?;
+                self.expression(*expr)?;
+                self.chunk.emit(Op::StoreIndex(*slot), span);
+            }
+
+            other => return Err(format!("Cannot evaluate expression: {:?}", other)),
+        }
+        Ok(())
+    }
+
+    // builtins first, then native functions, then the program's own
+    fn call(&mut self, name: Symbol, args: &[NodeId], span: Span) -> Result<(), String> {
+        let ast = self.ast;
+        if let Some(builtin) = INTERPRETER_BUILTINS.iter().position(|b| *b == name.as_str()) {
+            // these change the variable given first, which is not evaluated
+            if let "push" | "pop" | "insert" | "remove" = name.as_str() {
+                let MTree::ID { name: var, slot, .. } = &ast[args[0]] else {
+                    let kind = if let "push" | "pop" = name.as_str() { "vector" } else { "map" };
+                    return Err(format!("'{}' expects a {} variable", name, kind));
+                };
+                for &arg in &args[1..] {
+                    self.expression(arg)?;
+                }
+                let var = self.chunk.name(*var);
+                let op = match name.as_str() {
+                    "push" => Op::VecPush(*slot),
+                    "pop" => Op::VecPop(*slot, var),
+                    "insert" => Op::MapInsert(*slot),
+                    _ => Op::MapRemove(*slot, var),
+                };
+                self.chunk.emit(op, span);
+                return Ok(());
+            }
+            for &arg in args {
+                self.expression(arg)?;
+            }
+            self.chunk.emit(Op::Builtin(builtin as u32, args.len() as u32), span);
+            return Ok(());
+        }
+
+        for &arg in args {
+            self.expression(arg)?;
+        }
+        let op = if self.host.is_native(name) {
+            Op::Native(self.chunk.name(name), args.len() as u32)
+        } else {
+            let function = self.functions.get(&name).ok_or_else(|| format!("Function '{}' not found", name))?;
+            Op::Call(*function, args.len() as u32)
+        };
+        self.chunk.emit(op, span);
+        Ok(())
+    }
+}
+
+// a call under way: the function, where it was called from, where its
+// slots start on the stack and where it returns to
+struct Frame {
+    function: u32,
+    site: Option<Span>,
+    base: usize,
+    ret: usize,
+}
+
+/// Runs chunks.
+pub struct Vm {
+    host: Host,
+    stack: Vec<Value>,
+    globals: Vec<Value>,
+    // the calls under way, outermost first
+    frames: Vec<Frame>,
+    fault: Option<Fault>,
+}
+
+impl Vm {
+    pub fn new(host: Host) -> Self {
+        Self { host, stack: Vec::new(), globals: Vec::new(), frames: Vec::new(), fault: None }
+    }
+
+    /// Run the program, returning the status it ends with: the one it gives
+    /// `exit`, or 0 when `main` returns.
+    pub fn execute(&mut self, chunk: &Chunk) -> Result<i32, Diagnostic> {
+        let result = self.run(chunk, 0).and_then(|()| {
+            // main returns to the End that closes the globals' code
+            let end = chunk.code.iter().position(|op| *op == Op::End).unwrap_or_default();
+            let main = Symbol::intern("main");
+            let main = chunk
+                .functions
+                .iter()
+                .position(|f| f.name == main)
+                .ok_or_else(|| format!("Function '{}' not found", main))?;
+            let entry = self.enter(chunk, main as u32, 0, None, end)?;
+            self.run(chunk, entry)
+        });
+        match result {
+            Ok(()) => Ok(0),
+            Err(Halt::Exit(status)) => Ok(status),
+            Err(Halt::Error(message)) => Err(runtime_error(message, self.fault.take())),
+        }
+    }
+
+    // run from `ip` until the next End
+    fn run(&mut self, chunk: &Chunk, mut ip: usize) -> Result<(), Halt> {
+        loop {
+            let at = ip;
+            match self.step(chunk, &mut ip) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(halt) => {
+                    if let Halt::Error(_) = halt {
+                        self.fault = Some(Fault { span: chunk.spans[at], calls: self.calls(chunk, at) });
+                    }
+                    return Err(halt);
+                }
+            }
+        }
+    }
+
+    // the calls under way when the instruction at `at` failed, as a runtime
+    // error lists them: each frame, then the inlined calls it was inside of
+    // at the instruction it was running, which for all but the innermost is
+    // the call it waits on
+    fn calls(&self, chunk: &Chunk, at: usize) -> Vec<Call> {
+        let mut calls = Vec::new();
+        for (i, frame) in self.frames.iter().enumerate() {
+            calls.push((chunk.functions[frame.function as usize].name, frame.site));
+            let here = self.frames.get(i + 1).map_or(at, |callee| callee.ret - 1);
+            calls.extend(chunk.inlined_at(here));
+        }
+        calls
+    }
+
+    // start a call to `function` with the top `argc` values as its
+    // arguments, returning where its code starts
+    fn enter(&mut self, chunk: &Chunk, function: u32, argc: usize, site: Option<Span>, ret: usize) -> Result<usize, String> {
+        let callee = &chunk.functions[function as usize];
+        check_arity(callee, argc)?;
+        if self.frames.len() >= self.host.max_depth() {
+            return Err(format!("maximum recursion depth exceeded ({})", self.host.max_depth()));
+        }
+        let base = self.stack.len() - argc;
+        self.stack.resize(base + callee.frame.max(argc), Value::Void);
+        self.frames.push(Frame { function, site, base, ret });
+        Ok(callee.entry)
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the stack holds an instruction's operands")
+    }
+
+    // the top `n` values, in the order they were pushed
+    fn pop_n(&mut self, n: usize) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - n)
+    }
+
+    // the variable in `slot`, to read or change in place; a global only
+    // exists once its Define has run
+    fn variable(&mut self, chunk: &Chunk, slot: Slot) -> Result<&mut Value, String> {
+        match slot {
+            Slot::Local(i) => {
+                let base = self.frames.last().map_or(0, |frame| frame.base);
+                Ok(&mut self.stack[base + i])
+            }
+            Slot::Global(i) => self.globals.get_mut(i).ok_or_else(|| format!("Variable '{}' not found", chunk.globals[i])),
+            Slot::Unresolved => Err("Variable not found".to_string()),
+        }
+    }
+
+    // run the instruction at `ip` and move past it; false at an End
+    fn step(&mut self, chunk: &Chunk, ip: &mut usize) -> Result<bool, Halt> {
+        let op = chunk.code[*ip];
+        *ip += 1;
+        match op {
+            Op::Constant(i) => self.stack.push(chunk.constants[i as usize].clone()),
+            Op::Load(slot) => {
+                let value = self.variable(chunk, slot)?.clone();
+                self.stack.push(value);
+            }
+            Op::Store(slot) => {
+                let value = self.stack.last().expect("a value to store").clone();
+                *self.variable(chunk, slot)? = value;
+            }
+            // globals are defined in slot order
+            Op::Define(_) => {
+                let value = self.pop();
+                self.globals.push(value);
+            }
+            Op::Pop => {
+                self.pop();
+            }
+            Op::Array(n) => {
+                let elems = self.pop_n(n as usize);
+                self.stack.push(Value::Array(Rc::new(Block::new(elems))));
+            }
+            Op::Index => {
+                let i = self.pop().as_int()?;
+                match self.pop() {
+                    Value::Array(elems) => {
+                        let slot = checked_index(i, elems.len())?;
+                        self.stack.push(elems[slot].clone());
+                    }
+                    other => return Err(format!("Cannot index {:?}", other).into()),
+                }
+            }
+            Op::StoreIndex(slot) => {
+                let value = self.pop();
+                let i = self.pop().as_int()?;
+                match self.variable(chunk, slot)? {
+                    Value::Array(elems) => {
+                        let position = checked_index(i, elems.len())?;
+                        Rc::make_mut(elems)[position] = value.clone();
+                    }
+                    other => return Err(format!("Cannot index {:?}", other).into()),
+                }
+                self.stack.push(value);
+            }
+            Op::Unary(op) => {
+                let value = self.pop();
+                self.stack.push(eval_unary(op, value, self.host.overflow())?);
+            }
+            Op::Binary(op) => {
+                let right = self.pop();
+                let left = self.pop();
+                self.stack.push(eval_binary(op, left, right, self.host.overflow())?);
+            }
+            Op::Cast(ty) => {
+                let value = self.pop();
+                self.stack.push(cast(value, &chunk.types[ty as usize], &chunk.enums)?);
+            }
+            Op::Jump(to) => *ip = to as usize,
+            Op::JumpIfFalse(to) => {
+                if !self.pop().as_bool()? {
+                    *ip = to as usize;
+                }
+            }
+            Op::ForNext(slot, exit) => {
+                let top = self.stack.len();
+                let counter = self.stack[top - 2].as_int()?;
+                let end = self.stack[top - 1].as_int()?;
+                if counter < end {
+                    *self.variable(chunk, slot)? = Value::Int(counter);
+                    self.stack[top - 2] = Value::Int(counter + 1);
+                } else {
+                    self.stack.truncate(top - 2);
+                    *ip = exit as usize;
+                }
+            }
+            Op::Call(function, argc) => {
+                let site = Some(chunk.spans[*ip - 1]);
+                *ip = self.enter(chunk, function, argc as usize, site, *ip)?;
+            }
+            Op::TailCall(function, argc) => {
+                let callee = &chunk.functions[function as usize];
+                check_arity(callee, argc as usize)?;
+                let args = self.pop_n(argc as usize);
+                let frame = self.frames.last_mut().expect("a tail call is made from inside a call");
+                self.stack.truncate(frame.base);
+                self.stack.extend(args);
+                self.stack.resize(frame.base + callee.frame.max(argc as usize), Value::Void);
+                frame.function = function;
+                frame.site = Some(chunk.spans[*ip - 1]);
+                *ip = callee.entry;
+            }
+            Op::Native(name, argc) => {
+                let args = self.pop_n(argc as usize);
+                let value = self.host.call_native(chunk.names[name as usize], &args)?;
+                self.stack.push(value);
+            }
+            Op::Builtin(builtin, argc) => {
+                let args = self.pop_n(argc as usize);
+                let value = match INTERPRETER_BUILTINS[builtin as usize] {
+                    // a run on this engine takes no snapshots
+                    "checkpoint" => Value::Void,
+                    name => self.host.call_builtin(name, &args)?,
+                };
+                self.stack.push(value);
+            }
+            Op::VecPush(slot) => {
+                let value = self.pop();
+                match self.variable(chunk, slot)? {
+                    Value::Array(elems) => Rc::make_mut(elems).push(value),
+                    other => return Err(format!("'push' expects a vector, found {:?}", other).into()),
+                }
+                self.stack.push(Value::Void);
+            }
+            Op::VecPop(slot, var) => {
+                let value = match self.variable(chunk, slot)? {
+                    Value::Array(elems) => Rc::make_mut(elems).pop(),
+                    other => return Err(format!("'pop' expects a vector, found {:?}", other).into()),
+                };
+                let value = value.ok_or_else(|| format!("Cannot pop from empty vector '{}'", chunk.names[var as usize]))?;
+                self.stack.push(value);
+            }
+            Op::MapInsert(slot) => {
+                let value = self.pop();
+                let key = MapKey::try_from(self.pop())?;
+                match self.variable(chunk, slot)? {
+                    Value::Map(entries) => Rc::make_mut(entries).insert(key, value),
+                    other => return Err(format!("'insert' expects a map, found {:?}", other).into()),
+                };
+                self.stack.push(Value::Void);
+            }
+            Op::MapRemove(slot, var) => {
+                let key = MapKey::try_from(self.pop())?;
+                let value = match self.variable(chunk, slot)? {
+                    Value::Map(entries) => Rc::make_mut(entries).remove(&key),
+                    other => return Err(format!("'remove' expects a map, found {:?}", other).into()),
+                };
+                let value = value
+                    .ok_or_else(|| format!("Key {} not found in map '{}'", Value::from(key), chunk.names[var as usize]))?;
+                self.stack.push(value);
+            }
+            Op::Print => println!("{}", self.pop()),
+            Op::Return => {
+                let value = self.pop();
+                let frame = self.frames.pop().expect("a return is made from inside a call");
+                self.stack.truncate(frame.base);
+                self.stack.push(value);
+                *ip = frame.ret;
+            }
+            Op::End => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+fn check_arity(function: &Function, argc: usize) -> Result<(), String> {
+    if function.arity != argc {
+        return Err(format!("Function '{}' expects {} arguments, got {}", function.name, function.arity, argc));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::semantic::from_parse_tree;
+
+    // the runtime error `source` stops with under each engine, unchecked, so
+    // a program the analyzer would turn away still runs
+    fn errors(source: &str) -> (String, String) {
+        let parse_tree = Parser::new(Lexer::new(source.to_string())).analyze().unwrap_or_else(|_| panic!("{} parses", source));
+        let ast = from_parse_tree(&parse_tree).unwrap_or_else(|e| panic!("{}: {}", source, e.message));
+        let interp = Interpreter::new(Host::new()).execute(ast.clone()).expect_err("the interpreter fails");
+        let chunk = compile(ast, &Host::new()).expect("the program compiles");
+        let vm = Vm::new(Host::new()).execute(&chunk).expect_err("the vm fails");
+        (interp.message, vm.message)
+    }
+
+    #[test]
+    fn globals_cannot_be_read_before_they_are_defined() {
+        let (interp, vm) = errors(
+            "let a: i32 = f();
+            let b: i32 = 5;
+            func f() -> i32 [ return b + 1; ]
+            func main() [ print a; ]",
+        );
+        assert_eq!(interp, "Variable 'b' not found");
+        assert_eq!(vm, interp);
+    }
+
+    #[test]
+    fn globals_cannot_be_set_before_they_are_defined() {
+        let (interp, vm) = errors(
+            "let a: i32 = f();
+            let b: i32 = 5;
+            func f() -> i32 [ b = 2; return 1; ]
+            func main() [ print a; ]",
+        );
+        assert_eq!(interp, "Variable 'b' not found");
+        assert_eq!(vm, interp);
+    }
+}
@@ -1,23 +1,69 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::fs;
+use std::io;
+use std::process;
 
-use crate::lexer::Lexer;
-use crate::parser::Parser as LangParser;
+use crate::diagnostics::{render, Diagnostic, DiagnosticSink, Level};
+use crate::lexer::{self, Lexer};
+use crate::parser::{ParseError, Parser as LangParser, MAX_DEPTH};
 
 // parser returns mtree::MTree, NOT semantic::MTree
 use crate::mtree::MTree as ParseTree;
 
 // semantic analysis outputs semantic::MTree
-use crate::semantic::{MTree as SemanticTree, from_parse_tree, fold_constants, SymbolTable, analyze};
-use crate::interpreter::Interpreter;
+use crate::semantic::{from_parse_tree, SymbolTable, analyze, warnings, lint_allows};
+use crate::interpreter::{Host, Interpreter, Overflow, Snapshot, MAX_CALL_DEPTH, STACK_PER_CALL};
+use crate::bytecode::{self, Vm};
+use crate::optimizer::{fold_constants, Pass, PassManager};
+use crate::difftest;
+use crate::heap;
+use crate::explanations;
 
 #[derive(Parser)]
 #[command(name = "lang", version)]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Command,
+    // how deeply blocks, expressions and types may nest when parsing
+    #[arg(long, global = true, default_value_t = MAX_DEPTH)]
+    pub max_depth: usize,
+    // also accept `{ ... }` as block delimiters
+    #[arg(long, global = true)]
+    pub brace_blocks: bool,
 }
 
+// parser settings shared by every command that parses a program
+#[derive(Clone, Copy)]
+struct ParseOptions {
+    max_depth: usize,
+    brace_blocks: bool,
+}
+
+// settings of `execute` beyond those for parsing
+struct RunOptions {
+    engine: Engine,
+    trace: bool,
+    default_init: bool,
+    max_errors: Option<usize>,
+    explain_opt: bool,
+    opt_stats: bool,
+    optimizer: PassManager,
+    max_call_depth: usize,
+    overflow: Overflow,
+    input: Option<String>,
+    seed: Option<u64>,
+    allow_fs: bool,
+    profile: bool,
+    profile_json: Option<String>,
+    heap_stats: bool,
+    snapshot: Option<String>,
+    resume: Option<String>,
+    args: Vec<String>,
+}
+
+// one is parsed per run, so Execute being far larger than the rest costs
+// nothing worth boxing for
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Subcommand)]
 pub enum Command {
     Print {
@@ -27,35 +73,256 @@ pub enum Command {
     },
     Tokenize {
         filepath: String,
+        // print the tokens and their spans as JSON
+        #[arg(long)]
+        json: bool,
+        // include whitespace and comment tokens
+        #[arg(long)]
+        trivia: bool,
     },
     Parse {
         filepath: String,
+        // print each step the parser takes
+        #[arg(long)]
+        trace_parse: bool,
+        // how to print the finished tree
+        #[arg(long, value_enum, default_value_t = TreeFormat::Tree)]
+        format: TreeFormat,
+    },
+    // parse one expression given on the command line, e.g. `expr "1 + 2 * x"`
+    Expr {
+        source: String,
+        #[arg(long, value_enum, default_value_t = TreeFormat::Tree)]
+        format: TreeFormat,
     },
     Execute {
         filepath: String,
+        #[arg(long, value_enum, default_value_t = Engine::Interp)]
+        engine: Engine,
+        // print the parser's steps and the parse tree and AST before running
+        #[arg(long)]
+        trace_parse: bool,
+        // let a `let` without an initializer hold its type's default value
+        // instead of rejecting reads that may come before an assignment
+        #[arg(long)]
+        default_init: bool,
+        // silence the lint with this code, e.g. `-A W0002`; repeatable
+        #[arg(short = 'A', long, value_name = "CODE")]
+        allow: Vec<String>,
+        // report the lint with this code as a warning, even under --Werror
+        #[arg(short = 'W', long, value_name = "CODE")]
+        warn: Vec<String>,
+        // report every lint not given a level above as an error
+        #[arg(long = "Werror")]
+        werror: bool,
+        // print at most this many semantic errors
+        #[arg(long, value_name = "N")]
+        max_errors: Option<usize>,
+        // report what the optimizer changed before running
+        #[arg(long)]
+        explain_opt: bool,
+        // inline functions whose body has at most this many nodes; 0 turns
+        // inlining off
+        #[arg(long, value_name = "N", default_value_t = 20)]
+        inline_threshold: usize,
+        // optimization level: -O0 runs the program as written, -O1 the
+        // passes within a function, -O2 also CSE and inlining
+        #[arg(short = 'O', value_name = "LEVEL", default_value_t = 2, value_parser = clap::value_parser!(u8).range(0..=2))]
+        opt_level: u8,
+        // run exactly these passes, in this order, instead of a level's
+        #[arg(long, value_enum, value_delimiter = ',', value_name = "PASS,...")]
+        passes: Option<Vec<Pass>>,
+        // print what each pass changed and how the tree's size went
+        #[arg(long)]
+        opt_stats: bool,
+        // how many calls may be active at once before the program stops
+        // with a recursion error
+        #[arg(long, value_name = "N", default_value_t = MAX_CALL_DEPTH)]
+        max_call_depth: usize,
+        // what integer arithmetic does with a result too large for an i32
+        #[arg(long, value_enum, default_value_t = Overflow::Checked)]
+        overflow: Overflow,
+        // file for read_int and read_line to read instead of stdin
+        #[arg(long, value_name = "FILE")]
+        input: Option<String>,
+        // seed for rand, so a randomized run can be repeated; without it
+        // each run differs
+        #[arg(long, value_name = "N")]
+        seed: Option<u64>,
+        // let read_file and write_file use the file system; without it
+        // they stop the program, so untrusted programs can be run safely
+        #[arg(long)]
+        allow_fs: bool,
+        // after the run, print how many nodes were evaluated and, for each
+        // function, its calls, nodes and time, slowest first
+        #[arg(long)]
+        profile: bool,
+        // write the same profile as JSON to this file
+        #[arg(long, value_name = "FILE")]
+        profile_json: Option<String>,
+        // after the run, print how many array and map blocks were made,
+        // copied on write, live at once at most and still live at the end
+        #[arg(long)]
+        heap_stats: bool,
+        // file checkpoint() saves the program's state to, as JSON; without
+        // it checkpoint() does nothing
+        #[arg(long, value_name = "FILE")]
+        snapshot: Option<String>,
+        // saved calls are not re-entered, so say what resuming does where
+        // it is offered
+        #[arg(long, value_name = "FILE", help = "Restore the globals and random state saved in FILE, then restart main")]
+        resume: Option<String>,
+        // arguments after `--`, for the program to read with arg_count,
+        // arg_int and arg_str
+        #[arg(last = true, value_name = "ARGS")]
+        args: Vec<String>,
+    },
+    // write a Graphviz graph of the program's parse tree, or its semantic
+    // tree with --semantic
+    Dot {
+        filepath: String,
+        #[arg(long)]
+        semantic: bool,
+        // file to write; stdout if omitted
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    Difftest {
+        dir: String,
+        #[arg(long, value_enum, value_delimiter = ',', default_value = "interp,vm")]
+        engines: Vec<Engine>,
+        // seed for rand, the same for every engine so randomized programs
+        // can agree
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        seed: u64,
+    },
+    // print the bytecode `execute --engine vm` runs for a program
+    Disasm {
+        filepath: String,
+        // optimization level, as for execute
+        #[arg(short = 'O', value_name = "LEVEL", default_value_t = 2, value_parser = clap::value_parser!(u8).range(0..=2))]
+        opt_level: u8,
+        // as for execute: uninitialized variables hold their type's default
+        #[arg(long)]
+        default_init: bool,
+    },
+    // describe a diagnostic code, e.g. `explain E0401`
+    Explain {
+        code: String,
+    },
+}
+
+// execution backends selectable with --engine
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Engine {
+    // walk the semantic tree
+    Interp,
+    // compile to bytecode and run it on a stack machine
+    Vm,
+}
+
+// output formats for `parse`: the indented dump, or machine-readable forms
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum TreeFormat {
+    Tree,
+    Json,
+    Sexpr,
+}
+
+impl Engine {
+    pub fn name(&self) -> String {
+        self.to_possible_value().unwrap().get_name().to_string()
     }
 }
 
+// the stack to run the command on: room for the parser, and for as many
+// calls as the interpreter is allowed to nest
+pub fn stack_size(cli: &Cli) -> usize {
+    let calls = match cli.command {
+        Command::Execute { max_call_depth, .. } => max_call_depth,
+        _ => MAX_CALL_DEPTH,
+    };
+    calls.saturating_mul(STACK_PER_CALL).saturating_add(16 * 1024 * 1024)
+}
+
 pub fn handle(cli: Cli)  {
+    let options = ParseOptions { max_depth: cli.max_depth, brace_blocks: cli.brace_blocks };
     match cli.command {
         Command::Print { filepath, numbered } => {
             print_file(filepath, numbered);
         }
 
-        Command::Tokenize { filepath } => {
-            tokenize(filepath);
+        Command::Tokenize { filepath, json, trivia } => {
+            tokenize(filepath, json, trivia);
+        }
+
+        Command::Parse { filepath, trace_parse, format } => {
+            parse(filepath, trace_parse, format, options);
+        }
+
+        Command::Expr { source, format } => {
+            parse_expr(source, format, options);
+        }
+
+        Command::Execute { filepath, engine, trace_parse, default_init, allow, warn, werror, max_errors, explain_opt, inline_threshold, opt_level, passes, opt_stats, max_call_depth, overflow, input, seed, allow_fs, profile, profile_json, heap_stats, snapshot, resume, args } => {
+            let mut sink = DiagnosticSink::new();
+            if werror {
+                sink = sink.with_warnings_as_errors();
+            }
+            // an allow wins over a warn for the same code
+            for code in &warn {
+                sink = sink.with_level(code, Level::Warn);
+            }
+            for code in &allow {
+                sink = sink.with_level(code, Level::Allow);
+            }
+            let optimizer = match passes {
+                Some(passes) => PassManager::new(passes),
+                None => PassManager::at_level(opt_level),
+            };
+            let optimizer = optimizer.with_inline_threshold(inline_threshold);
+            let run = RunOptions { engine, trace: trace_parse, default_init, max_errors, explain_opt, opt_stats, optimizer, max_call_depth, overflow, input, seed, allow_fs, profile, profile_json, heap_stats, snapshot, resume, args };
+            execute(filepath, run, sink, options);
+        }
+
+        Command::Dot { filepath, semantic, output } => {
+            dot(filepath, semantic, output, options);
+        }
+
+        Command::Difftest { dir, engines, seed } => {
+            // every engine parses the programs as this run was told to
+            let mut flags = vec!["--seed".to_string(), seed.to_string(), "--max-depth".to_string(), options.max_depth.to_string()];
+            if options.brace_blocks {
+                flags.push("--brace-blocks".to_string());
+            }
+            if !difftest::run(&dir, &engines, &flags) {
+                process::exit(1);
+            }
         }
 
-        Command::Parse { filepath } => {
-            parse(filepath);
+        Command::Disasm { filepath, opt_level, default_init } => {
+            disasm(filepath, opt_level, default_init, options);
         }
 
-        Command::Execute { filepath } => {
-            execute(filepath);
+        Command::Explain { code } => {
+            explain(code);
         }
     }
 }
 
+fn explain(code: String) {
+    let Some(explanation) = explanations::explain(&code) else {
+        eprintln!("✗ No diagnostic has the code '{}'", code);
+        process::exit(1);
+    };
+    println!("{}: {}\n", explanation.code, explanation.title);
+    println!("{}\n", explanation.description);
+    println!("For example:\n");
+    for line in explanation.example.lines() {
+        println!("    {}", line);
+    }
+}
+
 fn print_file(path: String, numbered: bool) {
     let contents = fs::read_to_string(path).unwrap();
     if numbered {
@@ -68,79 +335,332 @@ fn print_file(path: String, numbered: bool) {
     }
 }
 
-fn tokenize(path: String) {
-    let contents = fs::read_to_string(path).unwrap();
-    let mut lexer = Lexer::new(contents);
+// `-` reads the program from stdin; tokens are lexed as the input streams in
+fn tokenize(path: String, json: bool, trivia: bool) {
+    let mut lexer = if path == "-" {
+        Lexer::from_reader(io::stdin())
+    } else {
+        Lexer::from_reader(fs::File::open(path).unwrap())
+    };
+    if trivia {
+        lexer = lexer.with_trivia();
+    }
+    if json {
+        println!("{}", lexer::tokens_to_json(&lexer::spanned_tokens(&mut lexer)));
+        return;
+    }
     lexer.print_tokens();
 }
 
-fn parse(path: String) {
-    let contents = fs::read_to_string(path).unwrap();
+// a program's text and the name diagnostics refer to it by
+struct Source {
+    path: String,
+    text: String,
+}
 
-    // correct: parser produces mtree::MTree
-    let lexer = Lexer::new(contents);
-    let mut parser = LangParser::new(lexer);
+impl Source {
+    fn read(path: String) -> Self {
+        let text = fs::read_to_string(&path).unwrap();
+        Source { path, text }
+    }
 
-    let parse_tree: ParseTree = parser.analyze();
+    // a diagnostic shown with the line of this source it points at
+    fn render(&self, diagnostic: &Diagnostic) -> String {
+        render::render(diagnostic, &self.path, &self.text)
+    }
+}
 
-    println!("\n=== Parse Tree ===");
-    parse_tree.print();
+// print every lexical error in the source, returning whether there were any
+fn report_lex_errors(source: &Source) -> bool {
+    let errors = lexer::lex_errors(&source.text);
+    for error in &errors {
+        eprintln!("{}", source.render(error));
+    }
+    !errors.is_empty()
 }
 
-fn execute(path: String) {
-    let contents = fs::read_to_string(path).unwrap();
+fn exit_parse_errors(source: &Source, errors: Vec<ParseError>) -> ! {
+    for error in errors {
+        eprintln!("{}", source.render(&Diagnostic::from(error)));
+    }
+    process::exit(1);
+}
+
+// the parse tree could not be turned into a semantic tree
+fn exit_malformed(source: &Source, diagnostic: Diagnostic) -> ! {
+    eprintln!("{}", source.render(&diagnostic));
+    process::exit(1);
+}
+
+// a parser over `contents`, tracing its steps if asked to
+fn parser_for(contents: String, trace: bool, options: ParseOptions) -> LangParser {
+    let mut parser = LangParser::new(Lexer::new(contents).with_layout()).with_max_depth(options.max_depth);
+    if options.brace_blocks {
+        parser = parser.with_brace_blocks();
+    }
+    if trace { parser.with_trace() } else { parser }
+}
+
+fn parse(path: String, trace: bool, format: TreeFormat, options: ParseOptions) {
+    let source = Source::read(path);
+
+    if report_lex_errors(&source) {
+        process::exit(1);
+    }
 
     // correct: parser produces mtree::MTree
-    let lexer = Lexer::new(contents);
-    let mut parser = LangParser::new(lexer);
+    let mut parser = parser_for(source.text.clone(), trace, options);
 
-    let parse_tree: ParseTree = parser.analyze();
+    let parse_tree: ParseTree = parser.analyze().unwrap_or_else(|errors| exit_parse_errors(&source, errors));
 
-    println!("\n=== Parse Tree ===");
-    parse_tree.print();
+    print_tree(&parse_tree, format);
+}
 
-    // Convert parse tree to semantic tree
-    match from_parse_tree(&parse_tree) {
-        Ok(mut ast) => {
-            println!("\n=== Semantic AST ===\n{:#?}", ast);
-
-            fold_constants(&mut ast);
-
-            // symbol table
-            let mut sym_table = SymbolTable::new();
-
-            // run semantic analysis and report how many errors we found
-            match analyze(&ast, &mut sym_table) {
-                Ok(_) => {
-                    println!("\n✓ Semantic analysis completed with 0 error(s).");
-                    
-                    // If semantic analysis passed, execute the program
-                    println!("\n=== Program Execution ===");
-                    let mut interp = Interpreter::new();
-                    match interp.execute(ast) {
-                        Ok(_) => println!("\n✓ Execution completed successfully"),
-                        Err(e) => eprintln!("\n✗ Runtime error: {}", e),
-                    }
-                }
-                Err(errors) => {
-                    println!("\n✓ Semantic analysis completed with {} error(s):", errors.len());
-                    for (i, error) in errors.iter().enumerate() {
-                        println!("  {}. {}", i + 1, error);
-                    }
-                    println!("\n✗ Skipping execution due to semantic errors");
-                }
-            }
-        }
-        Err(e) => {
-            panic!("Semantic conversion failed: {}", e);
+fn parse_expr(text: String, format: TreeFormat, options: ParseOptions) {
+    let source = Source { path: "<expr>".to_string(), text };
+    if report_lex_errors(&source) {
+        process::exit(1);
+    }
+
+    let tree = parser_for(source.text.clone(), false, options)
+        .parse_single_expr()
+        .unwrap_or_else(|error| exit_parse_errors(&source, vec![error]));
+    print_tree(&tree, format);
+}
+
+fn print_tree(tree: &ParseTree, format: TreeFormat) {
+    match format {
+        TreeFormat::Tree => {
+            println!("\n=== Parse Tree ===");
+            tree.print();
         }
+        TreeFormat::Json => println!("{}", tree.to_json()),
+        TreeFormat::Sexpr => println!("{}", tree.to_sexpr()),
     }
+}
 
-    
+fn dot(path: String, semantic: bool, output: Option<String>, options: ParseOptions) {
+    let source = Source::read(path);
 
+    if report_lex_errors(&source) {
+        process::exit(1);
+    }
 
+    let parse_tree = parser_for(source.text.clone(), false, options)
+        .analyze()
+        .unwrap_or_else(|errors| exit_parse_errors(&source, errors));
+    let graph = if semantic {
+        from_parse_tree(&parse_tree).unwrap_or_else(|e| exit_malformed(&source, e)).to_dot()
+    } else {
+        parse_tree.to_dot()
+    };
+
+    match output {
+        Some(file) => fs::write(&file, graph).unwrap_or_else(|e| {
+            eprintln!("✗ Cannot write '{}': {}", file, e);
+            process::exit(1);
+        }),
+        None => print!("{}", graph),
+    }
 }
 
+fn disasm(path: String, opt_level: u8, default_init: bool, options: ParseOptions) {
+    let source = Source::read(path);
+
+    if report_lex_errors(&source) {
+        process::exit(1);
+    }
+
+    let parse_tree = parser_for(source.text.clone(), false, options)
+        .analyze()
+        .unwrap_or_else(|errors| exit_parse_errors(&source, errors));
+    let mut ast = from_parse_tree(&parse_tree).unwrap_or_else(|e| exit_malformed(&source, e));
+
+    // only a checked program can be compiled
+    let host = Host::new();
+    let mut sym_table = SymbolTable::new();
+    sym_table.declare_natives(host.natives());
+    if let Err(errors) = analyze(&ast, &mut sym_table, default_init) {
+        for error in &errors {
+            eprintln!("{}", source.render(error));
+        }
+        eprintln!("✗ Cannot disassemble a program with semantic errors");
+        process::exit(1);
+    }
+
+    PassManager::at_level(opt_level).run(&mut ast);
+    let chunk = bytecode::compile(ast, &host).unwrap_or_else(|e| {
+        eprintln!("✗ Cannot compile '{}': {}", source.path, e);
+        process::exit(1);
+    });
+    print!("{}", chunk.disassemble());
+}
+
+fn execute(path: String, run: RunOptions, mut sink: DiagnosticSink, options: ParseOptions) {
+    let source = Source::read(path);
+
+    if report_lex_errors(&source) {
+        process::exit(1);
+    }
+
+    // correct: parser produces mtree::MTree
+    let mut parser = parser_for(source.text.clone(), run.trace, options);
+
+    let parse_tree: ParseTree = parser.analyze().unwrap_or_else(|errors| exit_parse_errors(&source, errors));
+
+    if run.trace {
+        println!("\n=== Parse Tree ===");
+        parse_tree.print();
+    }
+
+    // lints silenced in the source apply however the command line set them
+    let (allows, annotation_warnings) = lint_allows(&parse_tree);
+    sink.allow(allows);
+    sink.extend(annotation_warnings);
+
+    // Convert parse tree to semantic tree
+    let mut ast = from_parse_tree(&parse_tree).unwrap_or_else(|e| exit_malformed(&source, e));
+    if run.trace {
+        println!("\n=== Semantic AST ===");
+        ast.print();
+    }
+
+    // profiles and snapshots are taken by the tree-walker alone
+    if run.engine != Engine::Interp
+        && (run.profile || run.profile_json.is_some() || run.snapshot.is_some() || run.resume.is_some())
+    {
+        eprintln!("✗ --profile, --profile-json, --snapshot and --resume need --engine interp");
+        process::exit(1);
+    }
+
+    let mut host = Host::new().with_max_depth(run.max_call_depth).with_overflow(run.overflow).with_args(run.args);
+    if let Some(file) = &run.input {
+        let input = fs::File::open(file).unwrap_or_else(|e| {
+            eprintln!("✗ Cannot read '{}': {}", file, e);
+            process::exit(1);
+        });
+        host = host.with_input(io::BufReader::new(input));
+    }
+    if let Some(seed) = run.seed {
+        host = host.with_seed(seed);
+    }
+    if run.allow_fs {
+        host = host.with_file_access();
+    }
+    let resume = run.resume.map(|path| {
+        let snapshot = fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|text| Snapshot::from_json(&text));
+        snapshot.unwrap_or_else(|e| {
+            eprintln!("✗ Cannot resume from '{}': {}", path, e);
+            process::exit(1);
+        })
+    });
 
+    // symbol table, which knows the functions the host provides
+    let mut sym_table = SymbolTable::new();
+    sym_table.declare_natives(host.natives());
 
+    // lints look at conditions as folded, whatever the optimizer runs later
+    let mut folded = ast.clone();
+    fold_constants(&mut folded);
+    sink.extend(warnings(&folded));
+    for warning in sink.warnings() {
+        println!("{}", source.render(warning));
+    }
 
+    // run semantic analysis and report how many errors we found
+    if let Err(errors) = analyze(&ast, &mut sym_table, run.default_init) {
+        sink.extend(errors);
+    }
+    if sink.has_errors() {
+        println!("\n✓ Semantic analysis completed with {} error(s):\n", sink.error_count());
+        let shown = run.max_errors.unwrap_or(usize::MAX);
+        for error in sink.errors().take(shown) {
+            println!("{}", source.render(error));
+        }
+        if sink.error_count() > shown {
+            println!("... {} more error(s) not shown; raise --max-errors to see them\n", sink.error_count() - shown);
+        }
+        println!("For more information about an error, try `lang explain <code>`.");
+        println!("✗ Skipping execution due to semantic errors");
+        process::exit(1);
+    }
+    println!("\n✓ Semantic analysis completed with 0 error(s).");
+
+    // a snapshot taken before the program changed may not fit it any more
+    if let Some(snapshot) = &resume {
+        let mismatches = snapshot.mismatches(&ast, &sym_table);
+        if !mismatches.is_empty() {
+            for error in &mismatches {
+                eprintln!("{}", source.render(error));
+            }
+            eprintln!("✗ Cannot resume from a snapshot that does not fit the program");
+            process::exit(1);
+        }
+    }
+
+    let stats = run.optimizer.run(&mut ast);
+    if run.explain_opt {
+        println!("\n=== Optimizations ===");
+        for rewrite in stats.iter().flat_map(|s| &s.rewrites) {
+            println!("{}: {}", rewrite.span, rewrite.message);
+        }
+    }
+    if run.opt_stats {
+        println!("\n=== Optimization Statistics ===");
+        for s in &stats {
+            println!("{:<10} {:>4} change(s)  {} -> {} nodes", s.pass.name(), s.rewrites.len(), s.nodes_before, s.nodes_after);
+        }
+    }
+
+    // If semantic analysis passed, execute the program
+    let result = match run.engine {
+        Engine::Interp => {
+            let mut interpreter = Interpreter::new(host);
+            if run.profile || run.profile_json.is_some() {
+                interpreter = interpreter.with_profile();
+            }
+            if let Some(path) = run.snapshot {
+                interpreter = interpreter.with_checkpoints(path);
+            }
+            if let Some(snapshot) = resume {
+                interpreter = interpreter.with_snapshot(snapshot);
+            }
+            println!("\n=== Program Execution ===");
+            let result = interpreter.execute(ast);
+            if let Some(profile) = interpreter.profile() {
+                if run.profile {
+                    print!("\n=== Profile ===\n{}", profile);
+                }
+                if let Some(path) = &run.profile_json
+                    && let Err(e) = fs::write(path, profile.to_json())
+                {
+                    eprintln!("✗ Cannot write '{}': {}", path, e);
+                }
+            }
+            result
+        }
+        Engine::Vm => {
+            let chunk = bytecode::compile(ast, &host).unwrap_or_else(|e| {
+                eprintln!("✗ Cannot compile '{}': {}", source.path, e);
+                process::exit(1);
+            });
+            println!("\n=== Program Execution ===");
+            Vm::new(host).execute(&chunk)
+        }
+    };
+    if run.heap_stats {
+        print!("\n=== Heap Statistics ===\n{}", heap::stats());
+    }
+    match result {
+        Ok(0) => println!("\n✓ Execution completed successfully"),
+        // the program called exit with a failing status, which lang passes on
+        Ok(status) => {
+            println!("\n✓ Program exited with status {}", status);
+            process::exit(status);
+        }
+        Err(e) => {
+            eprintln!("\n{}", source.render(&e));
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::token::Span;
+
+pub mod render;
+
+// stable codes naming each kind of diagnostic, so they can be looked up and
+// allowed by name; errors are E, warnings W
+pub const LEXICAL: &str = "E0100";
+pub const SYNTAX: &str = "E0200";
+pub const TOO_DEEP: &str = "E0201";
+pub const MALFORMED: &str = "E0300";
+pub const TYPE_MISMATCH: &str = "E0400";
+pub const UNASSIGNED: &str = "E0401";
+pub const MISSING_RETURN: &str = "E0402";
+pub const REDECLARED: &str = "E0403";
+pub const UNDECLARED: &str = "E0404";
+pub const ARGUMENT_COUNT: &str = "E0405";
+pub const NOT_CALLABLE: &str = "E0406";
+pub const RETURN_TYPE: &str = "E0407";
+pub const BAD_MAIN: &str = "E0408";
+pub const LOOP_CONTROL: &str = "E0409";
+pub const CONSTANT_ASSIGNED: &str = "E0410";
+pub const RUNTIME: &str = "E0500";
+pub const ENDLESS_LOOP: &str = "W0001";
+pub const UNREAD_PARAMETER: &str = "W0002";
+pub const UNCALLED_FUNCTION: &str = "W0003";
+pub const UNUSED_VARIABLE: &str = "W0004";
+pub const BAD_ANNOTATION: &str = "W0005";
+pub const UNREACHABLE_BRANCH: &str = "W0006";
+
+// the names lints go by in `// lang: allow(...)` comments
+const LINT_NAMES: [(&str, &str); 5] = [
+    ("endless_loop", ENDLESS_LOOP),
+    ("unread_parameter", UNREAD_PARAMETER),
+    ("uncalled_function", UNCALLED_FUNCTION),
+    ("unused_variable", UNUSED_VARIABLE),
+    ("unreachable_branch", UNREACHABLE_BRANCH),
+];
+
+// the code of the lint called `name`, which may also be given as its code
+pub fn lint_code(name: &str) -> Option<&'static str> {
+    LINT_NAMES
+        .iter()
+        .find(|(lint, code)| *lint == name || code.eq_ignore_ascii_case(name))
+        .map(|(_, code)| *code)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found by any stage, from lexing to running the program.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Option<Span>,
+    // further detail shown under the message, such as an earlier declaration
+    pub notes: Vec<String>,
+    // other places the same problem was found, reported along with this one
+    pub also_at: Vec<Span>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic { severity: Severity::Error, code, message: message.into(), span: None, notes: Vec::new(), also_at: Vec::new() }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic { severity: Severity::Warning, code, ..Diagnostic::error(code, message) }
+    }
+
+    pub fn at(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // the notes, followed by one listing the other places it was found
+    pub fn all_notes(&self) -> Vec<String> {
+        let mut notes = self.notes.clone();
+        if !self.also_at.is_empty() {
+            let places: Vec<String> = self.also_at.iter().map(Span::to_string).collect();
+            let uses = if places.len() == 1 { "use" } else { "uses" };
+            notes.push(format!("...and {} more {} at {}", places.len(), uses, places.join(", ")));
+        }
+        notes
+    }
+
+    // the same problem, possibly found somewhere else
+    fn repeats(&self, other: &Diagnostic) -> bool {
+        self.severity == other.severity && self.code == other.code && self.message == other.message
+    }
+
+    // where the diagnostic sorts in the output: by position, with those that
+    // have none after the rest
+    fn position(&self) -> (bool, Option<Span>) {
+        (self.span.is_none(), self.span)
+    }
+}
+
+// most analysis errors are about operand and value types, so a bare
+// message is one of those
+impl From<String> for Diagnostic {
+    fn from(message: String) -> Self {
+        Diagnostic::error(TYPE_MISMATCH, message)
+    }
+}
+
+// `error[E0400] at 3:5: message`, with each note on a line of its own
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}[{}]", self.severity, self.code)?;
+        if let Some(span) = self.span {
+            write!(f, " at {}", span)?;
+        }
+        write!(f, ": {}", self.message)?;
+        for note in self.all_notes() {
+            write!(f, "\n    note: {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// What to do with a lint: drop it, report it as a warning, or report it
+/// as an error that stops the program from running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// A lint silenced for the source from `from` to `to`, the extent of the
+/// statement or declaration annotated with `// lang: allow(...)`.
+#[derive(Clone, Debug)]
+pub struct Allow {
+    pub code: &'static str,
+    pub from: Span,
+    pub to: Span,
+}
+
+impl Allow {
+    fn covers(&self, diagnostic: &Diagnostic) -> bool {
+        diagnostic.code == self.code && diagnostic.span.is_some_and(|span| self.from <= span && span <= self.to)
+    }
+}
+
+/// Collects the diagnostics of a run, applying the level chosen for each
+/// lint, and counts them by severity. Diagnostics are kept in source order,
+/// and one found again elsewhere is merged into the earliest report of it.
+#[derive(Default)]
+pub struct DiagnosticSink {
+    diagnostics: Vec<Diagnostic>,
+    // per-code levels, ahead of the default for every warning
+    levels: HashMap<String, Level>,
+    warnings_are_errors: bool,
+    // allows written in the source, which win over every level
+    allows: Vec<Allow>,
+}
+
+impl DiagnosticSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // set the level of the lint `code`; errors cannot be allowed or demoted
+    pub fn with_level(mut self, code: &str, level: Level) -> Self {
+        self.levels.insert(code.to_string(), level);
+        self
+    }
+
+    // deny every lint that has no level of its own
+    pub fn with_warnings_as_errors(mut self) -> Self {
+        self.warnings_are_errors = true;
+        self
+    }
+
+    pub fn allow(&mut self, allows: impl IntoIterator<Item = Allow>) {
+        self.allows.extend(allows);
+    }
+
+    pub fn push(&mut self, mut diagnostic: Diagnostic) {
+        if !diagnostic.is_error() {
+            if self.allows.iter().any(|allow| allow.covers(&diagnostic)) {
+                return;
+            }
+            let default = if self.warnings_are_errors { Level::Deny } else { Level::Warn };
+            match self.levels.get(diagnostic.code).copied().unwrap_or(default) {
+                Level::Allow => return,
+                Level::Warn => {}
+                Level::Deny => diagnostic.severity = Severity::Error,
+            }
+        }
+        match self.diagnostics.iter_mut().find(|d| d.repeats(&diagnostic)) {
+            Some(existing) => {
+                // the earliest place becomes the one reported
+                match (existing.span, diagnostic.span) {
+                    (_, None) => {}
+                    (None, span) => existing.span = span,
+                    (Some(first), Some(span)) if first < span => existing.also_at.push(span),
+                    (Some(first), Some(span)) if span < first => {
+                        existing.also_at.push(first);
+                        existing.span = Some(span);
+                    }
+                    _ => {}
+                }
+                existing.also_at.sort();
+                existing.also_at.dedup();
+            }
+            None => self.diagnostics.push(diagnostic),
+        }
+        self.diagnostics.sort_by_key(Diagnostic::position);
+    }
+
+    pub fn extend(&mut self, diagnostics: impl IntoIterator<Item = Diagnostic>) {
+        for diagnostic in diagnostics {
+            self.push(diagnostic);
+        }
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.is_error())
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| !d.is_error())
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.errors().count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+}
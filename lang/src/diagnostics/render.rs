@@ -0,0 +1,58 @@
+use crate::diagnostics::Diagnostic;
+
+// how many characters of `rest` the token starting there spans: a whole
+// identifier, number or quoted literal, otherwise the single character
+fn token_width(rest: &[char]) -> usize {
+    match rest.first() {
+        None => 1,
+        Some(&quote) if quote == '"' || quote == '\'' => {
+            let mut escaped = false;
+            for (i, &c) in rest.iter().enumerate().skip(1) {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    c if c == quote => return i + 1,
+                    _ => {}
+                }
+            }
+            rest.len()
+        }
+        Some(c) if c.is_ascii_digit() => rest.iter().take_while(|c| c.is_alphanumeric() || **c == '.').count(),
+        Some(c) if c.is_alphanumeric() || *c == '_' => rest.iter().take_while(|c| c.is_alphanumeric() || **c == '_').count(),
+        Some(_) => 1,
+    }
+}
+
+/// Render a diagnostic rustc-style: the message, the file position, the
+/// source line with the offending token underlined, and any notes.
+///
+/// ```text
+/// error[E0400]: Variable 'y' not declared
+///  --> prog.txt:3:11
+///   |
+/// 3 |     print(y);
+///   |           ^
+/// ```
+pub fn render(diagnostic: &Diagnostic, path: &str, source: &str) -> String {
+    let mut out = format!("{}[{}]: {}\n", diagnostic.severity, diagnostic.code, diagnostic.message);
+    // the gutter is as wide as the line number it shows
+    let mut gutter = String::new();
+    if let Some(span) = diagnostic.span {
+        gutter = " ".repeat(span.line.to_string().len());
+        out.push_str(&format!("{}--> {}:{}:{}\n", gutter, path, span.line, span.col));
+        if let Some(line) = source.lines().nth(span.line.saturating_sub(1)) {
+            let chars: Vec<char> = line.chars().collect();
+            let start = (span.col.saturating_sub(1)).min(chars.len());
+            // keep tabs so the caret lines up under tab-indented code
+            let indent: String = chars[..start].iter().map(|&c| if c == '\t' { '\t' } else { ' ' }).collect();
+            let carets = "^".repeat(token_width(&chars[start..]));
+            out.push_str(&format!("{} |\n", gutter));
+            out.push_str(&format!("{} | {}\n", span.line, line));
+            out.push_str(&format!("{} | {}{}\n", gutter, indent, carets));
+        }
+    }
+    for note in diagnostic.all_notes() {
+        out.push_str(&format!("{} = note: {}\n", gutter, note));
+    }
+    out
+}
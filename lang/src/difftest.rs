@@ -0,0 +1,154 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+use crate::cli::Engine;
+
+/// Everything observable about one run of a program.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    stdout: String,
+    stderr: String,
+    exit_code: Option<i32>,
+}
+
+// run a program under one engine in a child process so panics and
+// exit codes are captured the same way for every backend; `flags` go to
+// every engine alike
+fn run_engine(path: &str, engine: Engine, flags: &[String]) -> Result<Outcome, String> {
+    let exe = env::current_exe().map_err(|e| format!("Cannot locate lang executable: {}", e))?;
+    let output = Command::new(exe)
+        .args(["execute", path, "--engine", &engine.name()])
+        .args(flags)
+        .output()
+        .map_err(|e| format!("Failed to run '{}' with {}: {}", path, engine.name(), e))?;
+
+    Ok(Outcome {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        exit_code: output.status.code(),
+    })
+}
+
+// first line where two outputs disagree, for the report
+fn first_difference(a: &str, b: &str) -> String {
+    let mut a_lines = a.lines();
+    let mut b_lines = b.lines();
+    let mut line = 1;
+    loop {
+        match (a_lines.next(), b_lines.next()) {
+            (Some(x), Some(y)) if x == y => line += 1,
+            (None, None) => return "outputs differ only in trailing whitespace".to_string(),
+            (x, y) => {
+                return format!(
+                    "line {}: {:?} vs {:?}",
+                    line,
+                    x.unwrap_or("<end of output>"),
+                    y.unwrap_or("<end of output>")
+                );
+            }
+        }
+    }
+}
+
+fn compare(reference: &Outcome, other: &Outcome) -> Vec<String> {
+    let mut divergences = Vec::new();
+    if reference.exit_code != other.exit_code {
+        divergences.push(format!(
+            "exit code: {:?} vs {:?}",
+            reference.exit_code, other.exit_code
+        ));
+    }
+    if reference.stdout != other.stdout {
+        divergences.push(format!(
+            "stdout: {}",
+            first_difference(&reference.stdout, &other.stdout)
+        ));
+    }
+    if reference.stderr != other.stderr {
+        divergences.push(format!(
+            "diagnostics: {}",
+            first_difference(&reference.stderr, &other.stderr)
+        ));
+    }
+    divergences
+}
+
+/// Run every program in `dir` under each engine and report divergences.
+/// The first engine listed is the reference the others are compared to.
+/// Every run gets `flags` as well, such as the seed for `rand`, so nothing
+/// but the engine differs. Returns true when all engines agree on every
+/// program.
+pub fn run(dir: &str, engines: &[Engine], flags: &[String]) -> bool {
+    let mut programs: Vec<String> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect(),
+        Err(e) => {
+            eprintln!("✗ Cannot read directory '{}': {}", dir, e);
+            return false;
+        }
+    };
+    programs.sort();
+
+    let names: Vec<String> = engines.iter().map(|e| e.name()).collect();
+    println!("\n=== Differential Test ({}) ===", names.join(", "));
+
+    let mut diverged = 0;
+    for program in &programs {
+        let mut outcomes = Vec::new();
+        let mut failed = false;
+        for engine in engines {
+            match run_engine(program, *engine, flags) {
+                Ok(outcome) => outcomes.push((*engine, outcome)),
+                Err(e) => {
+                    println!("  {} ... ERROR\n    {}", program, e);
+                    failed = true;
+                    break;
+                }
+            }
+        }
+        if failed {
+            diverged += 1;
+            continue;
+        }
+
+        let (reference_engine, reference) = &outcomes[0];
+        let mut report = Vec::new();
+        for (engine, outcome) in &outcomes[1..] {
+            for divergence in compare(reference, outcome) {
+                report.push(format!(
+                    "{} vs {}: {}",
+                    reference_engine.name(),
+                    engine.name(),
+                    divergence
+                ));
+            }
+        }
+
+        if report.is_empty() {
+            println!("  {} ... ok", program);
+        } else {
+            diverged += 1;
+            println!("  {} ... DIVERGED", program);
+            for line in report {
+                println!("    {}", line);
+            }
+        }
+    }
+
+    if diverged == 0 {
+        println!(
+            "\n✓ {} program(s) agree across {} engine(s)",
+            programs.len(),
+            engines.len()
+        );
+        true
+    } else {
+        println!("\n✗ {} of {} program(s) diverged", diverged, programs.len());
+        false
+    }
+}
@@ -0,0 +1,29 @@
+// Graphviz output shared by the parse tree and the semantic tree: each tree
+// supplies a label and the children of a node, and nodes are numbered in
+// pre-order.
+
+use crate::stack;
+
+pub fn render<'a, T>(name: &str, root: &'a T, parts: &dyn Fn(&'a T) -> (String, Vec<&'a T>)) -> String {
+    let mut dot = format!("digraph {} {{\n    node [shape=box, fontname=\"monospace\"];\n", name);
+    let mut next_id = 0;
+    render_node(root, parts, &mut next_id, &mut dot);
+    dot.push_str("}\n");
+    dot
+}
+
+fn render_node<'a, T>(node: &'a T, parts: &dyn Fn(&'a T) -> (String, Vec<&'a T>), next_id: &mut usize, dot: &mut String) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    let (label, children) = parts(node);
+    dot.push_str(&format!("    n{} [label=\"{}\"];\n", id, escape(&label)));
+    for child in children {
+        let child_id = stack::guarded(|| render_node(child, parts, next_id, dot));
+        dot.push_str(&format!("    n{} -> n{};\n", id, child_id));
+    }
+    id
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,190 @@
+// long-form help for each diagnostic code, printed by `lang explain <code>`
+
+use crate::diagnostics;
+
+pub struct Explanation {
+    pub code: &'static str,
+    pub title: &'static str,
+    pub description: &'static str,
+    // a small program that triggers the diagnostic
+    pub example: &'static str,
+}
+
+pub const EXPLANATIONS: &[Explanation] = &[
+    Explanation {
+        code: diagnostics::LEXICAL,
+        title: "lexical error",
+        description: "The source contains text that is not part of any token: a character the \
+language does not use, an unterminated string or block comment, a malformed char literal or \
+escape sequence, or a number literal too large for i32 or f32.",
+        example: "func main() [\n    let a = 1 @ 2;\n]",
+    },
+    Explanation {
+        code: diagnostics::SYNTAX,
+        title: "syntax error",
+        description: "The tokens do not form a valid program. The message names the token that \
+was found and every token or construct that would have been accepted in its place. The parser \
+skips to the next statement and keeps going, so later errors may be knock-on effects of the \
+first.",
+        example: "func main() [\n    let x = ;\n]",
+    },
+    Explanation {
+        code: diagnostics::TOO_DEEP,
+        title: "program too deeply nested",
+        description: "Blocks, expressions or types are nested more deeply than the parser allows \
+(256 levels unless changed with --max-depth). The limit keeps the recursive parser from \
+overflowing its stack.",
+        example: "func main() [\n    print(((((((((((1)))))))))));  // with --max-depth 8\n]",
+    },
+    Explanation {
+        code: diagnostics::MALFORMED,
+        title: "malformed parse tree",
+        description: "The program parsed, but its tree could not be turned into the form the \
+analyzer works on, for instance an assignment whose left side is not a variable or an indexed \
+variable.",
+        example: "func main() [\n    1 = 2;\n]",
+    },
+    Explanation {
+        code: diagnostics::TYPE_MISMATCH,
+        title: "mismatched types",
+        description: "A value has a type its context does not accept: an operand of the wrong \
+type, a condition that is not a bool, an argument or initializer of the wrong type, an index \
+that is not an int, or a cast between unrelated types.",
+        example: "func main() [\n    let x: i32 = true;\n]",
+    },
+    Explanation {
+        code: diagnostics::UNASSIGNED,
+        title: "variable may be read before it is assigned",
+        description: "A variable declared without an initializer is read on a path where nothing \
+has been stored in it yet. Assign it on every path first, give it an initializer, or pass \
+--default-init to read its type's default value instead. Program-scope variables always need \
+an initializer, and run in order, so an initializer may not call a function that uses a \
+program-scope variable declared at or after it.",
+        example: "func main() [\n    let x: i32;\n    print(x);\n]",
+    },
+    Explanation {
+        code: diagnostics::MISSING_RETURN,
+        title: "function can end without returning",
+        description: "A function with a return type has a path that reaches the end of its body \
+without a return statement. The error points at where that path falls off, such as an `if` \
+without an `else`.",
+        example: "func sign(x: i32) -> i32 [\n    if x > 0 [ return 1; ]\n]",
+    },
+    Explanation {
+        code: diagnostics::REDECLARED,
+        title: "name declared twice",
+        description: "A variable, parameter, function, enum or enum variant reuses a name already \
+declared in the same scope. A note gives the first declaration. A `let` in an inner block may \
+shadow an outer variable, but not one of the same block, and not a parameter from the \
+function's outermost block.",
+        example: "func f(a: i32, a: i32) [ ]",
+    },
+    Explanation {
+        code: diagnostics::UNDECLARED,
+        title: "name not declared",
+        description: "A variable, function or type is used but never declared where it is \
+visible. Variables are visible from their `let` to the end of the enclosing block.",
+        example: "func main() [\n    if true [ let y = 1; ]\n    print(y);\n]",
+    },
+    Explanation {
+        code: diagnostics::ARGUMENT_COUNT,
+        title: "wrong number of arguments",
+        description: "A function or builtin is called with more or fewer arguments than it \
+takes.",
+        example: "func double(x: i32) -> i32 [ return x * 2; ]\nfunc main() [\n    print(double(1, 2));\n]",
+    },
+    Explanation {
+        code: diagnostics::NOT_CALLABLE,
+        title: "variable used as a function, or function as a value",
+        description: "Functions and variables live in separate namespaces: a variable cannot be \
+called, and a function name cannot be used as a value without calling it.",
+        example: "func main() [\n    let x = 3;\n    print(x());\n]",
+    },
+    Explanation {
+        code: diagnostics::RETURN_TYPE,
+        title: "returned value does not fit the function",
+        description: "The values a function returns disagree with each other or with its \
+declared return type, a function without a return type returns a value, or a function with \
+one uses a bare `return;`.",
+        example: "func f() -> i32 [\n    return \"one\";\n]",
+    },
+    Explanation {
+        code: diagnostics::BAD_MAIN,
+        title: "invalid main function",
+        description: "Execution starts at `main`, so every program needs one, and it must take \
+no parameters and return nothing or an i32.",
+        example: "func main(argc: i32) [ ]",
+    },
+    Explanation {
+        code: diagnostics::LOOP_CONTROL,
+        title: "misplaced break, continue or loop label",
+        description: "`break` or `continue` appears outside any loop, names a label that no \
+enclosing loop has, or a loop label reuses the label of a loop around it.",
+        example: "func main() [\n    break;\n]",
+    },
+    Explanation {
+        code: diagnostics::CONSTANT_ASSIGNED,
+        title: "assignment to a constant",
+        description: "A program-scope `const` is assigned to, or changed in place by a builtin \
+such as `push`. Use `let` for a value that changes.",
+        example: "const LIMIT = 10;\nfunc main() [\n    LIMIT = 11;\n]",
+    },
+    Explanation {
+        code: diagnostics::RUNTIME,
+        title: "runtime error",
+        description: "The program passed analysis but failed while running: an index out of \
+bounds, division by zero, integer overflow (unless run with --overflow wrap or saturate), \
+`unwrap` on `none`, recursion deeper than --max-call-depth allows (1000 calls by default), \
+or a similar error the analyzer cannot rule out ahead of time.",
+        example: "func main() [\n    let a = {1, 2};\n    print(a[5]);\n]",
+    },
+    Explanation {
+        code: diagnostics::ENDLESS_LOOP,
+        title: "loop that never ends",
+        description: "A `loop` has no `break` that leaves it and no `return`, so it runs \
+forever. Use -A W0001 if that is intended.",
+        example: "func main() [\n    loop [ print(1); ]\n]",
+    },
+    Explanation {
+        code: diagnostics::UNREAD_PARAMETER,
+        title: "parameter never read",
+        description: "A function never reads one of its parameters. Remove it, or start its \
+name with `_` to mark it as deliberately unused.",
+        example: "func f(x: i32) -> i32 [ return 0; ]",
+    },
+    Explanation {
+        code: diagnostics::UNCALLED_FUNCTION,
+        title: "function never called",
+        description: "A function other than `main` is never called anywhere in the program; \
+calls it makes to itself do not count. Start its name with `_` to keep it anyway.",
+        example: "func helper() [ ]\nfunc main() [ ]",
+    },
+    Explanation {
+        code: diagnostics::UNUSED_VARIABLE,
+        title: "local variable never read",
+        description: "A function declares a local variable that it never reads; storing into it \
+does not count. Remove it, or start its name with `_`.",
+        example: "func main() [\n    let total = 0;\n    total = 5;\n]",
+    },
+    Explanation {
+        code: diagnostics::BAD_ANNOTATION,
+        title: "annotation comment not understood",
+        description: "A comment starting with `lang:` is read as an annotation, and the only one \
+is `lang: allow(lint, ...)`, which silences the named lints within the statement or \
+declaration that follows. Lints go by name (endless_loop, unread_parameter, \
+uncalled_function, unused_variable, unreachable_branch) or by code.",
+        example: "func main() [\n    // lang: allow(unused)\n    let x = 1;\n]",
+    },
+    Explanation {
+        code: diagnostics::UNREACHABLE_BRANCH,
+        title: "branch that never runs",
+        description: "After constant folding, the condition of an `if` or `while` is a plain \
+`true` or `false`, so one branch of the `if`, or the whole body of the `while`, can never \
+run. The optimizer removes it before the program runs, unless run with -O0.",
+        example: "func main() [\n    if 1 > 2 [ print(1); ]\n]",
+    },
+];
+
+pub fn explain(code: &str) -> Option<&'static Explanation> {
+    EXPLANATIONS.iter().find(|e| e.code.eq_ignore_ascii_case(code))
+}
@@ -0,0 +1,109 @@
+// Bookkeeping for `execute --heap-stats`. The elements of an array, vector
+// or map live in a block shared between copies of the value (see Value);
+// every block is made, copied and freed through `Block`, which counts as it
+// goes.
+//
+// No collector is needed: a block is only ever changed through
+// Rc::make_mut, which copies it first when anything else holds it, so a
+// block can never come to hold itself, directly or through others, and the
+// last value to let go of one frees it.
+
+use std::cell::Cell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Counts of the blocks made on this thread.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+    // every block made, copies included
+    pub allocated: u64,
+    // blocks copied because a shared one was about to change
+    pub copied: u64,
+    pub live: u64,
+    pub peak: u64,
+}
+
+thread_local! {
+    static STATS: Cell<HeapStats> = Cell::new(HeapStats::default());
+}
+
+fn count(change: impl FnOnce(&mut HeapStats)) {
+    STATS.with(|stats| {
+        let mut counts = stats.get();
+        change(&mut counts);
+        stats.set(counts);
+    });
+}
+
+/// The blocks counted so far.
+pub fn stats() -> HeapStats {
+    STATS.with(Cell::get)
+}
+
+/// The elements of an array, vector or map, counted while they exist.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Block<T>(T);
+
+impl<T> Block<T> {
+    pub fn new(value: T) -> Self {
+        count(|stats| {
+            stats.allocated += 1;
+            stats.live += 1;
+            stats.peak = stats.peak.max(stats.live);
+        });
+        Block(value)
+    }
+}
+
+// a block read back from a snapshot is made like any other
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Block<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Block::new)
+    }
+}
+
+// only Rc::make_mut clones a block, when it is shared and about to change
+impl<T: Clone> Clone for Block<T> {
+    fn clone(&self) -> Self {
+        count(|stats| stats.copied += 1);
+        Block::new(self.0.clone())
+    }
+}
+
+impl<T: Default> Default for Block<T> {
+    fn default() -> Self {
+        Block::new(T::default())
+    }
+}
+
+impl<T> Drop for Block<T> {
+    fn drop(&mut self) {
+        count(|stats| stats.live -= 1);
+    }
+}
+
+impl<T> Deref for Block<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Block<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "blocks allocated   {:>10}", self.allocated)?;
+        writeln!(f, "copied on write    {:>10}", self.copied)?;
+        writeln!(f, "peak live          {:>10}", self.peak)?;
+        writeln!(f, "live at exit       {:>10}", self.live)
+    }
+}
@@ -1,13 +1,137 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use crate::semantic::{MTree, Type};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::diagnostics::{self, Diagnostic};
+use crate::heap::Block;
+use crate::intern::Symbol;
+use crate::profile::{Profile, Profiler};
+use crate::semantic::{resolve_slots, Ast, BinOp, MTree, NodeId, Slot, SymbolTable, Type, UnOp};
+use crate::stack;
+use crate::token::Span;
 
-#[derive(Clone, Debug)]
+/// Values usable as map keys.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MapKey {
+    Int(i32),
+    Bool(bool),
+    Char(char),
+    Str(Rc<str>),
+    Enum { enum_name: String, variant: String },
+}
+
+impl TryFrom<Value> for MapKey {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Int(i) => Ok(MapKey::Int(i)),
+            Value::Bool(b) => Ok(MapKey::Bool(b)),
+            Value::Char(c) => Ok(MapKey::Char(c)),
+            Value::Str(s) => Ok(MapKey::Str(s)),
+            Value::Enum { enum_name, variant } => Ok(MapKey::Enum { enum_name, variant }),
+            other => Err(format!("{:?} cannot be used as a map key", other)),
+        }
+    }
+}
+
+impl From<MapKey> for Value {
+    fn from(key: MapKey) -> Self {
+        match key {
+            MapKey::Int(i) => Value::Int(i),
+            MapKey::Bool(b) => Value::Bool(b),
+            MapKey::Char(c) => Value::Char(c),
+            MapKey::Str(s) => Value::Str(s),
+            MapKey::Enum { enum_name, variant } => Value::Enum { enum_name, variant },
+        }
+    }
+}
+
+/// A value of the running program. Every value is copied by assignment, by
+/// `let` and by passing it to a function, so no two variables ever share
+/// one. Arrays and maps make the copy lazily: a copy shares its elements
+/// until one side changes them (copy on write), so passing a large array
+/// costs nothing unless the callee modifies it. Strings are shared outright,
+/// since no operation changes one in place, so reading a string variable
+/// never copies its text. The elements live in a counted `Block` (see
+/// heap.rs); since a shared block is copied before it changes, no value can
+/// ever contain itself, and plain reference counting frees every block.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Value {
     Int(i32),
+    Float(f32),
     Bool(bool),
+    Char(char),
+    Str(Rc<str>),
+    Array(Rc<Block<Vec<Value>>>),
+    #[serde(with = "entries")]
+    Map(Rc<Block<HashMap<MapKey, Value>>>),
+    Option(Option<Box<Value>>),
+    Enum { enum_name: String, variant: String },
     Void,
 }
 
+// JSON keys can only be strings, so a map is saved as the list of its
+// entries, sorted so the same map always comes out the same
+mod entries {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(entries: &Rc<Block<HashMap<MapKey, Value>>>, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut sorted: Vec<_> = entries.iter().collect();
+        sorted.sort_by(|a, b| a.0.cmp(b.0));
+        serializer.collect_seq(sorted)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rc<Block<HashMap<MapKey, Value>>>, D::Error> {
+        let entries: Vec<(MapKey, Value)> = Vec::deserialize(deserializer)?;
+        Ok(Rc::new(Block::new(entries.into_iter().collect())))
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Bool(b) => write!(f, "{}", if *b { "true" } else { "false" }),
+            Value::Char(c) => write!(f, "{}", c),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Array(elems) => {
+                write!(f, "{{")?;
+                for (i, e) in elems.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", e)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Map(entries) => {
+                // sorted so output does not depend on hash order
+                let mut keys: Vec<&MapKey> = entries.keys().collect();
+                keys.sort();
+                write!(f, "{{")?;
+                for (i, key) in keys.into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", Value::from(key.clone()), entries[key])?;
+                }
+                write!(f, "}}")
+            }
+            Value::Option(Some(inner)) => write!(f, "some({})", inner),
+            Value::Option(None) => write!(f, "none"),
+            Value::Enum { variant, .. } => write!(f, "{}", variant),
+            Value::Void => write!(f, "void"),
+        }
+    }
+}
+
 impl Value {
     pub fn as_int(&self) -> Result<i32, String> {
         match self {
@@ -22,285 +146,1366 @@ impl Value {
             _ => Err(format!("Expected Bool, found {:?}", self)),
         }
     }
+
+    pub fn as_str(&self) -> Result<String, String> {
+        match self {
+            Value::Str(s) => Ok(s.to_string()),
+            _ => Err(format!("Expected Str, found {:?}", self)),
+        }
+    }
+}
+
+/// What integer arithmetic does with a result that does not fit in an i32.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Overflow {
+    // wrap around, as two's complement
+    Wrap,
+    // clamp to i32::MIN or i32::MAX
+    Saturate,
+    // stop the program with a runtime error
+    #[default]
+    Checked,
+}
+
+impl Overflow {
+    // the result under this mode of an operation whose checked result is
+    // `checked`, or None when the overflow is to be reported
+    fn pick(self, checked: Option<i32>, wrapped: i32, saturated: i32) -> Option<i32> {
+        match self {
+            Overflow::Checked => checked,
+            Overflow::Wrap => Some(wrapped),
+            Overflow::Saturate => Some(saturated),
+        }
+    }
 }
 
+// the generator behind `rand`: splitmix64, which is small, fast and fine
+// for games and simulations, though not for anything secret
+struct Rng(u64);
+
+impl Rng {
+    // seeded from the clock, so each run differs unless --seed is given
+    fn from_clock() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default();
+        Rng(nanos)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // a number from `lo` to `hi`, both included
+    fn between(&mut self, lo: i32, hi: i32) -> i32 {
+        let range = (hi as i64 - lo as i64 + 1) as u64;
+        (lo as i64 + (self.next() % range) as i64) as i32
+    }
+}
+
+/// Why running stopped short: a runtime error, or the program calling
+/// `exit`, which unwinds every call without being an error.
+///
+/// `exit` is a call, so it can happen in the middle of an expression, where
+/// evaluation returns a `Value` and has no `Flow` to carry it; only the error
+/// channel leaves every expression, statement and call at once. Sharing it
+/// with errors is safe because an exit is never caught on the way out, and
+/// only `Halt::Error` records a fault, so an exit prints no trace.
+#[derive(Debug)]
+pub enum Halt {
+    Error(String),
+    Exit(i32),
+}
+
+impl From<String> for Halt {
+    fn from(message: String) -> Self {
+        Halt::Error(message)
+    }
+}
+
+/// How a statement finished: fall through, leave or restart a loop
+/// (the innermost one, or the one with the given label), return from the
+/// function, or return what a call in tail position returns, which the
+/// function's caller makes in place of the current call.
+#[derive(Debug)]
+pub enum Flow {
+    Normal,
+    Break(Option<Symbol>),
+    Continue(Option<Symbol>),
+    Return(Value),
+    TailCall(Symbol, Vec<Value>, Span),
+}
+
+/// Variables visible to the running code: program-scope variables, and the
+/// slots of the current call. Each call gets a frame of its own, so a
+/// function sees its parameters, its own locals and the globals, never the
+/// locals of whoever called it. Variables are found by the slot
+/// `resolve_slots` gave them; their names are only for error messages.
 pub struct Environment {
-    scopes: Vec<HashMap<String, Value>>,
+    globals: Vec<Value>,
+    // the name of each global, by slot, for snapshots
+    names: Vec<Symbol>,
+    // slots of the running call; empty outside any call
+    frame: Vec<Value>,
+    // the frames of the calls the running one interrupted, outermost first
+    callers: Vec<Vec<Value>>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Self {
-            scopes: vec![HashMap::new()],
+            globals: Vec::new(),
+            names: Vec::new(),
+            frame: Vec::new(),
+            callers: Vec::new(),
         }
     }
 
-    pub fn push_scope(&mut self) {
-        self.scopes.push(HashMap::new());
+    // start a call's frame of `size` slots, the first holding `args`, until
+    // leave_frame brings back the caller's
+    pub fn enter_frame(&mut self, args: Vec<Value>, size: usize) {
+        let caller = std::mem::take(&mut self.frame);
+        self.callers.push(caller);
+        self.replace_frame(args, size);
+    }
+
+    // give the running call a new frame, as a tail call does
+    pub fn replace_frame(&mut self, mut args: Vec<Value>, size: usize) {
+        args.resize(size.max(args.len()), Value::Void);
+        self.frame = args;
+    }
+
+    pub fn leave_frame(&mut self) {
+        self.frame = self.callers.pop().unwrap_or_default();
+    }
+
+    // the frame of each call under way, outermost first
+    fn frames(&self) -> impl Iterator<Item = &Vec<Value>> {
+        self.callers.iter().skip(1).chain(std::iter::once(&self.frame))
     }
 
-    pub fn pop_scope(&mut self) {
-        if self.scopes.len() > 1 {
-            self.scopes.pop();
+    // globals are declared in slot order, so each new one goes at the end
+    pub fn declare(&mut self, name: Symbol, slot: Slot, value: Value) -> Result<(), String> {
+        if let Slot::Global(i) = slot
+            && i == self.globals.len()
+        {
+            self.globals.push(value);
+            self.names.push(name);
+            return Ok(());
         }
+        *self.get_mut(name, slot)? = value;
+        Ok(())
+    }
+
+    pub fn set(&mut self, name: Symbol, slot: Slot, value: Value) -> Result<(), String> {
+        *self.get_mut(name, slot)? = value;
+        Ok(())
     }
 
-    pub fn declare(&mut self, name: String, value: Value) {
-        if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name, value);
+    // the variable's value, to change in place
+    pub fn get_mut(&mut self, name: Symbol, slot: Slot) -> Result<&mut Value, String> {
+        let value = match slot {
+            Slot::Local(i) => self.frame.get_mut(i),
+            Slot::Global(i) => self.globals.get_mut(i),
+            Slot::Unresolved => None,
+        };
+        value.ok_or_else(|| format!("Variable '{}' not found", name))
+    }
+
+    pub fn get(&self, name: Symbol, slot: Slot) -> Result<Value, String> {
+        let value = match slot {
+            Slot::Local(i) => self.frame.get(i),
+            Slot::Global(i) => self.globals.get(i),
+            Slot::Unresolved => None,
+        };
+        value.cloned().ok_or_else(|| format!("Variable '{}' not found", name))
+    }
+}
+
+// how many calls may be active at once unless set with with_max_depth
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+// each call the interpreter runs recurses through several of its own
+// functions, more for deeply nested bodies; this bounds the Rust stack one
+// call with an ordinary body takes, so the caller can give the interpreter
+// a stack that fits max_depth calls
+pub const STACK_PER_CALL: usize = 256 * 1024;
+
+// a function being run, and where it was called from; main has no caller
+pub type Call = (Symbol, Option<Span>);
+
+/// The state of a run at one moment, as `checkpoint()` saves it: every
+/// program-scope variable by name, the calls under way with the slots of
+/// each, outermost first, and where `rand` had got to. A run made
+/// `with_snapshot` starts `main` afresh with those globals and that random
+/// state; the calls are there to inspect, as a call cannot be re-entered
+/// midway.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub globals: Vec<(Symbol, Value)>,
+    pub calls: Vec<SavedCall>,
+    pub rng: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedCall {
+    pub function: Symbol,
+    pub site: Option<Span>,
+    pub slots: Vec<Value>,
+}
+
+impl Snapshot {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("snapshot serializes to JSON")
+    }
+
+    pub fn from_json(text: &str) -> Result<Snapshot, String> {
+        serde_json::from_str(text).map_err(|e| format!("not a snapshot: {}", e))
+    }
+
+    // the saved value of the program-scope variable `name`
+    fn global(&self, name: Symbol) -> Option<Value> {
+        self.globals.iter().find(|(saved, _)| *saved == name).map(|(_, value)| value.clone())
+    }
+
+    /// A type error for each program-scope variable of `ast` whose saved
+    /// value does not fit the type `symbols` gives it after analysis, as when
+    /// the program changed since the snapshot was taken.
+    pub fn mismatches(&self, ast: &Ast, symbols: &SymbolTable) -> Vec<Diagnostic> {
+        let MTree::START { funcs } = &ast[ast.root] else { return Vec::new() };
+        let enums: HashMap<String, Vec<String>> = funcs
+            .iter()
+            .filter_map(|&item| match &ast[item] {
+                MTree::ENUM_DECL { name, variants, .. } => Some((name.clone(), variants.clone())),
+                _ => None,
+            })
+            .collect();
+        let mut errors = Vec::new();
+        for &item in funcs {
+            if let MTree::LET_STMT { id, span, .. } = &ast[item]
+                && let Some(value) = self.global(*id)
+            {
+                let ty = symbols.check(*id).unwrap_or(Type::Unknown);
+                if !fits(&value, &ty, &enums) {
+                    errors.push(
+                        Diagnostic::error(diagnostics::TYPE_MISMATCH, format!("Snapshot saved '{}' as {}, which is not a {:?}", id, value, ty))
+                            .at(*span),
+                    );
+                }
+            }
         }
+        errors
     }
+}
 
-    pub fn set(&mut self, name: &str, value: Value) -> Result<(), String> {
-        // Search from innermost to outermost scope
-        for scope in self.scopes.iter_mut().rev() {
-            if scope.contains_key(name) {
-                scope.insert(name.to_string(), value);
-                return Ok(());
+// where a runtime error arose: the innermost expression or statement that
+// failed, and the calls active at the time, outermost first
+pub struct Fault {
+    pub span: Span,
+    pub calls: Vec<Call>,
+}
+
+impl Fault {
+    // a note per active call, innermost first, like `in 'f', called at 3:9`;
+    // a run of recursive calls to one function gets a single note
+    fn trace(&self) -> Vec<String> {
+        let mut runs: Vec<(Call, usize)> = Vec::new();
+        for &call in &self.calls {
+            match runs.last_mut() {
+                Some(((name, _), depth)) if *name == call.0 => *depth += 1,
+                _ => runs.push((call, 1)),
             }
         }
-        Err(format!("Variable '{}' not found", name))
+        runs.into_iter()
+            .rev()
+            .map(|((name, site), depth)| {
+                let nested = if depth > 1 { format!(" ({} calls deep)", depth) } else { String::new() };
+                match site {
+                    Some(site) if depth > 1 => format!("in '{}'{}, first called at {}", name, nested, site),
+                    Some(site) => format!("in '{}', called at {}", name, site),
+                    None => format!("in '{}'{}", name, nested),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The runtime error `message`, placed where it arose and with the chain of
+/// calls that led there, when that is known.
+pub fn runtime_error(message: String, fault: Option<Fault>) -> Diagnostic {
+    let mut error = Diagnostic::error(diagnostics::RUNTIME, message);
+    if let Some(fault) = fault {
+        error = error.at(fault.span);
+        for note in fault.trace() {
+            error = error.with_note(note);
+        }
+    }
+    error
+}
+
+/// A function the host provides, called with its evaluated arguments.
+pub type NativeFn = fn(&[Value]) -> Result<Value, String>;
+
+// the builtins that only read their arguments, which run through the same
+// registry as a host's own functions: (name, arity, function)
+const STANDARD_BUILTINS: [(&str, usize, NativeFn); 14] = [
+    ("len", 1, |args| match &args[0] {
+        Value::Array(elems) => Ok(Value::Int(elems.len() as i32)),
+        Value::Map(entries) => Ok(Value::Int(entries.len() as i32)),
+        Value::Str(s) => Ok(Value::Int(s.chars().count() as i32)),
+        other => Err(format!("'len' expects a collection, found {:?}", other)),
+    }),
+    ("map", 0, |_| Ok(Value::Map(Rc::default()))),
+    ("get", 2, |args| {
+        let key = MapKey::try_from(args[1].clone())?;
+        match map_arg("get", &args[0])?.get(&key) {
+            Some(value) => Ok(value.clone()),
+            None => Err(format!("Key {} not found in map", Value::from(key))),
+        }
+    }),
+    ("contains", 2, |args| {
+        let key = MapKey::try_from(args[1].clone())?;
+        Ok(Value::Bool(map_arg("contains", &args[0])?.contains_key(&key)))
+    }),
+    ("int_to_string", 1, |args| Ok(Value::Str(args[0].as_int()?.to_string().into()))),
+    ("some", 1, |args| Ok(Value::Option(Some(Box::new(args[0].clone()))))),
+    ("is_some", 1, |args| Ok(Value::Bool(option_arg("is_some", &args[0])?.is_some()))),
+    ("is_none", 1, |args| Ok(Value::Bool(option_arg("is_none", &args[0])?.is_none()))),
+    ("unwrap", 1, |args| {
+        option_arg("unwrap", &args[0])?.clone().map(|v| *v).ok_or_else(|| "Called unwrap on none".to_string())
+    }),
+    ("min", 2, |args| pick_number("min", &args[0], &args[1], Ordering::Less)),
+    ("max", 2, |args| pick_number("max", &args[0], &args[1], Ordering::Greater)),
+    ("sqrt", 1, |args| Ok(Value::Float(float_arg("sqrt", &args[0])?.sqrt()))),
+    ("floor", 1, |args| Ok(Value::Float(float_arg("floor", &args[0])?.floor()))),
+    ("ceil", 1, |args| Ok(Value::Float(float_arg("ceil", &args[0])?.ceil()))),
+];
+
+// the builtins handled by each engine rather than the native registry
+pub const INTERPRETER_BUILTINS: [&str; 16] = [
+    "push", "pop", "insert", "remove", "read_int", "read_line", "read_file", "write_file", "arg_count", "arg_int",
+    "arg_str", "rand", "exit", "abs", "pow", "checkpoint",
+];
+
+fn map_arg<'a>(name: &str, value: &'a Value) -> Result<&'a HashMap<MapKey, Value>, String> {
+    match value {
+        Value::Map(entries) => Ok(&**entries),
+        other => Err(format!("'{}' expects a map, found {:?}", name, other)),
+    }
+}
+
+// `right` if it compares to `left` as `wanted`, otherwise `left`
+fn pick_number(name: &str, left: &Value, right: &Value, wanted: Ordering) -> Result<Value, String> {
+    let ordering = match (left, right) {
+        (Value::Int(l), Value::Int(r)) => r.partial_cmp(l),
+        (Value::Float(l), Value::Float(r)) => r.partial_cmp(l),
+        (l, r) => return Err(format!("'{}' expects two ints or two floats, found {:?} and {:?}", name, l, r)),
+    };
+    Ok(if ordering == Some(wanted) { right.clone() } else { left.clone() })
+}
+
+fn float_arg(name: &str, value: &Value) -> Result<f32, String> {
+    match value {
+        Value::Float(f) => Ok(*f),
+        other => Err(format!("'{}' expects a float, found {:?}", name, other)),
+    }
+}
+
+fn option_arg<'a>(name: &str, value: &'a Value) -> Result<&'a Option<Box<Value>>, String> {
+    match value {
+        Value::Option(inner) => Ok(inner),
+        other => Err(format!("'{}' expects an option, found {:?}", name, other)),
+    }
+}
+
+/// What a running program can reach beyond its own variables, the same
+/// whichever engine runs it: the native functions, program input and
+/// arguments, the file system if allowed, the random numbers `rand` draws,
+/// and the limits set on the run.
+pub struct Host {
+    max_depth: usize,
+    overflow: Overflow,
+    // functions implemented in Rust, by name: (arity, function)
+    natives: HashMap<Symbol, (usize, NativeFn)>,
+    // where read_int and read_line read from
+    input: Box<dyn BufRead>,
+    rng: Rng,
+    // whether read_file and write_file may touch the file system
+    file_access: bool,
+    // the arguments given to the program on the command line
+    args: Vec<String>,
+}
+
+impl Host {
+    pub fn new() -> Self {
+        let mut host = Self {
+            max_depth: MAX_CALL_DEPTH,
+            overflow: Overflow::default(),
+            natives: HashMap::new(),
+            input: Box::new(io::stdin().lock()),
+            rng: Rng::from_clock(),
+            file_access: false,
+            args: Vec::new(),
+        };
+        for (name, arity, function) in STANDARD_BUILTINS {
+            host.register_builtin(name, arity, function);
+        }
+        host
+    }
+
+    /// Make `function` callable from programs as `name` with `arity`
+    /// arguments. Calls to it are resolved before user functions, so a
+    /// host can add functions without changing the evaluator; the
+    /// analyzer learns of them from `natives`.
+    pub fn register_builtin(&mut self, name: &str, arity: usize, function: NativeFn) {
+        self.natives.insert(Symbol::intern(name), (arity, function));
+    }
+
+    // every native function and its arity, for the analyzer
+    pub fn natives(&self) -> impl Iterator<Item = (Symbol, usize)> + '_ {
+        self.natives.iter().map(|(name, (arity, _))| (*name, *arity))
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
     }
 
-    pub fn get(&self, name: &str) -> Result<Value, String> {
-        // Search from innermost to outermost scope
-        for scope in self.scopes.iter().rev() {
-            if let Some(value) = scope.get(name) {
-                return Ok(value.clone());
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    // seed `rand`, so a run can be repeated exactly
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng(seed);
+        self
+    }
+
+    // let read_file and write_file use the file system, which they are
+    // denied by default so untrusted programs can be run safely
+    pub fn with_file_access(mut self) -> Self {
+        self.file_access = true;
+        self
+    }
+
+    // the arguments arg_count, arg_int and arg_str see
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    // read program input from `input` instead of stdin
+    pub fn with_input(mut self, input: impl BufRead + 'static) -> Self {
+        self.input = Box::new(input);
+        self
+    }
+
+    // call the native function `name` with `args`, checking their number
+    pub fn call_native(&self, name: Symbol, args: &[Value]) -> Result<Value, String> {
+        let &(arity, native) = self.natives.get(&name).ok_or_else(|| format!("Function '{}' not found", name))?;
+        if arity != args.len() {
+            return Err(format!("Function '{}' expects {} arguments, got {}", name, arity, args.len()));
+        }
+        native(args)
+    }
+
+    pub fn is_native(&self, name: Symbol) -> bool {
+        self.natives.contains_key(&name)
+    }
+
+    // the next line of input, without its line ending; running out of input
+    // is an error rather than an endless run of empty lines
+    fn read_line(&mut self, name: &str) -> Result<String, String> {
+        let mut line = String::new();
+        match self.input.read_line(&mut line) {
+            Ok(0) => Err(format!("'{}' found no more input", name)),
+            Ok(_) => Ok(line.trim_end_matches(['\n', '\r']).to_string()),
+            Err(e) => Err(format!("'{}' cannot read input: {}", name, e)),
+        }
+    }
+
+    /// Run one of the builtins that need more than their arguments:
+    /// `exit`, those that read input, files or program arguments or draw
+    /// random numbers, and those whose int results overflow as --overflow
+    /// says. Builtins that change a variable, and `checkpoint`, are left
+    /// to the engine.
+    pub fn call_builtin(&mut self, name: &str, args: &[Value]) -> Result<Value, Halt> {
+        match name {
+            "abs" => match args[0] {
+                Value::Int(i) => {
+                    let result = self.overflow.pick(i.checked_abs(), i.wrapping_abs(), i.saturating_abs());
+                    Ok(result.map(Value::Int).ok_or_else(|| format!("Integer overflow in abs({})", i))?)
+                }
+                Value::Float(f) => Ok(Value::Float(f.abs())),
+                ref other => Err(format!("'abs' expects a number, found {:?}", other).into()),
+            },
+            "exit" => Err(Halt::Exit(args[0].as_int()?)),
+            "read_file" | "write_file" => {
+                if !self.file_access {
+                    return Err(format!("'{}' needs file access, which is only given with --allow-fs", name).into());
+                }
+                let path = args[0].as_str()?;
+                if name == "read_file" {
+                    let text = fs::read_to_string(&path).map_err(|e| format!("Cannot read '{}': {}", path, e))?;
+                    return Ok(Value::Str(text.into()));
+                }
+                let text = args[1].as_str()?;
+                fs::write(&path, text).map_err(|e| format!("Cannot write '{}': {}", path, e))?;
+                Ok(Value::Void)
             }
+            "arg_count" => Ok(Value::Int(self.args.len() as i32)),
+            "arg_int" | "arg_str" => {
+                let i = args[0].as_int()?;
+                let arg = usize::try_from(i)
+                    .ok()
+                    .and_then(|i| self.args.get(i))
+                    .ok_or_else(|| format!("Program argument {} out of range ({} given)", i, self.args.len()))?;
+                if name == "arg_str" {
+                    return Ok(Value::Str(arg.as_str().into()));
+                }
+                let value = arg.trim().parse().map_err(|_| format!("'arg_int' expects an integer, found '{}'", arg))?;
+                Ok(Value::Int(value))
+            }
+            "rand" => {
+                let lo = args[0].as_int()?;
+                let hi = args[1].as_int()?;
+                if lo > hi {
+                    return Err(format!("'rand' expects lo <= hi, found {} and {}", lo, hi).into());
+                }
+                Ok(Value::Int(self.rng.between(lo, hi)))
+            }
+            "pow" => Ok(eval_arith(BinOp::Pow, args[0].clone(), args[1].clone(), self.overflow)?),
+            "read_line" => Ok(Value::Str(self.read_line(name)?.into())),
+            "read_int" => {
+                let line = self.read_line(name)?;
+                let value = line.trim().parse().map_err(|_| format!("'read_int' expects an integer, found '{}'", line))?;
+                Ok(Value::Int(value))
+            }
+            _ => Err(format!("Function '{}' not found", name).into()),
         }
-        Err(format!("Variable '{}' not found", name))
     }
 }
 
+// (params, return type, body, frame size)
+type FunctionDef = (Vec<(Symbol, Type, Span)>, Type, NodeId, usize);
+
 pub struct Interpreter {
     env: Environment,
-    functions: HashMap<String, (Vec<(String, Type)>, Type, Box<MTree>)>,
+    functions: HashMap<Symbol, FunctionDef>,
+    enums: HashMap<String, Vec<String>>,
+    // variant name -> enum it belongs to
+    variants: HashMap<String, String>,
+    // the functions being run, outermost first
+    calls: Vec<Call>,
+    fault: Option<Fault>,
+    host: Host,
+    // counts for --profile, when asked for
+    profiler: Option<Profiler>,
+    // where checkpoint() saves the state; nowhere when None
+    checkpoints: Option<String>,
+    // the state a resumed run starts from
+    resume: Option<Snapshot>,
 }
 
 impl Interpreter {
-    pub fn new() -> Self {
+    pub fn new(host: Host) -> Self {
         Self {
             env: Environment::new(),
             functions: HashMap::new(),
+            enums: HashMap::new(),
+            variants: HashMap::new(),
+            calls: Vec::new(),
+            fault: None,
+            host,
+            profiler: None,
+            checkpoints: None,
+            resume: None,
         }
     }
 
-    pub fn execute(&mut self, ast: MTree) -> Result<(), String> {
-        // Register all functions
-        if let MTree::START { funcs } = &ast {
-            for func in funcs {
-                if let MTree::FUNC_DECL { name, params, ret_type, body } = func {
-                    self.functions.insert(
-                        name.clone(),
-                        (params.clone(), ret_type.clone(), body.clone()),
-                    );
+    // count the nodes evaluated and the calls made while running, for
+    // `profile` to report
+    pub fn with_profile(mut self) -> Self {
+        self.profiler = Some(Profiler::default());
+        self
+    }
+
+    /// What the run has done so far, when made `with_profile`.
+    pub fn profile(&self) -> Option<Profile> {
+        self.profiler.as_ref().map(Profiler::report)
+    }
+
+    // have checkpoint() save the state of the run to the file at `path`;
+    // without this it does nothing
+    pub fn with_checkpoints(mut self, path: String) -> Self {
+        self.checkpoints = Some(path);
+        self
+    }
+
+    // start from the globals and random state of `snapshot`: a program-scope
+    // variable it saved takes the saved value instead of being initialized
+    pub fn with_snapshot(mut self, snapshot: Snapshot) -> Self {
+        self.host.rng = Rng(snapshot.rng);
+        self.resume = Some(snapshot);
+        self
+    }
+
+    /// The state of the run as it stands.
+    pub fn snapshot(&self) -> Snapshot {
+        let globals = self.env.names.iter().copied().zip(self.env.globals.iter().cloned()).collect();
+        let calls = self
+            .calls
+            .iter()
+            .zip(self.env.frames())
+            .map(|(&(function, site), slots)| SavedCall { function, site, slots: slots.clone() })
+            .collect();
+        Snapshot { globals, calls, rng: self.host.rng.0 }
+    }
+
+    // remember where the first error arose, with the calls active then; the
+    // innermost expression or statement to fail reports it first
+    fn note_fault(&mut self, ast: &Ast, node: NodeId) {
+        if self.fault.is_none()
+            && let Some(span) = ast.span(node)
+        {
+            self.fault = Some(Fault { span, calls: self.calls.clone() });
+        }
+    }
+
+    // an error leaving a statement that runs part of an inlined call passed
+    // through that call, so its trace gets back the frame inlining took
+    // away, at the depth the statement ran at
+    fn note_inlined(&mut self, ast: &Ast, stmt: NodeId) {
+        if let (Some(fault), Some((function, site))) = (&mut self.fault, ast.inlined(stmt)) {
+            fault.calls.insert(self.calls.len(), (function, Some(site)));
+        }
+    }
+
+
+    /// Run the program, returning the status it ends with: the one it gives
+    /// `exit`, or 0 when `main` returns.
+    pub fn execute(&mut self, mut ast: Ast) -> Result<i32, Diagnostic> {
+        resolve_slots(&mut ast);
+        let ast = &ast;
+
+        // Register all functions and enums
+        if let MTree::START { funcs } = &ast[ast.root] {
+            for &func in funcs {
+                match &ast[func] {
+                    MTree::FUNC_DECL { name, params, ret_type, body, frame, .. } => {
+                        self.functions.insert(
+                            *name,
+                            (params.clone(), ret_type.clone(), *body, *frame),
+                        );
+                    }
+                    MTree::ENUM_DECL { name, variants, .. } => {
+                        for v in variants {
+                            self.variants.insert(v.clone(), name.clone());
+                        }
+                        self.enums.insert(name.clone(), variants.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Initialize globals in order, outside any frame
+        if let MTree::START { funcs } = &ast[ast.root] {
+            for &item in funcs {
+                // a resumed run gives a variable the value the snapshot
+                // saved, if any; constants are always as the program says
+                let saved = match ast[item] {
+                    MTree::LET_STMT { id, slot, .. } => {
+                        self.resume.as_ref().and_then(|snapshot| snapshot.global(id)).map(|value| (id, slot, value))
+                    }
+                    _ => None,
+                };
+                let result = match (saved, &ast[item]) {
+                    (Some((id, slot, value)), _) => Ok(self.env.declare(id, slot, value)?),
+                    (None, MTree::LET_STMT { .. }) => self.execute_statement(ast, item).map(|_| ()),
+                    (None, MTree::CONST_DECL { id, slot, expr, .. }) => {
+                        self.eval_expr(ast, *expr).and_then(|value| Ok(self.env.declare(*id, *slot, value)?))
+                    }
+                    _ => Ok(()),
+                };
+                if let Err(halt) = result {
+                    self.note_fault(ast, item);
+                    return self.halted(halt);
                 }
             }
         }
 
         // Call main
-        match self.call_function("main", vec![]) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(format!("Runtime error: {}", e)),
+        match self.call_function(ast, Symbol::intern("main"), vec![], None) {
+            Ok(_) => Ok(0),
+            Err(halt) => self.halted(halt),
         }
     }
 
-    fn call_function(&mut self, name: &str, args: Vec<Value>) -> Result<Value, String> {
-        let (params, ret_type, body) = self.functions.get(name)
-            .ok_or_else(|| format!("Function '{}' not found", name))?
-            .clone();
+    // the outcome of a run that stopped short
+    fn halted(&mut self, halt: Halt) -> Result<i32, Diagnostic> {
+        match halt {
+            Halt::Exit(status) => Ok(status),
+            Halt::Error(message) => Err(runtime_error(message, self.fault.take())),
+        }
+    }
 
-        // Check argument count
-        if params.len() != args.len() {
-            return Err(format!(
-                "Function '{}' expects {} arguments, got {}",
-                name,
-                params.len(),
-                args.len()
-            ));
+    // `site` is where the call is made, for the trace of a runtime error
+    fn call_function(&mut self, ast: &Ast, name: Symbol, args: Vec<Value>, site: Option<Span>) -> Result<Value, Halt> {
+        let (mut body, frame) = self.function(name, args.len())?;
+        if self.calls.len() >= self.host.max_depth {
+            return Err(format!("maximum recursion depth exceeded ({})", self.host.max_depth).into());
+        }
+        self.calls.push((name, site));
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(name);
         }
 
-        // Create new scope 
-        self.env.push_scope();
+        // Run in a fresh frame, the params in its first slots
+        self.env.enter_frame(args, frame);
 
-        // Bind params
-        for ((param_name, _), arg_value) in params.iter().zip(args.iter()) {
-            self.env.declare(param_name.clone(), arg_value.clone());
+        // a call in tail position takes over this call's place rather than
+        // nesting inside it, so recursion through tail calls never deepens
+        let result = loop {
+            match self.execute_block(ast, body) {
+                Ok(Flow::TailCall(callee, args, site)) => match self.function(callee, args.len()) {
+                    Ok((callee_body, frame)) => {
+                        self.env.replace_frame(args, frame);
+                        if let Some(call) = self.calls.last_mut() {
+                            *call = (callee, Some(site));
+                        }
+                        if let Some(profiler) = &mut self.profiler {
+                            profiler.leave();
+                            profiler.enter(callee);
+                        }
+                        body = callee_body;
+                    }
+                    Err(e) => break Err(e.into()),
+                },
+                result => break result,
+            }
+        };
+        self.env.leave_frame();
+        self.calls.pop();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.leave();
         }
 
-        // Execute function body
-        let result = match self.execute_block(&body)? {
-            Some(val) => val,
-            None => Value::Void,
-        };
+        Ok(match result? {
+            Flow::Return(val) => val,
+            _ => Value::Void,
+        })
+    }
 
-        
-        self.env.pop_scope();
+    // the body and frame size of the function `name`, checking it takes
+    // `arg_count` arguments
+    fn function(&self, name: Symbol, arg_count: usize) -> Result<(NodeId, usize), String> {
+        let (params, _, body, frame) = self.functions.get(&name).ok_or_else(|| format!("Function '{}' not found", name))?;
+        if params.len() != arg_count {
+            return Err(format!("Function '{}' expects {} arguments, got {}", name, params.len(), arg_count));
+        }
+        Ok((*body, *frame))
+    }
 
-        Ok(result)
+    // whether a call to `name` goes to a function of the program, rather
+    // than a builtin or native function, which come first
+    fn is_user_function(&self, name: Symbol) -> bool {
+        self.functions.contains_key(&name)
+            && !self.host.is_native(name)
+            && !INTERPRETER_BUILTINS.contains(&name.as_str())
     }
 
-    fn execute_block(&mut self, block: &MTree) -> Result<Option<Value>, String> {
-        if let MTree::BLOCK { stmts } = block {
-            for stmt in stmts {
-                if let Some(ret_val) = self.execute_statement(stmt)? {
-                    return Ok(Some(ret_val));
+    // the builtins the native registry cannot run: those that change a
+    // vector or map variable in place, which take that argument unevaluated,
+    // `checkpoint`, and the ones the host runs on their evaluated arguments
+    fn call_builtin(&mut self, ast: &Ast, name: &str, args: &[NodeId]) -> Result<Value, Halt> {
+        match name {
+            "checkpoint" => {
+                if let Some(path) = &self.checkpoints {
+                    fs::write(path, self.snapshot().to_json()).map_err(|e| format!("Cannot write '{}': {}", path, e))?;
                 }
+                Ok(Value::Void)
             }
-            Ok(None)
+            "push" | "pop" => {
+                let (var, slot) = match &ast[args[0]] {
+                    MTree::ID { name, slot, .. } => (name, slot),
+                    _ => return Err(format!("'{}' expects a vector variable", name).into()),
+                };
+                let pushed = if name == "push" { Some(self.eval_expr(ast, args[1])?) } else { None };
+                let elems = match self.env.get_mut(*var, *slot)? {
+                    Value::Array(elems) => Rc::make_mut(elems),
+                    other => return Err(format!("'{}' expects a vector, found {:?}", name, other).into()),
+                };
+                match pushed {
+                    Some(value) => {
+                        elems.push(value);
+                        Ok(Value::Void)
+                    }
+                    None => Ok(elems.pop().ok_or_else(|| format!("Cannot pop from empty vector '{}'", var))?),
+                }
+            }
+            "insert" | "remove" => {
+                let (var, slot) = match &ast[args[0]] {
+                    MTree::ID { name, slot, .. } => (name, slot),
+                    _ => return Err(format!("'{}' expects a map variable", name).into()),
+                };
+                let key = MapKey::try_from(self.eval_expr(ast, args[1])?)?;
+                let inserted = if name == "insert" { Some(self.eval_expr(ast, args[2])?) } else { None };
+                let entries = match self.env.get_mut(*var, *slot)? {
+                    Value::Map(entries) => Rc::make_mut(entries),
+                    other => return Err(format!("'{}' expects a map, found {:?}", name, other).into()),
+                };
+                match inserted {
+                    Some(value) => {
+                        entries.insert(key, value);
+                        Ok(Value::Void)
+                    }
+                    None => Ok(entries
+                        .remove(&key)
+                        .ok_or_else(|| format!("Key {} not found in map '{}'", Value::from(key), var))?),
+                }
+            }
+            _ => {
+                let mut values = Vec::new();
+                for &arg in args {
+                    values.push(self.eval_expr(ast, arg)?);
+                }
+                self.host.call_builtin(name, &values)
+            }
+        }
+    }
+
+    // a block's lets have slots of their own, which resolve_slots keeps
+    // out of reach once the block ends
+    fn execute_block(&mut self, ast: &Ast, block: NodeId) -> Result<Flow, Halt> {
+        if let MTree::BLOCK { stmts, .. } = &ast[block] {
+            self.execute_stmts(ast, stmts)
         } else {
-            Err("Expected BLOCK node".to_string())
+            Err("Expected BLOCK node".to_string().into())
         }
     }
 
-    fn execute_statement(&mut self, stmt: &MTree) -> Result<Option<Value>, String> {
-        match stmt {
-            MTree::LET_STMT { id, ty, expr } => {
+    fn execute_stmts(&mut self, ast: &Ast, stmts: &[NodeId]) -> Result<Flow, Halt> {
+        for &stmt in stmts {
+            match self.execute_statement(ast, stmt) {
+                Ok(Flow::Normal) => {}
+                Ok(flow) => return Ok(flow),
+                Err(halt) => {
+                    if let Halt::Error(_) = halt {
+                        self.note_fault(ast, stmt);
+                        self.note_inlined(ast, stmt);
+                    }
+                    return Err(halt);
+                }
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn execute_statement(&mut self, ast: &Ast, stmt: NodeId) -> Result<Flow, Halt> {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.node();
+        }
+        match &ast[stmt] {
+            MTree::LET_STMT { id, slot, ty, expr, .. } => {
                 let value = if let Some(e) = expr {
-                    self.eval_expr(e)?
+                    self.eval_expr(ast, *e)?
                 } else {
                     // Default initialization
-                    match ty {
-                        Type::Int => Value::Int(0),
-                        Type::Bool => Value::Bool(false),
-                        Type::Unknown => Value::Int(0),
-                    }
+                    default_value(ty, &self.enums)
                 };
-                self.env.declare(id.clone(), value);
-                Ok(None)
+                self.env.declare(*id, *slot, value)?;
+                Ok(Flow::Normal)
             }
 
-            MTree::ASSIGN { id, expr } => {
-                let value = self.eval_expr(expr)?;
-                self.env.set(id, value)?;
-                Ok(None)
+            MTree::ASSIGN { .. } | MTree::INDEX_ASSIGN { .. } => {
+                self.eval_expr(ast, stmt)?;
+                Ok(Flow::Normal)
             }
 
-            MTree::RTRN_STMT { expr } => {
-                let value = self.eval_expr(expr)?;
-                Ok(Some(value))
+            MTree::RTRN_STMT { expr, .. } => {
+                // a call to a user function is left to the caller to make
+                if let Some(MTree::CALL { name, args, span }) = expr.map(|e| &ast[e])
+                    && self.is_user_function(*name)
+                {
+                    let mut values = Vec::new();
+                    for &arg in args {
+                        values.push(self.eval_expr(ast, arg)?);
+                    }
+                    return Ok(Flow::TailCall(*name, values, *span));
+                }
+                let value = match expr {
+                    Some(e) => self.eval_expr(ast, *e)?,
+                    None => Value::Void,
+                };
+                Ok(Flow::Return(value))
             }
 
-            MTree::IF_STMT { cond, then_block, else_block } => {
-                let cond_val = self.eval_expr(cond)?;
+            MTree::IF_STMT { cond, then_block, else_block, .. } => {
+                let cond_val = self.eval_expr(ast, *cond)?;
                 if cond_val.as_bool()? {
-                    self.execute_block(then_block)
+                    self.execute_block(ast, *then_block)
                 } else if let Some(else_b) = else_block {
-                    self.execute_block(else_b)
+                    self.execute_block(ast, *else_b)
                 } else {
-                    Ok(None)
+                    Ok(Flow::Normal)
                 }
             }
 
-            MTree::WHILE_STMT { cond, body } => {
+            MTree::WHILE_STMT { cond, body, label, .. } => {
                 loop {
-                    let cond_val = self.eval_expr(cond)?;
+                    let cond_val = self.eval_expr(ast, *cond)?;
                     if !cond_val.as_bool()? {
                         break;
                     }
-                    if let Some(ret_val) = self.execute_block(body)? {
-                        return Ok(Some(ret_val));
+                    match loop_control(self.execute_block(ast, *body)?, label) {
+                        Ok(true) => {}
+                        Ok(false) => break,
+                        Err(flow) => return Ok(flow),
+                    }
+                }
+                Ok(Flow::Normal)
+            }
+
+            MTree::LOOP_STMT { body, label, .. } => {
+                loop {
+                    match loop_control(self.execute_block(ast, *body)?, label) {
+                        Ok(true) => {}
+                        Ok(false) => break,
+                        Err(flow) => return Ok(flow),
                     }
                 }
-                Ok(None)
+                Ok(Flow::Normal)
             }
 
-            MTree::PRINT_STMT { expr } => {
-                let value = self.eval_expr(expr)?;
-                match value {
-                    Value::Int(i) => println!("{}", i),
-                    Value::Bool(b) => println!("{}", if b { "true" } else { "false" }),
-                    Value::Void => println!("void"),
+            MTree::FOR_STMT { var, slot, start, end, body, label, .. } => {
+                let start = self.eval_expr(ast, *start)?.as_int()?;
+                let end = self.eval_expr(ast, *end)?.as_int()?;
+                for i in start..end {
+                    self.env.declare(*var, *slot, Value::Int(i))?;
+                    match loop_control(self.execute_block(ast, *body)?, label) {
+                        Ok(true) => {}
+                        Ok(false) => break,
+                        Err(flow) => return Ok(flow),
+                    }
                 }
-                Ok(None)
+                Ok(Flow::Normal)
+            }
+
+            MTree::BREAK_STMT { label, .. } => Ok(Flow::Break(*label)),
+
+            MTree::CONTINUE_STMT { label, .. } => Ok(Flow::Continue(*label)),
+
+            MTree::PRINT_STMT { expr, .. } => {
+                let value = self.eval_expr(ast, *expr)?;
+                println!("{}", value);
+                Ok(Flow::Normal)
             }
 
             MTree::BLOCK { .. } => {
-                self.execute_block(stmt)
+                self.execute_block(ast, stmt)
             }
 
             _ => {
                 // Try to evaluate as expression statement
-                self.eval_expr(stmt)?;
-                Ok(None)
+                self.eval_expr(ast, stmt)?;
+                Ok(Flow::Normal)
             }
         }
     }
 
-    fn eval_expr(&mut self, expr: &MTree) -> Result<Value, String> {
-        match expr {
-            MTree::LIT_INT { value } => Ok(Value::Int(*value)),
-            
-            MTree::LIT_BOOL { value } => Ok(Value::Bool(*value)),
+    fn eval_expr(&mut self, ast: &Ast, expr: NodeId) -> Result<Value, Halt> {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.node();
+        }
+        let result = stack::guarded(|| self.eval_node(ast, expr));
+        if let Err(Halt::Error(_)) = result {
+            self.note_fault(ast, expr);
+        }
+        result
+    }
 
-            MTree::ID { name } => self.env.get(name),
+    fn eval_node(&mut self, ast: &Ast, expr: NodeId) -> Result<Value, Halt> {
+        match &ast[expr] {
+            MTree::LIT_INT { value, .. } => Ok(Value::Int(*value)),
 
-            MTree::CALL { name, args } => {
-                let mut arg_values = Vec::new();
-                for arg in args {
-                    arg_values.push(self.eval_expr(arg)?);
+            MTree::LIT_FLOAT { value, .. } => Ok(Value::Float(*value)),
+
+            MTree::LIT_BOOL { value, .. } => Ok(Value::Bool(*value)),
+
+            MTree::LIT_CHAR { value, .. } => Ok(Value::Char(*value)),
+
+            MTree::LIT_STRING { value, .. } => Ok(Value::Str(value.as_str().into())),
+
+            MTree::LIT_NONE { .. } => Ok(Value::Option(None)),
+
+            MTree::ID { name, slot, .. } => match (slot, self.variants.get(name.as_str())) {
+                (Slot::Unresolved, Some(enum_name)) => Ok(Value::Enum { enum_name: enum_name.clone(), variant: name.to_string() }),
+                _ => Ok(self.env.get(*name, *slot)?),
+            },
+
+            MTree::ARRAY_LIT { elems, .. } => {
+                let mut values = Vec::new();
+                for &e in elems {
+                    values.push(self.eval_expr(ast, e)?);
                 }
-                self.call_function(name, arg_values)
+                Ok(Value::Array(Rc::new(Block::new(values))))
             }
 
-            MTree::EXPR { left, op, right } => {
-                // Handle unary operators
-                if op == "!" {
-                    let r = self.eval_expr(right)?;
-                    return Ok(Value::Bool(!r.as_bool()?));
+            MTree::INDEX { base, index, .. } => {
+                let base_val = self.eval_expr(ast, *base)?;
+                let i = self.eval_expr(ast, *index)?.as_int()?;
+                match base_val {
+                    Value::Array(elems) => {
+                        let slot = checked_index(i, elems.len())?;
+                        Ok(elems[slot].clone())
+                    }
+                    other => Err(format!("Cannot index {:?}", other).into()),
                 }
-                if op == "unary-" {
-                    let r = self.eval_expr(right)?;
-                    return Ok(Value::Int(-r.as_int()?));
+            }
+
+            // only the chosen arm is evaluated
+            MTree::TERNARY { cond, then_expr, else_expr, .. } => {
+                if self.eval_expr(ast, *cond)?.as_bool()? {
+                    self.eval_expr(ast, *then_expr)
+                } else {
+                    self.eval_expr(ast, *else_expr)
                 }
+            }
 
-                // Binary operators
-                let left_val = self.eval_expr(left)?;
-                let right_val = self.eval_expr(right)?;
-
-                match op.as_str() {
-                    "+" => Ok(Value::Int(left_val.as_int()? + right_val.as_int()?)),
-                    "-" => Ok(Value::Int(left_val.as_int()? - right_val.as_int()?)),
-                    "*" => Ok(Value::Int(left_val.as_int()? * right_val.as_int()?)),
-                    "/" => {
-                        let r = right_val.as_int()?;
-                        if r == 0 {
-                            return Err("Division by zero".to_string());
-                        }
-                        Ok(Value::Int(left_val.as_int()? / r))
-                    }
-                    "==" => {
-                        match (left_val, right_val) {
-                            (Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l == r)),
-                            (Value::Bool(l), Value::Bool(r)) => Ok(Value::Bool(l == r)),
-                            _ => Err("Type mismatch in ==".to_string()),
-                        }
-                    }
-                    "!=" => {
-                        match (left_val, right_val) {
-                            (Value::Int(l), Value::Int(r)) => Ok(Value::Bool(l != r)),
-                            (Value::Bool(l), Value::Bool(r)) => Ok(Value::Bool(l != r)),
-                            _ => Err("Type mismatch in !=".to_string()),
-                        }
-                    }
-                    "<" => Ok(Value::Bool(left_val.as_int()? < right_val.as_int()?)),
-                    ">" => Ok(Value::Bool(left_val.as_int()? > right_val.as_int()?)),
-                    "<=" => Ok(Value::Bool(left_val.as_int()? <= right_val.as_int()?)),
-                    ">=" => Ok(Value::Bool(left_val.as_int()? >= right_val.as_int()?)),
-                    "&&" => Ok(Value::Bool(left_val.as_bool()? && right_val.as_bool()?)),
-                    "||" => Ok(Value::Bool(left_val.as_bool()? || right_val.as_bool()?)),
-                    _ => Err(format!("Unknown operator: {}", op)),
+            MTree::CAST { expr, ty, .. } => {
+                let value = self.eval_expr(ast, *expr)?;
+                Ok(cast(value, ty, &self.enums)?)
+            }
+
+            MTree::CALL { name, args, .. } if INTERPRETER_BUILTINS.contains(&name.as_str()) => self.call_builtin(ast, name, args),
+
+            MTree::CALL { name, args, span } => {
+                let mut arg_values = Vec::new();
+                for &arg in args {
+                    arg_values.push(self.eval_expr(ast, arg)?);
                 }
+                // native functions come before user functions
+                if self.host.is_native(*name) {
+                    return Ok(self.host.call_native(*name, &arg_values)?);
+                }
+                self.call_function(ast, *name, arg_values, Some(*span))
+            }
+
+            MTree::UNARY { op, expr, .. } => {
+                let value = self.eval_expr(ast, *expr)?;
+                Ok(eval_unary(*op, value, self.host.overflow)?)
+            }
+
+            MTree::EXPR { left, op, right, .. } => {
+                let left_val = self.eval_expr(ast, *left)?;
+                let right_val = self.eval_expr(ast, *right)?;
+                Ok(eval_binary(*op, left_val, right_val, self.host.overflow)?)
             }
 
-            MTree::ASSIGN { id, expr } => {
-                let value = self.eval_expr(expr)?;
-                self.env.set(id, value.clone())?;
+            MTree::ASSIGN { id, slot, expr, .. } => {
+                let value = self.eval_expr(ast, *expr)?;
+                self.env.set(*id, *slot, value.clone())?;
                 Ok(value)
             }
 
-            _ => Err(format!("Cannot evaluate expression: {:?}", expr)),
+            MTree::INDEX_ASSIGN { id, slot, index, expr, .. } => {
+                let i = self.eval_expr(ast, *index)?.as_int()?;
+                let value = self.eval_expr(ast, *expr)?;
+                match self.env.get_mut(*id, *slot)? {
+                    Value::Array(elems) => {
+                        let position = checked_index(i, elems.len())?;
+                        Rc::make_mut(elems)[position] = value.clone();
+                    }
+                    other => return Err(format!("Cannot index {:?}", other).into()),
+                }
+                Ok(value)
+            }
+
+            _ => Err(format!("Cannot evaluate expression: {:?}", ast[expr]).into()),
+        }
+    }
+}
+
+/// The value of a declared-but-uninitialized variable of type `ty`.
+pub fn default_value(ty: &Type, enums: &HashMap<String, Vec<String>>) -> Value {
+    match ty {
+        Type::Int => Value::Int(0),
+        Type::Float => Value::Float(0.0),
+        Type::Bool => Value::Bool(false),
+        Type::Char => Value::Char('\0'),
+        Type::Str => Value::Str(Rc::from("")),
+        Type::Array(elem, size) => Value::Array(Rc::new(Block::new(vec![default_value(elem, enums); *size]))),
+        Type::Vec(_) => Value::Array(Rc::default()),
+        Type::Map(..) => Value::Map(Rc::default()),
+        Type::Option(_) => Value::Option(None),
+        Type::Enum(name) => match enums.get(name).and_then(|v| v.first()) {
+            Some(variant) => Value::Enum { enum_name: name.clone(), variant: variant.clone() },
+            None => Value::Void,
+        },
+        Type::Unknown => Value::Int(0),
+    }
+}
+
+/// Whether `value` is one of type `ty`. `enums` gives each enum's variants.
+pub fn fits(value: &Value, ty: &Type, enums: &HashMap<String, Vec<String>>) -> bool {
+    match (value, ty) {
+        (_, Type::Unknown) => true,
+        (Value::Int(_), Type::Int) | (Value::Float(_), Type::Float) => true,
+        (Value::Bool(_), Type::Bool) | (Value::Char(_), Type::Char) | (Value::Str(_), Type::Str) => true,
+        (Value::Array(items), Type::Array(elem, size)) => items.len() == *size && items.iter().all(|item| fits(item, elem, enums)),
+        (Value::Array(items), Type::Vec(elem)) => items.iter().all(|item| fits(item, elem, enums)),
+        (Value::Map(entries), Type::Map(key, value)) => entries
+            .iter()
+            .all(|(k, v)| fits(&Value::from(k.clone()), key, enums) && fits(v, value, enums)),
+        (Value::Option(inner), Type::Option(elem)) => inner.as_deref().is_none_or(|inner| fits(inner, elem, enums)),
+        (Value::Enum { enum_name, variant }, Type::Enum(name)) => {
+            enum_name == name && enums.get(name).is_some_and(|variants| variants.contains(variant))
+        }
+        _ => false,
+    }
+}
+
+/// `value` as type `ty`; float to int truncates toward zero, saturating
+/// like Rust's `as`. `enums` gives each enum's variants, in order.
+pub fn cast(value: Value, ty: &Type, enums: &HashMap<String, Vec<String>>) -> Result<Value, String> {
+    match (value, ty) {
+        (Value::Int(i), Type::Float) => Ok(Value::Float(i as f32)),
+        (Value::Float(f), Type::Int) => Ok(Value::Int(f as i32)),
+        (Value::Char(c), Type::Int) => Ok(Value::Int(c as i32)),
+        (Value::Int(i), Type::Char) => u32::try_from(i)
+            .ok()
+            .and_then(char::from_u32)
+            .map(Value::Char)
+            .ok_or_else(|| format!("{} is not a valid char", i)),
+        (Value::Bool(b), Type::Int) => Ok(Value::Int(b as i32)),
+        (Value::Enum { enum_name, variant }, Type::Int) => enums
+            .get(&enum_name)
+            .and_then(|variants| variants.iter().position(|v| *v == variant))
+            .map(|i| Value::Int(i as i32))
+            .ok_or_else(|| format!("Unknown variant {}", variant)),
+        // anything else is a cast to the value's own type, which
+        // semantic analysis has already checked
+        (value, _) => Ok(value),
+    }
+}
+
+// what a loop labelled `label` does with the flow out of its body:
+// Ok(true) keeps looping, Ok(false) stops, Err(flow) propagates outward
+fn loop_control(flow: Flow, label: &Option<Symbol>) -> Result<bool, Flow> {
+    match flow {
+        Flow::Normal => Ok(true),
+        Flow::Continue(target) if target.is_none() || target == *label => Ok(true),
+        Flow::Break(target) if target.is_none() || target == *label => Ok(false),
+        flow => Err(flow),
+    }
+}
+
+/// The value of `op` applied to `value`.
+pub fn eval_unary(op: UnOp, value: Value, overflow: Overflow) -> Result<Value, String> {
+    match (op, value) {
+        (UnOp::Not, value) => Ok(Value::Bool(!value.as_bool()?)),
+        (UnOp::Neg, Value::Float(f)) => Ok(Value::Float(-f)),
+        (UnOp::Neg, value) => {
+            let i = value.as_int()?;
+            let negated = overflow.pick(i.checked_neg(), i.wrapping_neg(), i.saturating_neg());
+            Ok(Value::Int(negated.ok_or_else(|| format!("Integer overflow in -({})", i))?))
+        }
+    }
+}
+
+/// The value of `op` applied to `left` and `right`, both already evaluated;
+/// `&&` and `||` evaluate both sides.
+pub fn eval_binary(op: BinOp, left: Value, right: Value, overflow: Overflow) -> Result<Value, String> {
+    match op {
+        BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge => eval_compare(op, left, right),
+        BinOp::And => Ok(Value::Bool(left.as_bool()? && right.as_bool()?)),
+        BinOp::Or => Ok(Value::Bool(left.as_bool()? || right.as_bool()?)),
+        _ => eval_arith(op, left, right, overflow),
+    }
+}
+
+// dividing by zero is an error whatever `overflow` says; only a result
+// too large for an i32 is up to it
+fn eval_arith(op: BinOp, left: Value, right: Value, overflow: Overflow) -> Result<Value, String> {
+    match (left, right) {
+        (Value::Int(l), Value::Int(r)) => {
+            let result = match op {
+                BinOp::Add => overflow.pick(l.checked_add(r), l.wrapping_add(r), l.saturating_add(r)),
+                BinOp::Sub => overflow.pick(l.checked_sub(r), l.wrapping_sub(r), l.saturating_sub(r)),
+                BinOp::Mul => overflow.pick(l.checked_mul(r), l.wrapping_mul(r), l.saturating_mul(r)),
+                BinOp::Div if r == 0 => return Err("Division by zero".to_string()),
+                BinOp::Div => overflow.pick(l.checked_div(r), l.wrapping_div(r), l.saturating_div(r)),
+                BinOp::Rem if r == 0 => return Err("Modulo by zero".to_string()),
+                // i32::MIN % -1 overflows on the way, but the remainder is 0
+                BinOp::Rem => overflow.pick(l.checked_rem(r), l.wrapping_rem(r), l.wrapping_rem(r)),
+                BinOp::Pow => {
+                    let exp = u32::try_from(r).map_err(|_| format!("Negative exponent {}", r))?;
+                    overflow.pick(l.checked_pow(exp), l.wrapping_pow(exp), l.saturating_pow(exp))
+                }
+                _ => return eval_bits(op, l, r),
+            };
+            result.map(Value::Int).ok_or_else(|| format!("Integer overflow in {} {} {}", l, op, r))
+        }
+        (Value::Str(l), Value::Str(r)) if op == BinOp::Add => Ok(Value::Str([&*l, &*r].concat().into())),
+        (Value::Float(l), Value::Float(r)) => match op {
+            BinOp::Add => Ok(Value::Float(l + r)),
+            BinOp::Sub => Ok(Value::Float(l - r)),
+            BinOp::Mul => Ok(Value::Float(l * r)),
+            BinOp::Div => Ok(Value::Float(l / r)),
+            BinOp::Pow => Ok(Value::Float(l.powf(r))),
+            _ => Err(format!("Unknown operator: {}", op)),
+        },
+        (l, r) => Err(format!("Type mismatch in {}: {:?} and {:?}", op, l, r)),
+    }
+}
+
+// the bitwise and shift operators, which cannot overflow
+fn eval_bits(op: BinOp, l: i32, r: i32) -> Result<Value, String> {
+    match op {
+        BinOp::BitAnd => Ok(Value::Int(l & r)),
+        BinOp::BitOr => Ok(Value::Int(l | r)),
+        BinOp::BitXor => Ok(Value::Int(l ^ r)),
+        BinOp::Shl | BinOp::Shr => {
+            if !(0..32).contains(&r) {
+                return Err(format!("Shift amount {} out of range", r));
+            }
+            Ok(Value::Int(if op == BinOp::Shl { l << r } else { l >> r }))
+        }
+        _ => Err(format!("Unknown operator: {}", op)),
+    }
+}
+
+fn eval_compare(op: BinOp, left: Value, right: Value) -> Result<Value, String> {
+    let ordering = match (&left, &right) {
+        (Value::Int(l), Value::Int(r)) => l.partial_cmp(r),
+        (Value::Float(l), Value::Float(r)) => l.partial_cmp(r),
+        (Value::Char(l), Value::Char(r)) => l.partial_cmp(r),
+        (Value::Enum { .. }, Value::Enum { .. })
+        | (Value::Array(_), Value::Array(_))
+        | (Value::Map(_), Value::Map(_))
+        | (Value::Option(_), Value::Option(_)) if matches!(op, BinOp::Eq | BinOp::Ne) => {
+            if left == right { Some(Ordering::Equal) } else { None }
+        }
+        (Value::Bool(l), Value::Bool(r)) if matches!(op, BinOp::Eq | BinOp::Ne) => l.partial_cmp(r),
+        (Value::Str(l), Value::Str(r)) => l.partial_cmp(r),
+        _ => return Err(format!("Type mismatch in {}: {:?} and {:?}", op, left, right)),
+    };
+
+    // NaN compares unordered: only != holds
+    let result = match op {
+        BinOp::Eq => ordering == Some(Ordering::Equal),
+        BinOp::Ne => ordering != Some(Ordering::Equal),
+        BinOp::Lt => ordering == Some(Ordering::Less),
+        BinOp::Gt => ordering == Some(Ordering::Greater),
+        BinOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        BinOp::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        _ => return Err(format!("Unknown operator: {}", op)),
+    };
+    Ok(Value::Bool(result))
+}
+
+pub fn checked_index(index: i32, len: usize) -> Result<usize, String> {
+    if index < 0 || index as usize >= len {
+        Err(format!("Index {} out of bounds for array of length {}", index, len))
+    } else {
+        Ok(index as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [Overflow; 3] = [Overflow::Wrap, Overflow::Saturate, Overflow::Checked];
+
+    fn int(i: i32) -> Value {
+        Value::Int(i)
+    }
+
+    // `l op r` under each mode, in the order of MODES
+    fn binary(op: BinOp, l: i32, r: i32) -> Vec<Result<Value, String>> {
+        MODES.iter().map(|&mode| eval_binary(op, int(l), int(r), mode)).collect()
+    }
+
+    fn negate(i: i32) -> Vec<Result<Value, String>> {
+        MODES.iter().map(|&mode| eval_unary(UnOp::Neg, int(i), mode)).collect()
+    }
+
+    fn abs(i: i32) -> Vec<Result<Value, String>> {
+        MODES
+            .iter()
+            .map(|&mode| match Host::new().with_overflow(mode).call_builtin("abs", &[int(i)]) {
+                Ok(value) => Ok(value),
+                Err(Halt::Error(message)) => Err(message),
+                Err(Halt::Exit(status)) => panic!("abs exited with {}", status),
+            })
+            .collect()
+    }
+
+    // wraps to `wrapped`, saturates to `saturated` and is an overflow error
+    // when checked
+    fn assert_overflows(results: Vec<Result<Value, String>>, wrapped: i32, saturated: i32) {
+        assert_eq!(results[0], Ok(int(wrapped)));
+        assert_eq!(results[1], Ok(int(saturated)));
+        assert!(matches!(&results[2], Err(message) if message.starts_with("Integer overflow")), "{:?}", results[2]);
+    }
+
+    #[test]
+    fn min_divided_by_minus_one_overflows() {
+        assert_overflows(binary(BinOp::Div, i32::MIN, -1), i32::MIN, i32::MAX);
+    }
+
+    #[test]
+    fn min_remainder_minus_one_is_zero_unless_checked() {
+        assert_overflows(binary(BinOp::Rem, i32::MIN, -1), 0, 0);
+    }
+
+    #[test]
+    fn negating_min_overflows() {
+        assert_overflows(negate(i32::MIN), i32::MIN, i32::MAX);
+        assert_eq!(negate(i32::MAX), vec![Ok(int(-i32::MAX)); 3]);
+    }
+
+    #[test]
+    fn power_overflows() {
+        assert_overflows(binary(BinOp::Pow, 2, 31), i32::MIN, i32::MAX);
+        assert_overflows(binary(BinOp::Pow, 2, 32), 0, i32::MAX);
+        assert_overflows(binary(BinOp::Pow, -3, 21), (-3i32).wrapping_pow(21), i32::MIN);
+        assert_eq!(binary(BinOp::Pow, -2, 31), vec![Ok(int(i32::MIN)); 3]);
+    }
+
+    #[test]
+    fn abs_of_min_overflows() {
+        assert_overflows(abs(i32::MIN), i32::MIN, i32::MAX);
+        assert_eq!(abs(-i32::MAX), vec![Ok(int(i32::MAX)); 3]);
+    }
+
+    #[test]
+    fn sums_and_products_overflow() {
+        assert_overflows(binary(BinOp::Add, i32::MAX, 1), i32::MIN, i32::MAX);
+        assert_overflows(binary(BinOp::Sub, i32::MIN, 1), i32::MAX, i32::MIN);
+        assert_overflows(binary(BinOp::Mul, 65536, 65536), 0, i32::MAX);
+    }
+
+    #[test]
+    fn dividing_by_zero_fails_in_every_mode() {
+        for result in binary(BinOp::Div, 1, 0) {
+            assert_eq!(result, Err("Division by zero".to_string()));
+        }
+        for result in binary(BinOp::Rem, i32::MIN, 0) {
+            assert_eq!(result, Err("Modulo by zero".to_string()));
         }
     }
-}
\ No newline at end of file
+}
@@ -1,4 +1,8 @@
-use crate::token::Token;
+use crate::diagnostics::{self, Diagnostic};
+use crate::intern::Symbol;
+use crate::token::{Leading, Span, SpannedToken, Token};
+use std::collections::VecDeque;
+use std::io::Read;
 
 pub enum LexerState {
     Start,
@@ -10,63 +14,282 @@ pub enum LexerState {
     Numbers,
     NumPoint,
     Decimals,
+    Exponent,
+    ExponentSign,
+    ExponentDigits,
+    Point,
 
     Not,
     And,
     Or,
 
     Dash,
+    Star,
     Slash,
     Comment,
+    Whitespace,
+    BlockComment,
+    BlockCommentStar,
+    BlockCommentSlash,
 
     Equal,
     Greater,
     Less,
 }
 
+// how many bytes a streaming lexer reads at a time
+const CHUNK_SIZE: usize = 8192;
+
 pub struct Lexer {
-    input_string: String,
+    // the source as chars, so `position` indexes it in constant time;
+    // when streaming this is a window starting at char `offset`
+    input: Vec<char>,
+    offset: usize,
+    // streaming source and any undecoded trailing bytes of its last chunk
+    source: Option<Box<dyn Read>>,
+    pending: Vec<u8>,
     position: usize,
     state: LexerState,
     current_token: Token,
     buffer_string: String,
+    // nesting level of /* */ comments
+    comment_depth: usize,
+    // first bad escape in the literal being read, reported at its end
+    literal_error: Option<(String, Span)>,
+    // char offsets where each line begins, for turning positions into spans
+    line_starts: Vec<usize>,
+    // position of the first char of the token being scanned
+    token_start: usize,
+    // the token the parser is looking at; `current_token` is only the
+    // latest one scanned, which may be further ahead
+    current: Token,
+    current_span: Span,
+    current_leading: Leading,
+    // tokens scanned past `current` by peek_nth
+    lookahead: VecDeque<(Token, Span, Leading)>,
+    // emit WHITESPACE and COMMENT tokens instead of skipping them
+    trivia: bool,
+    // fold trivia into the Leading of the token after it
+    layout: bool,
 }
 
 impl Lexer {
     pub fn new(input: String) -> Self {
+        let input: Vec<char> = input.chars().collect();
+        let line_starts = Self::line_starts(&input);
         Lexer {
-            input_string: input,
+            input,
+            offset: 0,
+            source: None,
+            pending: Vec::new(),
             position: 0,
             state: LexerState::Start,
             current_token: Token::EOI,
             buffer_string: String::new(),
+            comment_depth: 0,
+            literal_error: None,
+            line_starts,
+            token_start: 0,
+            current: Token::EOI,
+            current_span: Span::default(),
+            current_leading: Leading::default(),
+            lookahead: VecDeque::new(),
+            trivia: false,
+            layout: false,
         }
     }
 
+    // lex from a reader in chunks instead of holding the whole source
+    pub fn from_reader(reader: impl Read + 'static) -> Self {
+        let mut lexer = Lexer::new(String::new());
+        lexer.source = Some(Box::new(reader));
+        lexer
+    }
+
+    // keep whitespace and comments as tokens, for tools such as a formatter
+    // that must reproduce the source; the parser does not accept them
+    pub fn with_trivia(mut self) -> Self {
+        self.trivia = true;
+        self
+    }
+
+    // record the comments and blank lines before each token instead of
+    // discarding them; the tokens returned are the same as without
+    pub fn with_layout(mut self) -> Self {
+        self.trivia = true;
+        self.layout = true;
+        self
+    }
+
+    #[allow(dead_code)]
     pub fn set_input(&mut self, input: String) {
-        self.input_string = input;
-        self.position = 0;
-        self.state = LexerState::Start;
-        self.current_token = Token::EOI;
-        self.buffer_string = String::new();
+        *self = Lexer::new(input);
+    }
+
+    // the char at `position`, reading more of a streaming source as needed
+    fn char_at(&mut self, position: usize) -> Option<char> {
+        while position >= self.offset + self.input.len() && self.source.is_some() {
+            self.read_chunk();
+        }
+        self.input.get(position - self.offset).copied()
+    }
+
+    fn read_chunk(&mut self) {
+        let Some(source) = self.source.as_mut() else { return };
+        let mut chunk = [0; CHUNK_SIZE];
+        let read = match source.read(&mut chunk) {
+            Ok(0) | Err(_) => {
+                // end of input: whatever is still undecoded is malformed
+                self.source = None;
+                let rest = String::from_utf8_lossy(&self.pending).into_owned();
+                self.pending.clear();
+                self.append(&rest);
+                return;
+            }
+            Ok(read) => read,
+        };
+        self.pending.extend_from_slice(&chunk[..read]);
+        // decode up to any multi-byte char cut off at the end of the chunk
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        let text = String::from_utf8_lossy(&self.pending[..valid]).into_owned();
+        self.pending.drain(..valid);
+        self.append(&text);
+    }
+
+    fn append(&mut self, text: &str) {
+        for c in text.chars() {
+            self.input.push(c);
+            if c == '\n' {
+                self.line_starts.push(self.offset + self.input.len());
+            }
+        }
+    }
+
+    // drop streamed chars before the current token, which is never
+    // backtracked past
+    fn compact(&mut self) {
+        if self.token_start - self.offset > CHUNK_SIZE {
+            self.input.drain(..self.token_start - self.offset);
+            self.offset = self.token_start;
+        }
     }
 
     pub fn advance(&mut self) -> Token {
+        let (token, span, leading) = match self.lookahead.pop_front() {
+            Some(next) => next,
+            None => self.next_token(),
+        };
+        self.current = token;
+        self.current_span = span;
+        self.current_leading = leading;
+        self.curr()
+    }
+
+    // the token k places past the current one, without consuming anything;
+    // peek_nth(0) is the current token
+    pub fn peek_nth(&mut self, k: usize) -> Token {
+        if k == 0 {
+            return self.curr();
+        }
+        while self.lookahead.len() < k {
+            let next = self.next_token();
+            self.lookahead.push_back(next);
+        }
+        self.lookahead[k - 1].0.clone()
+    }
+
+    // the next token, with any trivia before it folded into its Leading
+    // when layout is kept
+    fn next_token(&mut self) -> (Token, Span, Leading) {
+        let mut leading = Leading::default();
+        loop {
+            let (token, span) = self.scan();
+            if !self.layout {
+                return (token, span, leading);
+            }
+            match token {
+                Token::WHITESPACE { text } => {
+                    let newlines = text.matches('\n').count();
+                    leading.blank_lines += newlines.saturating_sub(1);
+                }
+                Token::COMMENT { text } => leading.comments.push(text),
+                token => return (token, span, leading),
+            }
+        }
+    }
+
+    fn scan(&mut self) -> (Token, Span) {
         loop {
-            if self.position == self.input_string.len() {
+            if matches!(self.state, LexerState::Start) {
+                self.token_start = self.position;
+                self.compact();
+            }
+            let Some(current_char) = self.char_at(self.position) else {
                 match self.state {
                     LexerState::Greater => self.current_token = Token::GT,
                     LexerState::Less => self.current_token = Token::LT,
                     LexerState::Equal => self.current_token = Token::ASSIGN,
                     LexerState::Not => self.current_token = Token::NOT,
                     LexerState::Dash => self.current_token = Token::SUB,
+                    LexerState::Star => self.current_token = Token::MUL,
+                    LexerState::Point => self.current_token = Token::POINT,
                     LexerState::Slash => self.current_token = Token::DIV,
-                    LexerState::And => self.current_token = Token::AND,
-                    LexerState::Or => self.current_token = Token::OR,
+                    LexerState::And => self.current_token = Token::BIT_AND,
+                    LexerState::Or => self.current_token = Token::BIT_OR,
+                    LexerState::Whitespace => {
+                        let text = std::mem::take(&mut self.buffer_string);
+                        self.current_token = Token::WHITESPACE { text };
+                        self.state = LexerState::Start;
+                        break;
+                    }
+                    LexerState::Comment if self.trivia => {
+                        let text = std::mem::take(&mut self.buffer_string);
+                        self.current_token = Token::COMMENT { text };
+                        self.state = LexerState::Start;
+                        break;
+                    }
+                    LexerState::BlockComment | LexerState::BlockCommentStar | LexerState::BlockCommentSlash => {
+                        self.buffer_string = String::new();
+                        self.current_token = self.error("unterminated block comment");
+                        self.state = LexerState::End;
+                        break;
+                    }
+                    LexerState::ReadString => {
+                        self.current_token = self.error("unterminated string literal");
+                        self.buffer_string = String::new();
+                        self.state = LexerState::End;
+                        break;
+                    }
+                    LexerState::ReadChar if self.buffer_is_label() => {
+                        let name = Symbol::intern(&self.buffer_string);
+                        self.buffer_string.clear();
+                        self.state = LexerState::Start;
+                        self.current_token = Token::LABEL { name };
+                        break;
+                    }
+                    // a dangling `e` or `e-` is not part of the number
+                    LexerState::Exponent | LexerState::ExponentSign => {
+                        let dangling = if matches!(self.state, LexerState::Exponent) { 1 } else { 2 };
+                        self.buffer_string.truncate(self.buffer_string.len() - dangling);
+                        self.state = LexerState::Start;
+                        self.current_token = self.number_token();
+                        self.buffer_string = String::new();
+                        self.position -= dangling;
+                        break;
+                    }
+                    LexerState::ExponentDigits => {
+                        self.state = LexerState::Start;
+                        self.current_token = self.number_token();
+                        self.buffer_string = String::new();
+                        break;
+                    }
                     LexerState::NumPoint => {
-                        let value: i32 = self.buffer_string.parse().unwrap();
                         self.state = LexerState::Start;
-                        self.current_token = Token::LIT_INT32 { value };
+                        self.current_token = self.number_token();
                         self.buffer_string = String::new();
                         self.position -= 1;
                         break;
@@ -82,11 +305,18 @@ impl Lexer {
                 }
                 self.state = LexerState::End;
                 break;
-            }
-
-            let current_char = self.input_string.chars().nth(self.position).unwrap();
+            };
             self.position += 1;
 
+            if self.trivia
+                && matches!(
+                    self.state,
+                    LexerState::BlockComment | LexerState::BlockCommentStar | LexerState::BlockCommentSlash
+                )
+            {
+                self.buffer_string.push(current_char);
+            }
+
             match self.state {
                 LexerState::Start => match current_char {
                     'A'..='Z' | 'a'..='z' | '_' => {
@@ -137,8 +367,7 @@ impl Lexer {
                         self.state = LexerState::Or;
                     }
                     '.' => {
-                        self.current_token = Token::POINT;
-                        break;
+                        self.state = LexerState::Point;
                     }
                     ',' => {
                         self.current_token = Token::COMMA;
@@ -148,6 +377,10 @@ impl Lexer {
                         self.current_token = Token::COLON;
                         break;
                     }
+                    '?' => {
+                        self.current_token = Token::QUESTION;
+                        break;
+                    }
                     ';' => {
                         self.current_token = Token::SEMICOLON;
                         break;
@@ -160,7 +393,14 @@ impl Lexer {
                         self.state = LexerState::Dash;
                     }
                     '*' => {
-                        self.current_token = Token::MUL;
+                        self.state = LexerState::Star;
+                    }
+                    '%' => {
+                        self.current_token = Token::MOD;
+                        break;
+                    }
+                    '^' => {
+                        self.current_token = Token::BIT_XOR;
                         break;
                     }
                     '/' => {
@@ -175,8 +415,16 @@ impl Lexer {
                     '>' => {
                         self.state = LexerState::Greater;
                     }
+                    ' ' | '\t' | '\n' | '\r' if self.trivia => {
+                        self.state = LexerState::Whitespace;
+                        self.buffer_string.push(current_char);
+                    }
+                    ' ' | '\t' | '\n' | '\r' => {}
 
-                    _ => {}
+                    c => {
+                        self.current_token = self.error(&format!("unexpected character '{}'", c));
+                        break;
+                    }
                 },
 
                 LexerState::Chars => match current_char {
@@ -202,10 +450,14 @@ impl Lexer {
                         self.state = LexerState::NumPoint;
                     }
 
+                    'e' | 'E' => {
+                        self.state = LexerState::Exponent;
+                        self.buffer_string.push(current_char);
+                    }
+
                     _ => {
                         self.state = LexerState::Start;
-                        let value: i32 = self.buffer_string.parse().unwrap();
-                        self.current_token = Token::LIT_INT32 { value };
+                        self.current_token = self.number_token();
                         self.buffer_string = String::new();
 
                         self.position -= 1;
@@ -221,8 +473,7 @@ impl Lexer {
 
                     _ => {
                         self.state = LexerState::Start;
-                        let value: i32 = self.buffer_string.parse().unwrap();
-                        self.current_token = Token::LIT_INT32 { value };
+                        self.current_token = self.number_token();
                         self.buffer_string = String::new();
 
                         self.position -= 2;
@@ -234,6 +485,11 @@ impl Lexer {
                         self.buffer_string.push(current_char);
                     }
 
+                    'e' | 'E' => {
+                        self.state = LexerState::Exponent;
+                        self.buffer_string.push(current_char);
+                    }
+
                     _ => {
                         self.state = LexerState::Start;
                         let value: f32 = self.buffer_string.parse().unwrap();
@@ -244,16 +500,76 @@ impl Lexer {
                         break;
                     }
                 },
+                // scientific notation: 1e5, 2.5e-3
+                LexerState::Exponent | LexerState::ExponentSign => match current_char {
+                    '0'..='9' => {
+                        self.state = LexerState::ExponentDigits;
+                        self.buffer_string.push(current_char);
+                    }
+                    '+' | '-' if matches!(self.state, LexerState::Exponent) => {
+                        self.state = LexerState::ExponentSign;
+                        self.buffer_string.push(current_char);
+                    }
+                    _ => {
+                        // no exponent digits: back up to before the `e`
+                        let dangling = if matches!(self.state, LexerState::Exponent) { 1 } else { 2 };
+                        self.buffer_string.truncate(self.buffer_string.len() - dangling);
+                        self.state = LexerState::Start;
+                        self.current_token = self.number_token();
+                        self.buffer_string = String::new();
+
+                        self.position -= dangling + 1;
+                        break;
+                    }
+                },
+                LexerState::ExponentDigits => match current_char {
+                    '0'..='9' => {
+                        self.buffer_string.push(current_char);
+                    }
+
+                    _ => {
+                        self.state = LexerState::Start;
+                        self.current_token = self.number_token();
+                        self.buffer_string = String::new();
+
+                        self.position -= 1;
+                        break;
+                    }
+                },
                 LexerState::ReadChar => match current_char {
                     '\'' => {
                         self.state = LexerState::Start;
-                        if self.buffer_string.len() == 1 {
-                            let value = self.buffer_string.chars().nth(0).unwrap();
-                            self.current_token = Token::LIT_CHAR { value };
-                            self.buffer_string = String::new();
-                            break;
-                        }
+                        let mut chars = self.buffer_string.chars();
+                        let token = match (chars.next(), chars.next()) {
+                            (Some(value), None) => Token::LIT_CHAR { value },
+                            (None, _) => self.error("empty char literal"),
+                            _ => self.error(&format!("char literal '{}' must contain exactly one character", self.buffer_string)),
+                        };
+                        self.current_token = self.literal_token(token);
                         self.buffer_string = String::new();
+                        break;
+                    }
+                    '\\' => {
+                        let escape_start = self.position - 1;
+                        match self.read_escape() {
+                            Ok(escaped) => self.buffer_string.push(escaped),
+                            Err(message) => {
+                                let error = (message, self.span_at(escape_start));
+                                self.literal_error.get_or_insert(error);
+                            }
+                        }
+                    }
+                    // loop label such as 'outer: ends at the first non-identifier char
+                    'A'..='Z' | 'a'..='z' | '_' | '0'..='9' => {
+                        self.buffer_string.push(current_char);
+                    }
+                    _ if self.buffer_is_label() => {
+                        let name = Symbol::intern(&self.buffer_string);
+                        self.buffer_string.clear();
+                        self.state = LexerState::Start;
+                        self.current_token = Token::LABEL { name };
+                        self.position -= 1;
+                        break;
                     }
                     _ => {
                         self.buffer_string.push(current_char);
@@ -262,11 +578,20 @@ impl Lexer {
                 LexerState::ReadString => match current_char {
                     '"' => {
                         self.state = LexerState::Start;
-                        let value = self.buffer_string.clone();
-                        self.current_token = Token::LIT_STRING { value };
-                        self.buffer_string = String::new();
+                        let value = std::mem::take(&mut self.buffer_string);
+                        self.current_token = self.literal_token(Token::LIT_STRING { value });
                         break;
                     }
+                    '\\' => {
+                        let escape_start = self.position - 1;
+                        match self.read_escape() {
+                            Ok(escaped) => self.buffer_string.push(escaped),
+                            Err(message) => {
+                                let error = (message, self.span_at(escape_start));
+                                self.literal_error.get_or_insert(error);
+                            }
+                        }
+                    }
                     _ => {
                         self.buffer_string.push(current_char);
                     }
@@ -293,7 +618,9 @@ impl Lexer {
                     }
                     _ => {
                         self.state = LexerState::Start;
+                        self.current_token = Token::BIT_AND;
                         self.position -= 1;
+                        break;
                     }
                 },
                 LexerState::Or => match current_char {
@@ -304,7 +631,35 @@ impl Lexer {
                     }
                     _ => {
                         self.state = LexerState::Start;
+                        self.current_token = Token::BIT_OR;
+                        self.position -= 1;
+                        break;
+                    }
+                },
+                LexerState::Star => match current_char {
+                    '*' => {
+                        self.state = LexerState::Start;
+                        self.current_token = Token::POW;
+                        break;
+                    }
+                    _ => {
+                        self.state = LexerState::Start;
+                        self.current_token = Token::MUL;
+                        self.position -= 1;
+                        break;
+                    }
+                },
+                LexerState::Point => match current_char {
+                    '.' => {
+                        self.state = LexerState::Start;
+                        self.current_token = Token::RANGE;
+                        break;
+                    }
+                    _ => {
+                        self.state = LexerState::Start;
+                        self.current_token = Token::POINT;
                         self.position -= 1;
+                        break;
                     }
                 },
                 LexerState::Dash => match current_char {
@@ -339,6 +694,11 @@ impl Lexer {
                         self.current_token = Token::NLT;
                         break;
                     }
+                    '>' => {
+                        self.state = LexerState::Start;
+                        self.current_token = Token::SHR;
+                        break;
+                    }
                     _ => {
                         self.state = LexerState::Start;
                         self.current_token = Token::GT;
@@ -352,6 +712,11 @@ impl Lexer {
                         self.current_token = Token::NGT;
                         break;
                     }
+                    '<' => {
+                        self.state = LexerState::Start;
+                        self.current_token = Token::SHL;
+                        break;
+                    }
                     _ => {
                         self.state = LexerState::Start;
                         self.current_token = Token::LT;
@@ -363,6 +728,16 @@ impl Lexer {
                     '/' => {
                         // Comments - skip until end of line
                         self.state = LexerState::Comment;
+                        if self.trivia {
+                            self.buffer_string.push_str("//");
+                        }
+                    }
+                    '*' => {
+                        self.state = LexerState::BlockComment;
+                        self.comment_depth = 1;
+                        if self.trivia {
+                            self.buffer_string.push_str("/*");
+                        }
                     }
 
                     _ => {
@@ -373,35 +748,190 @@ impl Lexer {
                     }
                 },
                 LexerState::Comment => match current_char {
+                    '\n' | '\r' if self.trivia => {
+                        // the line break is whitespace, not part of the comment
+                        self.state = LexerState::Start;
+                        let text = std::mem::take(&mut self.buffer_string);
+                        self.current_token = Token::COMMENT { text };
+                        self.position -= 1;
+                        break;
+                    }
                     '\n' | '\r' => {
                         // End of comment, return to start
                         self.state = LexerState::Start;
                     }
+                    _ if self.trivia => self.buffer_string.push(current_char),
                     _ => {
                         // Continue skipping comment characters
                     }
                 },
+                LexerState::Whitespace => match current_char {
+                    ' ' | '\t' | '\n' | '\r' => self.buffer_string.push(current_char),
+                    _ => {
+                        self.state = LexerState::Start;
+                        let text = std::mem::take(&mut self.buffer_string);
+                        self.current_token = Token::WHITESPACE { text };
+                        self.position -= 1;
+                        break;
+                    }
+                },
+                // block comments nest: /* a /* b */ c */
+                LexerState::BlockComment => match current_char {
+                    '*' => self.state = LexerState::BlockCommentStar,
+                    '/' => self.state = LexerState::BlockCommentSlash,
+                    _ => {}
+                },
+                LexerState::BlockCommentStar => match current_char {
+                    '/' => {
+                        self.comment_depth -= 1;
+                        if self.comment_depth == 0 {
+                            self.state = LexerState::Start;
+                            if self.trivia {
+                                let text = std::mem::take(&mut self.buffer_string);
+                                self.current_token = Token::COMMENT { text };
+                                break;
+                            }
+                        } else {
+                            self.state = LexerState::BlockComment;
+                        }
+                    }
+                    '*' => {}
+                    _ => self.state = LexerState::BlockComment,
+                },
+                LexerState::BlockCommentSlash => match current_char {
+                    '*' => {
+                        self.comment_depth += 1;
+                        self.state = LexerState::BlockComment;
+                    }
+                    '/' => {}
+                    _ => self.state = LexerState::BlockComment,
+                },
 
                 _ => {}
             }
         }
-        self.curr()
+        (self.current_token.clone(), self.span_at(self.token_start))
     }
 
     pub fn curr(&self) -> Token {
-        self.current_token.clone()
+        self.current.clone()
+    }
+
+    // where the current token starts
+    pub fn span(&self) -> Span {
+        self.current_span
+    }
+
+    // the comments and blank lines before the current token; always empty
+    // unless built with_layout
+    pub fn leading(&self) -> Leading {
+        self.current_leading.clone()
+    }
+
+    fn span_at(&self, position: usize) -> Span {
+        let line = self.line_starts.partition_point(|&start| start <= position);
+        Span { line, col: position - self.line_starts[line - 1] + 1 }
+    }
+
+    fn line_starts(input: &[char]) -> Vec<usize> {
+        let mut starts = vec![0];
+        starts.extend(input.iter().enumerate().filter(|&(_, &c)| c == '\n').map(|(i, _)| i + 1));
+        starts
+    }
+
+    // consume the first `>` of a `>>`, as in option<option<i32>>
+    pub fn split_shr(&mut self) {
+        if self.current == Token::SHR {
+            self.current = Token::GT;
+            self.current_span.col += 1;
+        }
     }
 
     pub fn print_tokens(&mut self) {
-        println!("");
-        loop {
-            self.advance();
-            if let Token::EOI = self.curr() {
-                break;
+        println!();
+        for token in self.by_ref() {
+            print!("{:?}, ", token);
+        }
+        print!("{:?}", Token::EOI);
+    }
+
+    // the character denoted by an escape sequence, read just after its `\`
+    fn read_escape(&mut self) -> Result<char, String> {
+        let escape = self.char_at(self.position);
+        self.position += 1;
+        match escape {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('0') => Ok('\0'),
+            Some('\\') => Ok('\\'),
+            Some('"') => Ok('"'),
+            Some('\'') => Ok('\''),
+            Some('u') => {
+                let expected = "invalid unicode escape: expected \\u{...}";
+                if self.char_at(self.position) != Some('{') {
+                    return Err(expected.to_string());
+                }
+                let mut digits = String::new();
+                let mut end = self.position + 1;
+                loop {
+                    match self.char_at(end) {
+                        Some('}') => break,
+                        Some(c) if digits.len() < 6 => digits.push(c),
+                        _ => return Err(expected.to_string()),
+                    }
+                    end += 1;
+                }
+                self.position = end + 1;
+                u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("invalid unicode escape '\\u{{{digits}}}'"))
+            }
+            Some(c) => Err(format!("invalid escape sequence '\\{c}'")),
+            None => {
+                self.position -= 1;
+                Err("unterminated escape sequence".to_string())
             }
-            print!("{:?}, ", self.curr());
         }
-        print!("{:?}", self.curr());
+    }
+
+    // an ERROR spanning the token being scanned
+    fn error(&self, message: &str) -> Token {
+        Token::ERROR { message: message.to_string(), span: self.span_at(self.token_start) }
+    }
+
+    // a finished string or char literal, unless an escape in it was bad
+    fn literal_token(&mut self, token: Token) -> Token {
+        match self.literal_error.take() {
+            Some((message, span)) => Token::ERROR { message, span },
+            None => token,
+        }
+    }
+
+    // numeric literal in the buffer: float if it has a fraction or exponent
+    fn number_token(&self) -> Token {
+        if self.buffer_string.contains(['.', 'e', 'E']) {
+            // too large a literal parses as infinity rather than failing
+            match self.buffer_string.parse::<f32>() {
+                Ok(value) if value.is_finite() => Token::LIT_FLT32 { value },
+                _ => self.error(&format!("float literal {} out of range", self.buffer_string)),
+            }
+        } else {
+            // 2147483648 only fits once negated; it wraps to i32::MIN here
+            // and the parser folds it into a preceding minus
+            match self.buffer_string.parse::<i64>() {
+                Ok(value) if value <= 1 << 31 => Token::LIT_INT32 { value: value as u32 as i32 },
+                _ => self.error(&format!("integer literal {} out of range", self.buffer_string)),
+            }
+        }
+    }
+
+    // buffered text after a quote that reads as a loop label name
+    fn buffer_is_label(&self) -> bool {
+        let mut chars = self.buffer_string.chars();
+        matches!(chars.next(), Some('A'..='Z' | 'a'..='z' | '_'))
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
     }
 
     fn match_buffer_string(&mut self) -> Token {
@@ -409,17 +939,28 @@ impl Lexer {
         match self.buffer_string.as_str() {
             "func" => Token::FUNC,
             "let" => Token::LET,
+            "const" => Token::CONST,
             "if" => Token::IF,
             "else" => Token::ELSE,
+            "enum" => Token::ENUM,
+            "option" => Token::OPTION,
             "return" => Token::RETURN,
+            "break" => Token::BREAK,
+            "continue" => Token::CONTINUE,
             "while" => Token::WHILE,
+            "loop" => Token::LOOP,
+            "for" => Token::FOR,
+            "in" => Token::IN,
+            "as" => Token::AS,
             "print" => Token::PRINT,
             "i32" => Token::TYPE_INT32,
             "f32" => Token::TYPE_FLT32,
             "char" => Token::TYPE_CHAR,
             "bool" => Token::TYPE_BOOL,
+            "str" => Token::TYPE_STRING,
             "true" => Token::LIT_BOOL { value: true },
             "false" => Token::LIT_BOOL { value: false },
+            "none" => Token::LIT_NONE,
             _ => {
                 if string.contains('.') {
                     let value = string.parse::<f32>().unwrap();
@@ -435,10 +976,51 @@ impl Lexer {
                     return Token::LIT_INT32 { value };
                 }
 
-                return Token::ID {
-                    name: string.to_string(),
-                };
+                Token::ID {
+                    name: Symbol::intern(string),
+                }
             }
         }
     }
 }
+
+// yields each token up to, but not including, EOI
+impl Iterator for Lexer {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        match self.advance() {
+            Token::EOI => None,
+            token => Some(token),
+        }
+    }
+}
+
+// lex a whole source string, up to but not including EOI
+#[allow(dead_code)]
+pub fn tokenize(source: &str) -> Vec<SpannedToken> {
+    spanned_tokens(&mut Lexer::new(source.to_string()))
+}
+
+// the rest of a lexer's tokens, up to but not including EOI
+pub fn spanned_tokens(lexer: &mut Lexer) -> Vec<SpannedToken> {
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next() {
+        tokens.push(SpannedToken { token, span: lexer.span() });
+    }
+    tokens
+}
+
+// every lexical error in `contents`, in source order
+pub fn lex_errors(contents: &str) -> Vec<Diagnostic> {
+    Lexer::new(contents.to_string())
+        .filter_map(|token| match token {
+            Token::ERROR { message, span } => Some(Diagnostic::error(diagnostics::LEXICAL, message).at(span)),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn tokens_to_json(tokens: &[SpannedToken]) -> String {
+    serde_json::to_string_pretty(tokens).expect("tokens serialize to JSON")
+}
@@ -1,4 +1,5 @@
 mod cli;
+mod difftest;
 mod lexer;
 mod parser;
 mod pratt_parser;
@@ -6,6 +7,18 @@ mod semantic;
 mod token;
 mod mtree;
 mod interpreter;
+mod intern;
+mod dot;
+mod diagnostics;
+mod explanations;
+mod optimizer;
+mod arena;
+mod stack;
+mod profile;
+mod heap;
+mod bytecode;
+
+use std::{process, thread};
 
 use clap::Parser;
 
@@ -13,9 +26,18 @@ fn main() {
     // parse CLI
     let args: cli::Cli = cli::Cli::parse();
 
-    // get semantic tree from the command
-    cli::handle(args);
-    
+    // the interpreter recurses once per call in the program, so the command
+    // runs on a thread with a stack sized for the deepest recursion allowed
+    let stack_size = cli::stack_size(&args);
+    let worker = thread::Builder::new().stack_size(stack_size).spawn(move || cli::handle(args));
+    let worker = worker.unwrap_or_else(|e| {
+        eprintln!("✗ Cannot reserve a {} MiB stack; try a lower --max-call-depth: {}", stack_size >> 20, e);
+        process::exit(1);
+    });
+    if worker.join().is_err() {
+        // the panic has already been reported
+        process::exit(101);
+    }
 }
 
 
@@ -1,17 +1,41 @@
-use crate::token::Token;
+use crate::dot;
+use crate::stack;
+use crate::token::{Leading, Span, Token};
+use serde::Serialize;
+use serde_json::Value;
+use std::mem;
 use std::rc::Rc;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct MTree {
     pub token: Token,
+    pub span: Span,
     pub children: Vec<Rc<MTree>>,
+    // comments and blank lines before a statement or declaration
+    #[serde(skip_serializing_if = "Leading::is_empty")]
+    pub leading: Leading,
+}
+
+// dropping a node would drop its children before returning, one frame per
+// level; taking them apart here keeps that flat however deep the tree is
+impl Drop for MTree {
+    fn drop(&mut self) {
+        let mut pending = mem::take(&mut self.children);
+        while let Some(child) = pending.pop() {
+            if let Ok(mut child) = Rc::try_unwrap(child) {
+                pending.append(&mut child.children);
+            }
+        }
+    }
 }
 
 impl MTree {
-    pub fn new(token: Token) -> MTree {
+    pub fn new(token: Token, span: Span) -> MTree {
         MTree {
             token,
+            span,
             children: vec![],
+            leading: Leading::default(),
         }
     }
 
@@ -19,6 +43,14 @@ impl MTree {
         self.children.push(Rc::new(tree));
     }
 
+    // the first and last positions of any token in this subtree
+    pub fn extent(&self) -> (Span, Span) {
+        self.children.iter().fold((self.span, self.span), |(from, to), child| {
+            let (child_from, child_to) = stack::guarded(|| child.extent());
+            (from.min(child_from), to.max(child_to))
+        })
+    }
+
     pub fn node_string(&self) -> String {
         format!("{:?}", self.token)
     }
@@ -28,12 +60,50 @@ impl MTree {
         print!("{:1$}", "", shift);
         println!("{}", self.node_string());
         for child in &self.children {
-            child.as_ref().print_recursively(level + 1);
+            stack::guarded(|| child.as_ref().print_recursively(level + 1));
         }
     }
 
     pub fn print(&self) {
         self.print_recursively(0);
     }
+
+    pub fn to_dot(&self) -> String {
+        dot::render("parse_tree", self, &|node: &MTree| {
+            (node.node_string(), node.children.iter().map(|c| c.as_ref()).collect())
+        })
+    }
+
+    // the whole tree, spans included, for tools that consume it
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("parse tree serializes to JSON")
+    }
+
+    // (KIND field... child...), e.g. (ADD (ID "x") (LIT_INT32 1)); tokens
+    // go through serde so every variant's fields come out the same way
+    pub fn to_sexpr(&self) -> String {
+        let mut sexpr = String::from("(");
+        match serde_json::to_value(&self.token).expect("token serializes to JSON") {
+            Value::Object(variant) => {
+                for (name, fields) in variant {
+                    sexpr.push_str(&name);
+                    if let Value::Object(fields) = fields {
+                        for value in fields.values() {
+                            sexpr.push(' ');
+                            sexpr.push_str(&value.to_string());
+                        }
+                    }
+                }
+            }
+            Value::String(name) => sexpr.push_str(&name),
+            other => sexpr.push_str(&other.to_string()),
+        }
+        for child in &self.children {
+            sexpr.push(' ');
+            sexpr.push_str(&stack::guarded(|| child.to_sexpr()));
+        }
+        sexpr.push(')');
+        sexpr
+    }
 }
 
@@ -0,0 +1,1048 @@
+// Passes that rewrite a checked semantic tree into one that runs the same
+// but does less work.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use clap::ValueEnum;
+
+use crate::intern::Symbol;
+use crate::semantic::{Ast, BinOp, MTree, NodeId, Slot, Type, UnOp};
+use crate::stack;
+use crate::token::Span;
+
+type Live = HashSet<Symbol>;
+
+/// A change a pass made, for `--explain-opt`.
+pub struct Rewrite {
+    pub span: Span,
+    pub message: String,
+}
+
+/// An optimization pass, named on the command line by `--passes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Pass {
+    // fold operators applied to literals
+    Fold,
+    // substitute constants for the variables holding them
+    Propagate,
+    // remove branches that never run and stores that are never read
+    Dce,
+    // reuse a value a variable already holds instead of recomputing it
+    Cse,
+    // inline small functions at their call sites
+    Inline,
+}
+
+impl Pass {
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Fold => "fold",
+            Pass::Propagate => "propagate",
+            Pass::Dce => "dce",
+            Pass::Cse => "cse",
+            Pass::Inline => "inline",
+        }
+    }
+}
+
+/// What one pass did: its rewrites, and how many nodes the tree had before
+/// and after it.
+pub struct PassStats {
+    pub pass: Pass,
+    pub rewrites: Vec<Rewrite>,
+    pub nodes_before: usize,
+    pub nodes_after: usize,
+}
+
+/// Runs a list of passes over a checked tree, in order.
+pub struct PassManager {
+    passes: Vec<Pass>,
+    inline_threshold: usize,
+}
+
+impl PassManager {
+    pub fn new(passes: Vec<Pass>) -> Self {
+        PassManager { passes, inline_threshold: 20 }
+    }
+
+    // the passes of `-O<level>`: none at 0, the cheap ones within a function
+    // at 1, and at 2 also CSE and inlining, which give the others more to do
+    pub fn at_level(level: u8) -> Self {
+        let passes = match level {
+            0 => vec![],
+            1 => vec![Pass::Propagate, Pass::Fold, Pass::Dce],
+            _ => vec![Pass::Inline, Pass::Propagate, Pass::Fold, Pass::Cse, Pass::Dce],
+        };
+        Self::new(passes)
+    }
+
+    pub fn with_inline_threshold(mut self, threshold: usize) -> Self {
+        self.inline_threshold = threshold;
+        self
+    }
+
+    pub fn run(&self, ast: &mut Ast) -> Vec<PassStats> {
+        let mut stats = Vec::new();
+        for &pass in &self.passes {
+            let nodes_before = ast.size(ast.root);
+            let rewrites = match pass {
+                Pass::Fold => fold_constants(ast),
+                Pass::Propagate => propagate_constants(ast),
+                Pass::Dce => {
+                    let mut rewrites = simplify_branches(ast);
+                    rewrites.extend(eliminate_dead_stores(ast));
+                    rewrites
+                }
+                Pass::Cse => eliminate_common_subexpressions(ast),
+                Pass::Inline => inline_calls(ast, self.inline_threshold),
+            };
+            stats.push(PassStats { pass, rewrites, nodes_before, nodes_after: ast.size(ast.root) });
+        }
+        stats
+    }
+}
+
+// where a break or continue aimed at an enclosing loop goes: what is live
+// after the loop, and what is live at its next iteration
+struct Target {
+    label: Option<Symbol>,
+    after: Live,
+    next: Live,
+}
+
+// liveness of local variables, worked backwards through each function
+// body; `candidates` collects stores found dead and `needed` stores found
+// live. Loops are visited until their live sets stop growing, and since
+// the sets only grow, a store dead on the last visit was dead on every one
+#[derive(Default)]
+struct Liveness {
+    candidates: HashSet<Span>,
+    needed: HashSet<Span>,
+}
+
+impl Liveness {
+    // the variables `node` reads; a write inside an expression is not
+    // counted as killing anything, which only keeps more stores
+    fn reads(ast: &Ast, node: NodeId, live: &mut Live) {
+        match &ast[node] {
+            MTree::ID { name, .. } => {
+                live.insert(*name);
+            }
+            // an element store keeps the rest of the collection
+            MTree::INDEX_ASSIGN { id, .. } => {
+                live.insert(*id);
+            }
+            _ => {}
+        }
+        for child in ast[node].children() {
+            stack::guarded(|| Self::reads(ast, child, live));
+        }
+    }
+
+    fn target<'a>(targets: &'a [Target], label: &Option<Symbol>) -> Option<&'a Target> {
+        match label {
+            None => targets.last(),
+            Some(_) => targets.iter().rev().find(|t| t.label == *label),
+        }
+    }
+
+    // what is live before the statements of a block, given what is live
+    // after it; `scope` holds the locals visible where the block starts
+    fn block(&mut self, ast: &Ast, stmts: &[NodeId], after: &Live, scope: &HashSet<Symbol>, targets: &mut Vec<Target>) -> Live {
+        // the locals visible at each statement, counting earlier lets here
+        let mut scopes = Vec::with_capacity(stmts.len());
+        let mut visible = scope.clone();
+        for s in stmts {
+            scopes.push(visible.clone());
+            if let MTree::LET_STMT { id, .. } = &ast[*s] {
+                visible.insert(*id);
+            }
+        }
+
+        let mut live = after.clone();
+        for (s, scope) in stmts.iter().zip(&scopes).rev() {
+            live = match &ast[*s] {
+                MTree::LET_STMT { id, expr, .. } => {
+                    live.remove(id);
+                    // before the let, the name is an outer variable, whose
+                    // liveness inside the block is that at its end
+                    if scope.contains(id) && after.contains(id) {
+                        live.insert(*id);
+                    }
+                    if let Some(expr) = expr {
+                        Self::reads(ast, *expr, &mut live);
+                    }
+                    live
+                }
+                _ => self.statement(ast, *s, live, scope, targets),
+            };
+        }
+        live
+    }
+
+    fn statement(&mut self, ast: &Ast, stmt: NodeId, mut live: Live, scope: &HashSet<Symbol>, targets: &mut Vec<Target>) -> Live {
+        match &ast[stmt] {
+            // only locals are tracked; other functions may read a global
+            MTree::ASSIGN { id, expr, span, .. } if scope.contains(id) => {
+                if live.remove(id) {
+                    self.needed.insert(*span);
+                } else {
+                    self.candidates.insert(*span);
+                }
+                Self::reads(ast, *expr, &mut live);
+                live
+            }
+            MTree::BLOCK { stmts, .. } => self.block(ast, stmts, &live, scope, targets),
+            MTree::RTRN_STMT { expr, .. } => {
+                let mut live = Live::new();
+                if let Some(expr) = expr {
+                    Self::reads(ast, *expr, &mut live);
+                }
+                live
+            }
+            MTree::BREAK_STMT { label, .. } => Self::target(targets, label).map(|t| t.after.clone()).unwrap_or_default(),
+            MTree::CONTINUE_STMT { label, .. } => Self::target(targets, label).map(|t| t.next.clone()).unwrap_or_default(),
+            MTree::IF_STMT { cond, then_block, else_block, .. } => {
+                let mut before = self.statement(ast, *then_block, live.clone(), scope, targets);
+                match else_block {
+                    Some(else_block) => before.extend(self.statement(ast, *else_block, live, scope, targets)),
+                    None => before.extend(live),
+                }
+                Self::reads(ast, *cond, &mut before);
+                before
+            }
+            MTree::WHILE_STMT { cond, body, label, .. } => {
+                // live at the condition: what the condition reads, what is
+                // live after the loop, and what the body needs
+                let mut head = live.clone();
+                Self::reads(ast, *cond, &mut head);
+                loop {
+                    targets.push(Target { label: *label, after: live.clone(), next: head.clone() });
+                    let mut next = self.statement(ast, *body, head.clone(), scope, targets);
+                    targets.pop();
+                    next.extend(head.iter().copied());
+                    if next == head {
+                        return head;
+                    }
+                    head = next;
+                }
+            }
+            MTree::LOOP_STMT { body, label, .. } => {
+                let mut head = Live::new();
+                loop {
+                    targets.push(Target { label: *label, after: live.clone(), next: head.clone() });
+                    let mut next = self.statement(ast, *body, head.clone(), scope, targets);
+                    targets.pop();
+                    next.extend(head.iter().copied());
+                    if next == head {
+                        return head;
+                    }
+                    head = next;
+                }
+            }
+            // the range is worked out once and the loop variable is fresh
+            // in every iteration, so nothing carries it between them
+            MTree::FOR_STMT { var, start, end, body, label, .. } => {
+                let mut inner = scope.clone();
+                inner.insert(*var);
+                let mut head = live.clone();
+                loop {
+                    targets.push(Target { label: *label, after: live.clone(), next: head.clone() });
+                    let mut next = self.statement(ast, *body, head.clone(), &inner, targets);
+                    targets.pop();
+                    next.remove(var);
+                    if scope.contains(var) && head.contains(var) {
+                        next.insert(*var);
+                    }
+                    next.extend(head.iter().copied());
+                    if next == head {
+                        break;
+                    }
+                    head = next;
+                }
+                Self::reads(ast, *start, &mut head);
+                Self::reads(ast, *end, &mut head);
+                head
+            }
+            _ => {
+                Self::reads(ast, stmt, &mut live);
+                live
+            }
+        }
+    }
+}
+
+// replace the dead stores under `node`: an assignment of a plain value goes
+// away, one whose value is worked out is kept as an expression statement so
+// that calls and run-time errors in it still happen
+fn remove_stores(ast: &mut Ast, node: NodeId, dead: &HashSet<Span>, removed: &mut Vec<Rewrite>) {
+    if let MTree::BLOCK { stmts, .. } = &ast[node] {
+        let mut kept = Vec::with_capacity(stmts.len());
+        for &stmt in stmts {
+            match &ast[stmt] {
+                MTree::ASSIGN { id, expr, span, .. } if dead.contains(span) => {
+                    removed.push(Rewrite { span: *span, message: format!("removed store to '{}', which is never read", id) });
+                    if !matches!(ast[*expr], MTree::ID { .. } | MTree::LIT_INT { .. } | MTree::LIT_FLOAT { .. }
+                        | MTree::LIT_BOOL { .. } | MTree::LIT_CHAR { .. } | MTree::LIT_STRING { .. } | MTree::LIT_NONE { .. })
+                    {
+                        kept.push(*expr);
+                    }
+                }
+                _ => kept.push(stmt),
+            }
+        }
+        if let MTree::BLOCK { stmts, .. } = &mut ast[node] {
+            *stmts = kept;
+        }
+    }
+    for child in ast[node].children() {
+        stack::guarded(|| remove_stores(ast, child, dead, removed));
+    }
+}
+
+/// Remove assignments to local variables whose value is overwritten or goes
+/// out of scope before anything reads it, returning what was removed in
+/// source order.
+pub fn eliminate_dead_stores(ast: &mut Ast) -> Vec<Rewrite> {
+    let mut liveness = Liveness::default();
+    if let MTree::START { funcs } = &ast[ast.root] {
+        for f in funcs {
+            if let MTree::FUNC_DECL { params, body, .. } = &ast[*f] {
+                let scope = params.iter().map(|(name, _, _)| *name).collect();
+                liveness.statement(ast, *body, Live::new(), &scope, &mut Vec::new());
+            }
+        }
+    }
+    let dead: HashSet<Span> = liveness.candidates.difference(&liveness.needed).copied().collect();
+
+    let mut removed = Vec::new();
+    let root = ast.root;
+    remove_stores(ast, root, &dead, &mut removed);
+    removed.sort_by_key(|r| r.span);
+    removed
+}
+
+// the value of a condition folded to a literal
+fn constant(cond: &MTree) -> Option<bool> {
+    match cond {
+        MTree::LIT_BOOL { value, .. } => Some(*value),
+        _ => None,
+    }
+}
+
+/// Replace each `if` whose condition folded to a literal with the branch it
+/// always takes, and drop each `while` whose condition is always false.
+pub fn simplify_branches(ast: &mut Ast) -> Vec<Rewrite> {
+    let mut rewrites = Vec::new();
+    let root = ast.root;
+    simplify(ast, root, &mut rewrites);
+    rewrites.sort_by_key(|r| r.span);
+    rewrites
+}
+
+fn simplify(ast: &mut Ast, node: NodeId, rewrites: &mut Vec<Rewrite>) {
+    for child in ast[node].children() {
+        stack::guarded(|| simplify(ast, child, rewrites));
+    }
+    if let MTree::BLOCK { stmts, .. } = &ast[node] {
+        let mut kept = Vec::with_capacity(stmts.len());
+        for &stmt in stmts {
+            match &ast[stmt] {
+                MTree::IF_STMT { cond, then_block, else_block, span } => match constant(&ast[*cond]) {
+                    Some(true) => {
+                        rewrites.push(Rewrite { span: *span, message: "kept only the branch taken by an 'if' that is always true".to_string() });
+                        kept.push(*then_block);
+                    }
+                    Some(false) => {
+                        rewrites.push(Rewrite { span: *span, message: "kept only the branch taken by an 'if' that is always false".to_string() });
+                        kept.extend(*else_block);
+                    }
+                    None => kept.push(stmt),
+                },
+                MTree::WHILE_STMT { cond, span, .. } if constant(&ast[*cond]) == Some(false) => {
+                    rewrites.push(Rewrite { span: *span, message: "removed a 'while' loop whose condition is always false".to_string() });
+                }
+                _ => kept.push(stmt),
+            }
+        }
+        if let MTree::BLOCK { stmts, .. } = &mut ast[node] {
+            *stmts = kept;
+        }
+    }
+}
+
+// the user functions called anywhere under `node`
+fn callees(ast: &Ast, node: NodeId, found: &mut HashSet<Symbol>) {
+    if let MTree::CALL { name, .. } = &ast[node] {
+        found.insert(*name);
+    }
+    for child in ast[node].children() {
+        stack::guarded(|| callees(ast, child, found));
+    }
+}
+
+fn count_returns(ast: &Ast, node: NodeId) -> usize {
+    usize::from(matches!(ast[node], MTree::RTRN_STMT { .. }))
+        + ast[node].children().into_iter().map(|child| stack::guarded(|| count_returns(ast, child))).sum::<usize>()
+}
+
+// gives an inlined function's parameters and locals names of their own, so
+// they cannot collide with the caller's; names it does not declare are
+// left alone and collected in `free`
+struct Renamer<'a> {
+    scopes: Vec<HashMap<Symbol, Symbol>>,
+    counter: &'a mut usize,
+    free: HashSet<Symbol>,
+}
+
+impl Renamer<'_> {
+    // '#' cannot appear in an identifier, so the name is new
+    fn bind(&mut self, name: &mut Symbol) {
+        *self.counter += 1;
+        let fresh = Symbol::intern(&format!("{}#{}", name, self.counter));
+        self.scopes.last_mut().expect("renamer always has a scope").insert(*name, fresh);
+        *name = fresh;
+    }
+
+    fn resolve(&mut self, name: &mut Symbol) {
+        match self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            Some(fresh) => *name = *fresh,
+            None => {
+                self.free.insert(*name);
+            }
+        }
+    }
+
+    fn rename(&mut self, ast: &mut Ast, node: NodeId) {
+        stack::guarded(|| self.rename_node(ast, node))
+    }
+
+    fn rename_node(&mut self, ast: &mut Ast, node: NodeId) {
+        match ast[node] {
+            MTree::BLOCK { .. } => {
+                self.scopes.push(HashMap::new());
+                ast[node].children().into_iter().for_each(|s| self.rename(ast, s));
+                self.scopes.pop();
+            }
+            MTree::LET_STMT { expr, .. } => {
+                if let Some(expr) = expr {
+                    self.rename(ast, expr);
+                }
+                if let MTree::LET_STMT { id, .. } = &mut ast[node] {
+                    self.bind(id);
+                }
+            }
+            MTree::FOR_STMT { start, end, body, .. } => {
+                self.rename(ast, start);
+                self.rename(ast, end);
+                self.scopes.push(HashMap::new());
+                if let MTree::FOR_STMT { var, .. } = &mut ast[node] {
+                    self.bind(var);
+                }
+                self.rename(ast, body);
+                self.scopes.pop();
+            }
+            MTree::ID { .. } => {
+                if let MTree::ID { name, .. } = &mut ast[node] {
+                    self.resolve(name);
+                }
+            }
+            MTree::ASSIGN { .. } | MTree::INDEX_ASSIGN { .. } => {
+                ast[node].children().into_iter().for_each(|c| self.rename(ast, c));
+                if let MTree::ASSIGN { id, .. } | MTree::INDEX_ASSIGN { id, .. } = &mut ast[node] {
+                    self.resolve(id);
+                }
+            }
+            _ => ast[node].children().into_iter().for_each(|c| self.rename(ast, c)),
+        }
+    }
+}
+
+// the name an inlined local had in its function
+fn source_name(name: Symbol) -> &'static str {
+    name.as_str().split('#').next().unwrap_or_default()
+}
+
+// a function small and simple enough to inline: at most one `return`,
+// as its last statement, so its body can run in the caller's place
+struct Inlinable {
+    params: Vec<(Symbol, Type)>,
+    // a copy of the body's statements as they were before any inlining,
+    // which every call site gets a copy of its own of
+    body: Vec<NodeId>,
+    // names the body uses without declaring them: globals and enum variants
+    free: HashSet<Symbol>,
+}
+
+impl Inlinable {
+    // the body with fresh names, parameters bound to `args`, and the value
+    // the function returns split off
+    fn instantiate(&self, ast: &mut Ast, args: &[NodeId], counter: &mut usize) -> (Vec<NodeId>, Option<NodeId>) {
+        let mut renamer = Renamer { scopes: vec![HashMap::new()], counter, free: HashSet::new() };
+        let mut stmts = Vec::new();
+        for ((param, ty), &arg) in self.params.iter().zip(args) {
+            let mut id = *param;
+            renamer.bind(&mut id);
+            let span = ast.span(arg).unwrap_or_default();
+            stmts.push(ast.alloc(MTree::LET_STMT { id, slot: Slot::Unresolved, ty: ty.clone(), expr: Some(arg), span }));
+        }
+        let mut body: Vec<NodeId> = self.body.iter().map(|&s| ast.deep_copy(s)).collect();
+        body.iter().for_each(|&s| renamer.rename(ast, s));
+        let result = match body.last().map(|&s| &ast[s]) {
+            Some(&MTree::RTRN_STMT { expr, .. }) => {
+                body.pop();
+                expr
+            }
+            _ => None,
+        };
+        stmts.extend(body);
+        (stmts, result)
+    }
+}
+
+// the functions worth inlining: not main, not recursive, at most
+// `threshold` nodes, and with no return before the end
+fn inlinable(ast: &mut Ast, funcs: &[NodeId], threshold: usize) -> HashMap<Symbol, Inlinable> {
+    let mut calls: HashMap<Symbol, HashSet<Symbol>> = HashMap::new();
+    for f in funcs {
+        if let MTree::FUNC_DECL { name, body, .. } = &ast[*f] {
+            callees(ast, *body, calls.entry(*name).or_default());
+        }
+    }
+    // whether `name` can call itself, directly or through others
+    let recursive = |name: Symbol| {
+        let mut seen = HashSet::new();
+        let mut pending: Vec<Symbol> = calls[&name].iter().copied().collect();
+        while let Some(f) = pending.pop() {
+            if f == name {
+                return true;
+            }
+            if seen.insert(f) {
+                pending.extend(calls.get(&f).into_iter().flatten().copied());
+            }
+        }
+        false
+    };
+
+    let mut found = HashMap::new();
+    for f in funcs {
+        let MTree::FUNC_DECL { name, params, body, .. } = &ast[*f] else { continue };
+        let MTree::BLOCK { stmts, .. } = &ast[*body] else { continue };
+        let returns = count_returns(ast, *body);
+        let simple = returns == 0 || (returns == 1 && matches!(stmts.last().map(|&s| &ast[s]), Some(MTree::RTRN_STMT { .. })));
+        if *name == "main" || ast.size(*body) > threshold || !simple || recursive(*name) {
+            continue;
+        }
+        let name = *name;
+        let params: Vec<(Symbol, Type)> = params.iter().map(|(p, ty, _)| (*p, ty.clone())).collect();
+        let stmts = stmts.clone();
+        let body = stmts.iter().map(|&s| ast.deep_copy(s)).collect();
+        // a dry run of the renaming, on a copy of its own, finds the names
+        // the body does not declare
+        let mut renamer = Renamer { scopes: vec![HashMap::new()], counter: &mut 0, free: HashSet::new() };
+        for (param, _) in &params {
+            renamer.bind(&mut param.clone());
+        }
+        for s in stmts {
+            let copy = ast.deep_copy(s);
+            renamer.rename(ast, copy);
+        }
+        let inlinable = Inlinable { params, body, free: renamer.free };
+        found.insert(name, inlinable);
+    }
+    found
+}
+
+// replaces calls to inlinable functions, walking each function body with
+// the names declared so far in scope
+struct Inliner {
+    functions: HashMap<Symbol, Inlinable>,
+    // numbers the fresh names given to inlined locals
+    counter: usize,
+    rewrites: Vec<Rewrite>,
+}
+
+impl Inliner {
+    fn block(&mut self, ast: &mut Ast, block: NodeId, visible: &HashSet<Symbol>) {
+        let mut visible = visible.clone();
+        let mut stmts = Vec::new();
+        for stmt in ast[block].children() {
+            self.nested(ast, stmt, &visible);
+            if let MTree::LET_STMT { id, .. } = ast[stmt] {
+                visible.insert(id);
+            }
+            match self.expand(ast, stmt, &visible) {
+                Some(replacement) => stmts.extend(replacement),
+                None => stmts.push(stmt),
+            }
+        }
+        if let MTree::BLOCK { stmts: old, .. } = &mut ast[block] {
+            *old = stmts;
+        }
+    }
+
+    fn nested(&mut self, ast: &mut Ast, node: NodeId, visible: &HashSet<Symbol>) {
+        match ast[node] {
+            MTree::BLOCK { .. } => self.block(ast, node, visible),
+            MTree::FOR_STMT { var, body, .. } => {
+                let mut inner = visible.clone();
+                inner.insert(var);
+                self.nested(ast, body, &inner);
+            }
+            _ => ast[node].children().into_iter().for_each(|c| stack::guarded(|| self.nested(ast, c, visible))),
+        }
+    }
+
+    // the statements to run in place of `stmt`, when it is a call to an
+    // inlinable function, or stores, prints or returns what one returns.
+    // A `let` is spliced into the enclosing block so its variable stays in
+    // scope; everything else becomes a block of its own
+    fn expand(&mut self, ast: &mut Ast, stmt: NodeId, visible: &HashSet<Symbol>) -> Option<Vec<NodeId>> {
+        let call = match ast[stmt] {
+            MTree::ASSIGN { expr, .. } | MTree::PRINT_STMT { expr, .. } => expr,
+            MTree::LET_STMT { expr: Some(expr), .. } | MTree::RTRN_STMT { expr: Some(expr), .. } => expr,
+            _ => stmt,
+        };
+        let MTree::CALL { name, args, span } = &ast[call] else { return None };
+        let (name, args, span) = (*name, args.clone(), *span);
+        let function = self.functions.get(&name)?;
+        // the body must see the same globals it did in its own function
+        if !function.free.is_disjoint(visible) {
+            return None;
+        }
+        let (mut stmts, result) = function.instantiate(ast, &args, &mut self.counter);
+        let rewrite = Rewrite { span, message: format!("inlined call to '{}'", name) };
+        let block_span = ast.span(stmt).unwrap_or(span);
+        match (ast[stmt].clone(), result) {
+            (MTree::CALL { .. }, result) => stmts.extend(result),
+            (MTree::ASSIGN { id, span, .. }, Some(value)) => {
+                stmts.push(ast.alloc(MTree::ASSIGN { id, slot: Slot::Unresolved, expr: value, span }));
+            }
+            (MTree::PRINT_STMT { span, .. }, Some(value)) => {
+                stmts.push(ast.alloc(MTree::PRINT_STMT { expr: value, span }));
+            }
+            (MTree::RTRN_STMT { span, .. }, Some(value)) => {
+                stmts.push(ast.alloc(MTree::RTRN_STMT { expr: Some(value), span }));
+            }
+            (MTree::LET_STMT { id, ty, span, .. }, Some(value)) => {
+                stmts.push(ast.alloc(MTree::LET_STMT { id, slot: Slot::Unresolved, ty, expr: Some(value), span }));
+                mark_inlined(ast, &stmts[args.len()..], name, rewrite.span);
+                self.rewrites.push(rewrite);
+                return Some(stmts);
+            }
+            _ => return None,
+        }
+        mark_inlined(ast, &stmts[args.len()..], name, rewrite.span);
+        self.rewrites.push(rewrite);
+        Some(vec![ast.alloc(MTree::BLOCK { stmts, span: block_span })])
+    }
+}
+
+// the body's statements, and the one that uses the value it returns, run
+// on the function's behalf; the parameter lets before them evaluate the
+// caller's arguments, so they stay the caller's own
+fn mark_inlined(ast: &mut Ast, stmts: &[NodeId], function: Symbol, site: Span) {
+    for &stmt in stmts {
+        ast.mark_inlined(stmt, function, site);
+    }
+}
+
+/// Inline calls to small functions that are not recursive, made as a
+/// statement of their own or as the value of an assignment, `let`, `print`
+/// or `return`. The body runs in a block of its own, with a `let` for each
+/// parameter and fresh names for its locals, and is marked with the call it
+/// replaces so runtime errors still trace through it. Functions larger than
+/// `threshold` nodes are left alone; 0 turns the pass off.
+pub fn inline_calls(ast: &mut Ast, threshold: usize) -> Vec<Rewrite> {
+    let MTree::START { funcs } = ast[ast.root].clone() else { return Vec::new() };
+    if threshold == 0 {
+        return Vec::new();
+    }
+    let mut inliner = Inliner { functions: inlinable(ast, &funcs, threshold), counter: 0, rewrites: Vec::new() };
+    for f in funcs {
+        if let MTree::FUNC_DECL { params, body, .. } = &ast[f] {
+            let visible = params.iter().map(|(name, _, _)| *name).collect();
+            let body = *body;
+            inliner.nested(ast, body, &visible);
+        }
+    }
+    inliner.rewrites.sort_by_key(|r| r.span);
+    inliner.rewrites
+}
+
+/// Replace operators applied to literals anywhere in the tree by their
+/// result, except where they would fail at run time.
+pub fn fold_constants(ast: &mut Ast) -> Vec<Rewrite> {
+    let mut folded = Vec::new();
+    let root = ast.root;
+    fold(ast, root, &mut folded);
+    folded.sort_by_key(|r| r.span);
+    folded
+}
+
+fn fold(ast: &mut Ast, node: NodeId, folded: &mut Vec<Rewrite>) {
+    for child in ast[node].children() {
+        stack::guarded(|| fold(ast, child, folded));
+    }
+    let result = match &ast[node] {
+        MTree::EXPR { left, right, op, span } => fold_operator(*op, &ast[*left], &ast[*right], *span).map(|l| (l, op.to_string(), *span)),
+        MTree::UNARY { op, expr, span } => fold_unary(*op, &ast[*expr], *span).map(|l| (l, op.to_string(), *span)),
+        _ => None,
+    };
+    if let Some((literal, op, span)) = result {
+        folded.push(Rewrite { span, message: format!("folded '{}' applied to constants", op) });
+        ast[node] = literal;
+    }
+}
+
+// the literal `op expr` evaluates to; negation is left to run time, where
+// negating i32::MIN is reported or not as --overflow says
+fn fold_unary(op: UnOp, expr: &MTree, span: Span) -> Option<MTree> {
+    match (op, expr) {
+        (UnOp::Not, MTree::LIT_BOOL { value, .. }) => Some(MTree::lit_bool(!value, span)),
+        _ => None,
+    }
+}
+
+// the literal `left op right` evaluates to; None when an operand is not a
+// literal, the operands do not suit the operator (analysis reports those),
+// or evaluating it would overflow or divide by zero, which is left to be
+// reported when the program runs
+fn fold_operator(op: BinOp, left: &MTree, right: &MTree, span: Span) -> Option<MTree> {
+    let compared = |ordering: Option<Ordering>| {
+        // NaN compares unordered: only != holds
+        let result = match op {
+            BinOp::Eq => ordering == Some(Ordering::Equal),
+            BinOp::Ne => ordering != Some(Ordering::Equal),
+            BinOp::Lt => ordering == Some(Ordering::Less),
+            BinOp::Gt => ordering == Some(Ordering::Greater),
+            BinOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            BinOp::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            _ => return None,
+        };
+        Some(MTree::lit_bool(result, span))
+    };
+    match (left, right) {
+        (MTree::LIT_INT { value: a, .. }, MTree::LIT_INT { value: b, .. }) => {
+            let v = match op {
+                BinOp::Add => a.checked_add(*b),
+                BinOp::Sub => a.checked_sub(*b),
+                BinOp::Mul => a.checked_mul(*b),
+                BinOp::Div => a.checked_div(*b),
+                BinOp::Rem => a.checked_rem(*b),
+                _ => return compared(a.partial_cmp(b)),
+            };
+            Some(MTree::lit_int(v?, span))
+        }
+        (MTree::LIT_FLOAT { value: a, .. }, MTree::LIT_FLOAT { value: b, .. }) => {
+            let v = match op {
+                BinOp::Add => a + b,
+                BinOp::Sub => a - b,
+                BinOp::Mul => a * b,
+                BinOp::Div => a / b,
+                _ => return compared(a.partial_cmp(b)),
+            };
+            Some(MTree::LIT_FLOAT { value: v, span })
+        }
+        (MTree::LIT_BOOL { value: a, .. }, MTree::LIT_BOOL { value: b, .. }) => match op {
+            BinOp::And => Some(MTree::lit_bool(*a && *b, span)),
+            BinOp::Or => Some(MTree::lit_bool(*a || *b, span)),
+            // bools only compare for equality
+            BinOp::Eq | BinOp::Ne => compared(a.partial_cmp(b)),
+            _ => None,
+        },
+        (MTree::LIT_CHAR { value: a, .. }, MTree::LIT_CHAR { value: b, .. }) => compared(a.partial_cmp(b)),
+        (MTree::LIT_STRING { value: a, .. }, MTree::LIT_STRING { value: b, .. }) => compared(a.partial_cmp(b)),
+        _ => None,
+    }
+}
+
+fn is_literal(node: &MTree) -> bool {
+    matches!(node, MTree::LIT_INT { .. } | MTree::LIT_FLOAT { .. } | MTree::LIT_BOOL { .. } | MTree::LIT_CHAR { .. } | MTree::LIT_STRING { .. })
+}
+
+// the variables stored into anywhere under `node` after being declared,
+// by assignment or by a builtin that changes its first argument in place
+fn writes(ast: &Ast, node: NodeId, found: &mut HashSet<Symbol>) {
+    match &ast[node] {
+        MTree::ASSIGN { id, .. } | MTree::INDEX_ASSIGN { id, .. } => {
+            found.insert(*id);
+        }
+        MTree::CALL { name, args, .. } if matches!(name.as_str(), "push" | "pop" | "insert" | "remove") => {
+            if let Some(MTree::ID { name, .. }) = args.first().map(|a| &ast[*a]) {
+                found.insert(*name);
+            }
+        }
+        _ => {}
+    }
+    for child in ast[node].children() {
+        stack::guarded(|| writes(ast, child, found));
+    }
+}
+
+// constant propagation, one function at a time: `scopes` holds the value of
+// each variable in scope that is known to be a literal, or None
+struct Propagator {
+    scopes: Vec<HashMap<Symbol, Option<MTree>>>,
+    // variables the function stores into after declaring them
+    changed: HashSet<Symbol>,
+    rewrites: Vec<Rewrite>,
+}
+
+impl Propagator {
+    fn declare(&mut self, id: Symbol, value: Option<MTree>) {
+        self.scopes.last_mut().expect("propagator always has a scope").insert(id, value);
+    }
+
+    fn visit(&mut self, ast: &mut Ast, node: NodeId) {
+        stack::guarded(|| self.visit_node(ast, node))
+    }
+
+    fn visit_node(&mut self, ast: &mut Ast, node: NodeId) {
+        match ast[node] {
+            MTree::BLOCK { .. } => {
+                self.scopes.push(HashMap::new());
+                ast[node].children().into_iter().for_each(|s| self.visit(ast, s));
+                self.scopes.pop();
+            }
+            MTree::LET_STMT { id, expr, .. } => {
+                // folding the initializer lets one constant feed the next
+                let value = expr.and_then(|expr| {
+                    self.visit(ast, expr);
+                    fold(ast, expr, &mut self.rewrites);
+                    is_literal(&ast[expr]).then(|| ast[expr].clone())
+                });
+                let constant = value.filter(|_| !self.changed.contains(&id));
+                self.declare(id, constant);
+            }
+            MTree::FOR_STMT { var, start, end, body, .. } => {
+                self.visit(ast, start);
+                self.visit(ast, end);
+                self.scopes.push(HashMap::new());
+                self.declare(var, None);
+                self.visit(ast, body);
+                self.scopes.pop();
+            }
+            MTree::ID { name, span, .. } => {
+                if let Some(Some(value)) = self.scopes.iter().rev().find_map(|scope| scope.get(&name)) {
+                    self.rewrites.push(Rewrite { span, message: format!("replaced '{}' with its constant value", source_name(name)) });
+                    let mut value = value.clone();
+                    set_span(&mut value, span);
+                    ast[node] = value;
+                }
+            }
+            // the variable a builtin changes in place stays a variable
+            MTree::CALL { name, .. } if matches!(name.as_str(), "push" | "pop" | "insert" | "remove") => {
+                ast[node].children().into_iter().skip(1).for_each(|a| self.visit(ast, a));
+            }
+            _ => ast[node].children().into_iter().for_each(|c| self.visit(ast, c)),
+        }
+    }
+}
+
+// move a literal to where it is substituted
+fn set_span(literal: &mut MTree, to: Span) {
+    match literal {
+        MTree::LIT_INT { span, .. }
+        | MTree::LIT_FLOAT { span, .. }
+        | MTree::LIT_BOOL { span, .. }
+        | MTree::LIT_CHAR { span, .. }
+        | MTree::LIT_STRING { span, .. } => *span = to,
+        _ => {}
+    }
+}
+
+/// Replace reads of program-scope constants, and of locals that are
+/// initialized to a literal and never stored into again, with that literal.
+pub fn propagate_constants(ast: &mut Ast) -> Vec<Rewrite> {
+    let MTree::START { funcs } = ast[ast.root].clone() else { return Vec::new() };
+    let mut globals = HashMap::new();
+    for &f in &funcs {
+        match ast[f] {
+            MTree::CONST_DECL { id, expr, .. } => {
+                fold(ast, expr, &mut Vec::new());
+                globals.insert(id, is_literal(&ast[expr]).then(|| ast[expr].clone()));
+            }
+            // a program-scope variable may be stored into by any function
+            MTree::LET_STMT { id, .. } => {
+                globals.insert(id, None);
+            }
+            _ => {}
+        }
+    }
+    let mut propagator = Propagator { scopes: Vec::new(), changed: HashSet::new(), rewrites: Vec::new() };
+    for f in funcs {
+        if let MTree::FUNC_DECL { params, body, .. } = &ast[f] {
+            let params = params.iter().map(|(p, _, _)| (*p, None)).collect();
+            let body = *body;
+            propagator.changed.clear();
+            writes(ast, body, &mut propagator.changed);
+            propagator.scopes = vec![globals.clone(), params];
+            propagator.visit(ast, body);
+        }
+    }
+    propagator.rewrites.sort_by_key(|r| r.span);
+    propagator.rewrites
+}
+
+// whether two expressions compute the same thing, wherever they are
+fn same(ast: &Ast, a: NodeId, b: NodeId) -> bool {
+    match (&ast[a], &ast[b]) {
+        (MTree::EXPR { left: l1, op: o1, right: r1, .. }, MTree::EXPR { left: l2, op: o2, right: r2, .. }) => {
+            o1 == o2 && stack::guarded(|| same(ast, *l1, *l2) && same(ast, *r1, *r2))
+        }
+        (MTree::UNARY { op: o1, expr: e1, .. }, MTree::UNARY { op: o2, expr: e2, .. }) => o1 == o2 && stack::guarded(|| same(ast, *e1, *e2)),
+        (MTree::ID { name: a, .. }, MTree::ID { name: b, .. }) => a == b,
+        (MTree::LIT_INT { value: a, .. }, MTree::LIT_INT { value: b, .. }) => a == b,
+        (MTree::LIT_FLOAT { value: a, .. }, MTree::LIT_FLOAT { value: b, .. }) => a == b,
+        (MTree::LIT_BOOL { value: a, .. }, MTree::LIT_BOOL { value: b, .. }) => a == b,
+        (MTree::LIT_CHAR { value: a, .. }, MTree::LIT_CHAR { value: b, .. }) => a == b,
+        (MTree::LIT_STRING { value: a, .. }, MTree::LIT_STRING { value: b, .. }) => a == b,
+        _ => false,
+    }
+}
+
+// whether `node` is operators over `locals` and literals, so that nothing
+// but a store to one of those locals can change its value
+fn reusable(ast: &Ast, node: NodeId, locals: &HashSet<Symbol>) -> bool {
+    match &ast[node] {
+        MTree::EXPR { left, right, .. } => stack::guarded(|| reusable(ast, *left, locals) && reusable(ast, *right, locals)),
+        MTree::UNARY { expr, .. } => stack::guarded(|| reusable(ast, *expr, locals)),
+        MTree::ID { name, .. } => locals.contains(name),
+        other => is_literal(other),
+    }
+}
+
+fn mentions(ast: &Ast, node: NodeId, name: Symbol) -> bool {
+    matches!(ast[node], MTree::ID { name: n, .. } if n == name) || ast[node].children().into_iter().any(|c| stack::guarded(|| mentions(ast, c, name)))
+}
+
+// an expression already computed into a variable that still holds it
+#[derive(Clone)]
+struct Available {
+    expr: NodeId,
+    holder: Symbol,
+}
+
+// common subexpression elimination within each function body, walking
+// blocks in order with the expressions available at each statement
+struct Cse {
+    rewrites: Vec<Rewrite>,
+}
+
+impl Cse {
+    fn block(&mut self, ast: &mut Ast, stmts: &[NodeId], available: &[Available], locals: &HashSet<Symbol>) {
+        let mut available = available.to_vec();
+        let mut locals = locals.clone();
+        for &stmt in stmts {
+            match ast[stmt] {
+                MTree::LET_STMT { id, expr, .. } => {
+                    if let Some(expr) = expr {
+                        self.replace(ast, expr, &available);
+                    }
+                    kill(ast, &mut available, id);
+                    locals.insert(id);
+                    if let Some(expr) = expr {
+                        remember(ast, &mut available, expr, id, &locals);
+                    }
+                }
+                MTree::ASSIGN { id, expr, .. } => {
+                    self.replace(ast, expr, &available);
+                    kill(ast, &mut available, id);
+                    if locals.contains(&id) {
+                        remember(ast, &mut available, expr, id, &locals);
+                    }
+                }
+                MTree::IF_STMT { cond, then_block, else_block, .. } => {
+                    self.replace(ast, cond, &available);
+                    self.nested(ast, then_block, &available, &locals);
+                    if let Some(else_block) = else_block {
+                        self.nested(ast, else_block, &available, &locals);
+                    }
+                }
+                // a loop body starts with whatever its last iteration left
+                MTree::WHILE_STMT { cond, body, .. } => {
+                    kill_writes(ast, &mut available, body);
+                    self.replace(ast, cond, &available);
+                    self.nested(ast, body, &available, &locals);
+                }
+                MTree::LOOP_STMT { body, .. } => {
+                    kill_writes(ast, &mut available, body);
+                    self.nested(ast, body, &available, &locals);
+                }
+                MTree::FOR_STMT { var, start, end, body, .. } => {
+                    self.replace(ast, start, &available);
+                    self.replace(ast, end, &available);
+                    kill_writes(ast, &mut available, body);
+                    let mut inner = locals.clone();
+                    inner.insert(var);
+                    let mut in_body = available.clone();
+                    kill(ast, &mut in_body, var);
+                    self.nested(ast, body, &in_body, &inner);
+                }
+                MTree::BLOCK { .. } => self.nested(ast, stmt, &available, &locals),
+                _ => self.replace(ast, stmt, &available),
+            }
+            kill_writes(ast, &mut available, stmt);
+        }
+    }
+
+    fn nested(&mut self, ast: &mut Ast, block: NodeId, available: &[Available], locals: &HashSet<Symbol>) {
+        if let MTree::BLOCK { stmts, .. } = &ast[block] {
+            let stmts = stmts.clone();
+            self.block(ast, &stmts, available, locals);
+        }
+    }
+
+    // read each available expression under `node` from its variable
+    fn replace(&mut self, ast: &mut Ast, node: NodeId, available: &[Available]) {
+        if let Some(found) = available.iter().find(|a| same(ast, a.expr, node))
+            && let MTree::EXPR { span, .. } | MTree::UNARY { span, .. } = ast[node]
+        {
+            self.rewrites.push(Rewrite { span, message: format!("reused the value already computed into '{}'", source_name(found.holder)) });
+            ast[node] = MTree::id(found.holder, span);
+            return;
+        }
+        ast[node].children().into_iter().for_each(|c| stack::guarded(|| self.replace(ast, c, available)));
+    }
+}
+
+// forget what a store to `id` makes stale: expressions reading it, and
+// expressions it held
+fn kill(ast: &Ast, available: &mut Vec<Available>, id: Symbol) {
+    available.retain(|a| a.holder != id && !mentions(ast, a.expr, id));
+}
+
+fn kill_writes(ast: &Ast, available: &mut Vec<Available>, node: NodeId) {
+    let mut written = HashSet::new();
+    writes(ast, node, &mut written);
+    for id in written {
+        kill(ast, available, id);
+    }
+}
+
+// note that `holder` now holds the value of `expr`, when that is worth
+// reusing and stays valid until one of its variables is stored into
+fn remember(ast: &Ast, available: &mut Vec<Available>, expr: NodeId, holder: Symbol, locals: &HashSet<Symbol>) {
+    if matches!(ast[expr], MTree::EXPR { .. } | MTree::UNARY { .. }) && reusable(ast, expr, locals) && !mentions(ast, expr, holder) {
+        available.push(Available { expr, holder });
+    }
+}
+
+/// Replace an expression over locals that a variable already holds, having
+/// been computed and not changed since, with a read of that variable.
+pub fn eliminate_common_subexpressions(ast: &mut Ast) -> Vec<Rewrite> {
+    let mut cse = Cse { rewrites: Vec::new() };
+    if let MTree::START { funcs } = ast[ast.root].clone() {
+        for f in funcs {
+            if let MTree::FUNC_DECL { params, body, .. } = &ast[f] {
+                let locals = params.iter().map(|(p, _, _)| *p).collect();
+                let body = *body;
+                cse.nested(ast, body, &[], &locals);
+            }
+        }
+    }
+    cse.rewrites.sort_by_key(|r| r.span);
+    cse.rewrites
+}
@@ -1,26 +1,155 @@
+use std::fmt;
+use crate::diagnostics::{self, Diagnostic};
 use crate::lexer::Lexer;
-use crate::token::Token;
+use crate::token::{Span, Token};
 use crate::mtree::MTree;
 
 
 const INDENT: usize = 2;
 
+// default limit on how deeply blocks, expressions and types may nest;
+// parsing is recursive, so unbounded nesting would overflow the stack
+pub const MAX_DEPTH: usize = 256;
+
+// a syntax error: what went wrong, the token it happened at, and where
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub found: Token,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ParseErrorKind {
+    // the parser would have accepted any of these instead
+    Expected(Vec<String>),
+    // more than max_depth levels of nesting
+    TooDeep,
+    // a comparison straight after another, as in a < b < c
+    Chained,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let expected = match &self.kind {
+            ParseErrorKind::Expected(expected) => expected,
+            ParseErrorKind::TooDeep => return write!(f, "program too deeply nested"),
+            ParseErrorKind::Chained => return write!(f, "comparison '{}' cannot be chained, add parentheses", self.found),
+        };
+        let found = match &self.found {
+            Token::EOI => "end of input".to_string(),
+            // only an unfolded 2147483648 reaches the parser negative
+            Token::LIT_INT32 { value } if *value < 0 => format!("'{}'", *value as u32),
+            token => format!("'{}'", token),
+        };
+        let expected = match expected.split_last() {
+            Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+            _ => expected.join(""),
+        };
+        write!(f, "expected {}, found {}", expected, found)
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(error: ParseError) -> Self {
+        let code = match error.kind {
+            ParseErrorKind::Expected(_) | ParseErrorKind::Chained => diagnostics::SYNTAX,
+            ParseErrorKind::TooDeep => diagnostics::TOO_DEEP,
+        };
+        Diagnostic::error(code, error.to_string()).at(error.span)
+    }
+}
+
+pub type ParseResult = Result<MTree, ParseError>;
+
+// how a token is named in error messages; placeholder tokens such as
+// Token::id() stand for a whole class, so they get a class name
+fn describe(token: &Token) -> String {
+    match token {
+        Token::ID { .. } => "identifier".to_string(),
+        Token::LABEL { .. } => "loop label".to_string(),
+        Token::LIT_INT32 { .. } => "integer literal".to_string(),
+        Token::EOI => "end of input".to_string(),
+        token => format!("'{}'", token),
+    }
+}
+
 pub struct Parser {
     lexer: Lexer,
     pub indent: usize,
+    // `[` after an expression is an index unless a block may follow, in
+    // which case bracket_is_index decides
+    pub index_allowed: bool,
+    // syntax errors recovered from so far
+    errors: Vec<ParseError>,
+    // what else was tried at the current token, for error messages
+    alternatives: Vec<String>,
+    // print each parse step as it happens
+    trace: bool,
+    // levels of nesting currently open, and how many are allowed
+    depth: usize,
+    max_depth: usize,
+    // blocks may also be written `{ ... }`
+    brace_blocks: bool,
 }
 
 impl Parser {
     pub fn new(lexer: Lexer) -> Parser {
-        Parser { lexer, indent: 0 }
+        Parser {
+            lexer,
+            indent: 0,
+            index_allowed: true,
+            errors: Vec::new(),
+            alternatives: Vec::new(),
+            trace: false,
+            depth: 0,
+            max_depth: MAX_DEPTH,
+            brace_blocks: false,
+        }
+    }
+
+    // echo the descent to stdout, for debugging the grammar
+    pub fn with_trace(mut self) -> Self {
+        self.trace = true;
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    // accept `{ ... }` blocks alongside `[ ... ]`; a statement can then
+    // no longer start with an array literal
+    pub fn with_brace_blocks(mut self) -> Self {
+        self.brace_blocks = true;
+        self
     }
 
-    pub fn analyze(&mut self) -> MTree {
+    // the whole program, or every syntax error found in it
+    pub fn analyze(&mut self) -> Result<MTree, Vec<ParseError>> {
         self.indent = 0;
         self.advance();
         let tree = self.parse();
-        self.expect(Token::EOI);
-        tree
+        if let Err(error) = self.expect(Token::EOI) {
+            self.errors.push(error);
+        }
+        if self.errors.is_empty() {
+            Ok(tree)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    // a lone expression, optionally ended by `;`, instead of a program;
+    // lets a REPL or a test parse expressions without wrapping them in main
+    pub fn parse_single_expr(&mut self) -> ParseResult {
+        self.indent = 0;
+        self.advance();
+        let tree = self.parse_expr()?;
+        self.accept(Token::SEMICOLON);
+        self.expect(Token::EOI)?;
+        Ok(tree)
     }
 }
 
@@ -32,27 +161,95 @@ impl Parser {
 
     pub fn advance(&mut self) {
         self.lexer.advance();
+        self.alternatives.clear();
     }
 
     pub fn peek(&mut self, symbol: Token) -> bool {
-        self.lexer.curr() == symbol
+        let found = self.lexer.curr() == symbol;
+        if !found {
+            self.consider(describe(&symbol));
+        }
+        found
+    }
+
+    // note that `what` would also have been accepted at the current token
+    pub fn consider(&mut self, what: String) {
+        if !self.alternatives.contains(&what) {
+            self.alternatives.push(what);
+        }
     }
 
-    pub fn expect(&mut self, symbol: Token) {
+    // look k tokens past the current one without consuming them
+    pub fn peek_nth(&mut self, k: usize) -> Token {
+        self.lexer.peek_nth(k)
+    }
+
+    pub fn expect(&mut self, symbol: Token) -> Result<(), ParseError> {
         if self.curr() == symbol {
             self.advance();
-            println!("{:<indent$}expect({symbol:?})", "", indent = self.indent);
+            self.trace_line(&format!("expect({symbol:?})"));
+            Ok(())
         } else {
-            panic!("Expected '{symbol:?}', currently '{:?}'!", self.curr());
+            Err(self.error(&describe(&symbol)))
+        }
+    }
+
+    // an error at the current token, listing `expected` and whatever else
+    // was tried there; quoted tokens come before classes like "expression"
+    pub fn error(&mut self, expected: &str) -> ParseError {
+        let mut alternatives = vec![expected.to_string()];
+        for alternative in self.alternatives.drain(..) {
+            if !alternatives.contains(&alternative) {
+                alternatives.push(alternative);
+            }
+        }
+        alternatives.sort_by_key(|alternative| !alternative.starts_with('\''));
+        ParseError {
+            kind: ParseErrorKind::Expected(alternatives),
+            found: self.curr(),
+            span: self.lexer.span(),
+        }
+    }
+
+    // parse one level deeper, refusing once max_depth levels are open
+    pub fn nested(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult) -> ParseResult {
+        if self.depth >= self.max_depth {
+            return Err(ParseError { kind: ParseErrorKind::TooDeep, found: self.curr(), span: self.lexer.span() });
         }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    // a node located at the current token
+    pub fn node(&mut self, token: Token) -> MTree {
+        MTree::new(token, self.lexer.span())
+    }
+
+    // the current token as a leaf
+    pub fn curr_node(&mut self) -> MTree {
+        let token = self.curr();
+        self.node(token)
     }
 
-    pub fn expect_type(&mut self) {
+    // expect a token, returning it as a leaf
+    pub fn expect_leaf(&mut self, symbol: Token) -> ParseResult {
+        let leaf = self.curr_node();
+        self.expect(symbol)?;
+        Ok(leaf)
+    }
+
+    pub fn expect_type(&mut self) -> Result<(), ParseError> {
         if self.curr().is_type() {
             self.advance();
-            println!( "{:<indent$}expect({:?})", "", self.curr(), indent = self.indent);
+            let token = self.curr();
+            self.trace_line(&format!("expect({token:?})"));
+            Ok(())
         } else {
-            panic!("Expected variable type, currently '{:?}'!", self.curr());
+            // the forms parse_type tried are all types too
+            self.alternatives.clear();
+            Err(self.error("type"))
         }
     }
 
@@ -61,6 +258,7 @@ impl Parser {
             self.advance();
             true
         } else {
+            self.consider(describe(&symbol));
             false
         }
     }
@@ -70,7 +268,13 @@ impl Parser {
     // utility functions for pretty print
 
     pub fn indent_print(&mut self, msg: &'static str) {
-        println!("{:<indent$}{:}", "", msg, indent = self.indent);
+        self.trace_line(msg);
+    }
+
+    fn trace_line(&self, msg: &str) {
+        if self.trace {
+            println!("{:<indent$}{:}", "", msg, indent = self.indent);
+        }
     }
 
     pub fn indent_increment(&mut self) {
@@ -85,233 +289,527 @@ impl Parser {
     // recursive descend parser
 
     pub fn parse(&mut self) -> MTree {
-        let mut tree = MTree::new(Token::START);
+        let mut tree = self.node(Token::START);
         while !self.accept(Token::EOI) {
-            tree._push(self.parse_func());
+            let indent = self.indent;
+            let leading = self.lexer.leading();
+            let decl = if self.peek(Token::ENUM) {
+                self.parse_enum()
+            } else if self.peek(Token::LET) {
+                self.parse_let()
+            } else if self.peek(Token::CONST) {
+                self.parse_const()
+            } else {
+                self.parse_func()
+            };
+            match decl {
+                Ok(mut decl) => {
+                    decl.leading = leading;
+                    tree._push(decl);
+                }
+                Err(error) => {
+                    self.errors.push(error);
+                    self.indent = indent;
+                    self.index_allowed = true;
+                    // resume at the next declaration; `let` also starts
+                    // statements, so it is no safe place to resume
+                    while !matches!(self.curr(), Token::FUNC | Token::ENUM | Token::CONST | Token::EOI) {
+                        self.advance();
+                    }
+                }
+            }
         }
 
         tree
     }
 
-    pub fn parse_func(&mut self) -> MTree {
+    pub fn parse_func(&mut self) -> ParseResult {
         self.indent_print("parse_func()");
         self.indent_increment();
 
-        let mut child = MTree::new(Token::FUNC_DECL);
+        let mut child = self.node(Token::FUNC_DECL);
 
         {
-            self.expect(Token::FUNC);
+            self.expect(Token::FUNC)?;
 
-            let id = self.curr();
-            self.expect(Token::id());
-            child._push(MTree::new(id));
+            child._push(self.expect_leaf(Token::id())?);
 
-            child._push(self.parse_parameter_list());
+            child._push(self.parse_parameter_list()?);
 
             if self.accept(Token::ARROW_R) {
-                let token = self.curr();
-                self.expect_type();
-                child._push(MTree::new(token));
+                child._push(self.parse_type()?);
             }
 
-            child._push(self.parse_block_nest());
+            child._push(self.parse_block_nest()?);
         }
 
         self.indent_decrement();
 
-        child
+        Ok(child)
     }
 
-    pub fn parse_parameter_list(&mut self) -> MTree {
+    pub fn parse_enum(&mut self) -> ParseResult {
+        self.indent_print("parse_enum()");
+        self.indent_increment();
+
+        let mut child = self.node(Token::ENUM_DECL);
+
+        {
+            self.expect(Token::ENUM)?;
+
+            child._push(self.expect_leaf(Token::id())?);
+
+            // variants: { ID { , ID } [ , ] }
+            self.expect(Token::BRACE_L)?;
+            while !self.peek(Token::BRACE_R) {
+                child._push(self.expect_leaf(Token::id())?);
+                if !self.accept(Token::COMMA) {
+                    break;
+                }
+            }
+            self.expect(Token::BRACE_R)?;
+        }
+        self.indent_decrement();
+
+        Ok(child)
+    }
+
+    pub fn parse_parameter_list(&mut self) -> ParseResult {
         self.indent_print("parse_parameter_list()");
         self.indent_increment();
 
-        let mut child = MTree::new(Token::PARAM_LIST);
+        let mut child = self.node(Token::PARAM_LIST);
 
         {
-            self.expect(Token::PARENS_L);
+            self.expect(Token::PARENS_L)?;
             if self.accept(Token::PARENS_R) {
-                return child;
+                return Ok(child);
             }
 
-            child._push(self.parse_parameter());
+            child._push(self.parse_parameter()?);
             while self.accept(Token::COMMA) {
-                child._push(self.parse_parameter());
+                child._push(self.parse_parameter()?);
             }
-            self.expect(Token::PARENS_R);
+            self.expect(Token::PARENS_R)?;
         }
         self.indent_decrement();
 
-        child
+        Ok(child)
     }
 
-    pub fn parse_parameter(&mut self) -> MTree {
+    pub fn parse_parameter(&mut self) -> ParseResult {
         self.indent_print("parse_parameter()");
         self.indent_increment();
 
-        let mut child = MTree::new(Token::PARAM);
+        let mut child = self.node(Token::PARAM);
 
         {
-            let id = self.curr();
-            self.expect(Token::id());
-            child._push(MTree::new(id));
+            child._push(self.expect_leaf(Token::id())?);
 
-            self.expect(Token::COLON);
+            self.expect(Token::COLON)?;
 
-            let type_token = self.curr();
-            self.expect_type();
-            child._push(MTree::new(type_token));
+            child._push(self.parse_type()?);
         }
         self.indent_decrement();
 
-        child
+        Ok(child)
+    }
+
+    pub fn parse_type(&mut self) -> ParseResult {
+        self.nested(Self::parse_type_inner)
     }
 
-    pub fn parse_block_nest(&mut self) -> MTree {
+    fn parse_type_inner(&mut self) -> ParseResult {
+        // array type: [ <type> ; <int> ], vector type: [ <type> ]
+        if self.accept(Token::BRACKET_L) {
+            let elem = self.parse_type()?;
+            let mut child;
+            if self.accept(Token::SEMICOLON) {
+                child = self.node(Token::ARRAY_TYPE);
+                child._push(elem);
+
+                child._push(self.expect_leaf(Token::lit_i32())?);
+            } else {
+                child = self.node(Token::VEC_TYPE);
+                child._push(elem);
+            }
+
+            self.expect(Token::BRACKET_R)?;
+            return Ok(child);
+        }
+
+        // map type: { <type> : <type> }
+        if self.accept(Token::BRACE_L) {
+            let mut child = self.node(Token::MAP_TYPE);
+            child._push(self.parse_type()?);
+            self.expect(Token::COLON)?;
+            child._push(self.parse_type()?);
+            self.expect(Token::BRACE_R)?;
+            return Ok(child);
+        }
+
+        // option type: option < <type> >
+        if self.accept(Token::OPTION) {
+            let mut child = self.node(Token::OPTION_TYPE);
+            self.expect(Token::LT)?;
+            child._push(self.parse_type()?);
+            if self.peek(Token::SHR) {
+                self.lexer.split_shr();
+                self.alternatives.clear();
+                self.trace_line("expect(GT)");
+                return Ok(child);
+            }
+            self.expect(Token::GT)?;
+            return Ok(child);
+        }
+
+        // user-defined type name
+        if self.peek(Token::id()) {
+            let mut child = self.node(Token::NAMED_TYPE);
+            child._push(self.curr_node());
+            self.advance();
+            return Ok(child);
+        }
+
+        let type_node = self.curr_node();
+        self.expect_type()?;
+        Ok(type_node)
+    }
+
+    pub fn parse_block_nest(&mut self) -> ParseResult {
+        self.nested(Self::parse_block_nest_inner)
+    }
+
+    fn parse_block_nest_inner(&mut self) -> ParseResult {
         self.indent_print("parse_block_nest()");
         self.indent_increment();
 
-        let mut child = MTree::new(Token::BLOCK);
+        let mut child = self.node(Token::BLOCK);
 
         {
-            self.expect(Token::BRACKET_L);
-            while !self.peek(Token::BRACKET_R) {
-                child._push(self.parse_statement());
+            // a block closes with the kind of bracket that opened it
+            let (open, close) = if self.brace_blocks && self.peek(Token::BRACE_L) {
+                (Token::BRACE_L, Token::BRACE_R)
+            } else {
+                (Token::BRACKET_L, Token::BRACKET_R)
+            };
+            self.expect(open)?;
+            while !self.at_block_end() {
+                let indent = self.indent;
+                match self.parse_statement() {
+                    Ok(stmt) => child._push(stmt),
+                    Err(error) => {
+                        self.errors.push(error);
+                        self.indent = indent;
+                        self.index_allowed = true;
+                        self.synchronize();
+                    }
+                }
             }
-            self.expect(Token::BRACKET_R);
+            self.expect(close)?;
         }
         self.indent_decrement();
 
-        child
+        Ok(child)
     }
 }
 
 impl Parser {
     // statement/expression parsing functions
 
-    pub fn parse_statement(&mut self) -> MTree {
+    // whether the current token ends the block being parsed; a declaration
+    // keyword cannot start a statement, so seeing one means the block was
+    // never closed
+    fn at_block_end(&mut self) -> bool {
+        match self.curr() {
+            Token::BRACKET_R | Token::FUNC | Token::ENUM | Token::CONST | Token::EOI => true,
+            Token::BRACE_R => self.brace_blocks,
+            _ => false,
+        }
+    }
+
+    // skip the rest of a bad statement: past its `;`, or up to the `]` (or
+    // `}`) closing the block or the keyword starting the next statement;
+    // blocks opened inside the bad statement are skipped whole
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.curr() {
+                Token::FUNC | Token::ENUM | Token::CONST | Token::EOI => return,
+                Token::BRACKET_L => depth += 1,
+                Token::BRACKET_R if depth > 0 => depth -= 1,
+                Token::BRACE_L if self.brace_blocks => depth += 1,
+                Token::BRACE_R if self.brace_blocks && depth > 0 => depth -= 1,
+                Token::BRACE_R if self.brace_blocks => return,
+                Token::SEMICOLON if depth == 0 => {
+                    self.advance();
+                    return;
+                }
+                Token::BRACKET_R
+                | Token::LET | Token::IF | Token::WHILE | Token::LOOP | Token::FOR
+                | Token::PRINT | Token::RETURN | Token::BREAK | Token::CONTINUE if depth == 0 => return,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    pub fn parse_statement(&mut self) -> ParseResult {
         self.indent_print("parse_statement()");
         self.indent_increment();
 
-        let child: MTree;
+        let leading = self.lexer.leading();
+        let mut child: MTree;
         {
             match self.curr() {
-                Token::LET => child = self.parse_let(),
-                Token::IF => child = self.parse_if(),
-                Token::WHILE => child = self.parse_while(),
-                Token::PRINT => child = self.parse_print(),  // <-- ADDED THIS LINE
-                Token::RETURN => child = self.parse_return(),
-                Token::BRACKET_L => child = self.parse_block_nest(),
+                Token::LET => child = self.parse_let()?,
+                Token::IF => child = self.parse_if()?,
+                Token::WHILE => child = self.parse_while()?,
+                Token::LOOP => child = self.parse_loop()?,
+                Token::FOR => child = self.parse_for()?,
+                Token::LABEL { .. } => child = self.parse_labeled_loop()?,
+                Token::PRINT => child = self.parse_print()?,  // <-- ADDED THIS LINE
+                Token::RETURN => child = self.parse_return()?,
+                Token::BREAK => child = self.parse_break()?,
+                Token::CONTINUE => child = self.parse_continue()?,
+                Token::BRACKET_L => child = self.parse_block_nest()?,
+                Token::BRACE_L if self.brace_blocks => child = self.parse_block_nest()?,
                 _ => {
-                    child = self.parse_expr();
-                    self.expect(Token::SEMICOLON);
+                    child = self.parse_expr()?;
+                    self.expect(Token::SEMICOLON)?;
                 },
             }
         }
+        child.leading = leading;
         self.indent_decrement();
 
-        child
+        Ok(child)
     }
 
 
-    pub fn parse_let(&mut self) -> MTree {
+    pub fn parse_let(&mut self) -> ParseResult {
         self.indent_print("parse_let()");
         self.indent_increment();
 
-        let mut child = MTree::new(Token::LET_STMT);
+        let mut child = self.node(Token::LET_STMT);
 
         {
-            self.expect(Token::LET);
+            self.expect(Token::LET)?;
 
-            let id = self.curr();
-            self.expect(Token::id());
-            child._push(MTree::new(id));
+            child._push(self.expect_leaf(Token::id())?);
 
             if self.accept(Token::COLON) {
-                if self.curr().is_type() {
-                    let type_token = self.curr();
-                    self.advance();
-                    child._push(MTree::new(type_token));
-                } else {
-                    panic!("Expected type token after ':', got {:?}", self.curr());
-                }
+                child._push(self.parse_type()?);
             }
 
             if !self.peek(Token::SEMICOLON){
-                self.expect(Token::ASSIGN);
-                child._push(self.parse_expr());
+                self.expect(Token::ASSIGN)?;
+                child._push(self.parse_expr()?);
             }
             
-            self.expect(Token::SEMICOLON);
+            self.expect(Token::SEMICOLON)?;
         }
         self.indent_decrement();
 
-        child
+        Ok(child)
     }
 
-    pub fn parse_if(&mut self) -> MTree {
+    // program-scope constant: the value is required and never reassigned
+    pub fn parse_const(&mut self) -> ParseResult {
+        self.indent_print("parse_const()");
+        self.indent_increment();
+
+        let mut child = self.node(Token::CONST_DECL);
+
+        {
+            self.expect(Token::CONST)?;
+
+            child._push(self.expect_leaf(Token::id())?);
+
+            if self.accept(Token::COLON) {
+                child._push(self.parse_type()?);
+            }
+
+            self.expect(Token::ASSIGN)?;
+            child._push(self.parse_expr()?);
+
+            self.expect(Token::SEMICOLON)?;
+        }
+        self.indent_decrement();
+
+        Ok(child)
+    }
+
+    pub fn parse_if(&mut self) -> ParseResult {
         self.indent_print("parse_if()");
         self.indent_increment();
 
-        let mut child = MTree::new(Token::IF_STMT);
+        let mut child = self.node(Token::IF_STMT);
 
         {
-            self.expect(Token::IF);
-            child._push(self.parse_expr());
-            child._push(self.parse_block_nest());
+            self.expect(Token::IF)?;
+            child._push(self.parse_condition()?);
+            child._push(self.parse_block_nest()?);
             if self.accept(Token::ELSE) {
-                child._push(self.parse_block_nest());
+                child._push(self.parse_block_nest()?);
             }
         }
         self.indent_decrement();
 
-        child
+        Ok(child)
     }
 
-    pub fn parse_while(&mut self) -> MTree {
+    pub fn parse_while(&mut self) -> ParseResult {
         self.indent_print("parse_while()");
         self.indent_increment();
 
-        let mut child = MTree::new(Token::WHILE_STMT);
+        let mut child = self.node(Token::WHILE_STMT);
 
         {
-            self.expect(Token::WHILE);
-            child._push(self.parse_expr());
-            child._push(self.parse_block_nest());
+            self.expect(Token::WHILE)?;
+            child._push(self.parse_condition()?);
+            child._push(self.parse_block_nest()?);
         }
         self.indent_decrement();
 
-        child
+        Ok(child)
+    }
+
+    // <label> : <loop>, the label is appended as the loop's last child
+    pub fn parse_labeled_loop(&mut self) -> ParseResult {
+        self.indent_print("parse_labeled_loop()");
+        self.indent_increment();
+
+        let label = self.expect_leaf(Token::label())?;
+        self.expect(Token::COLON)?;
+
+        let mut child = match self.curr() {
+            Token::WHILE => self.parse_while()?,
+            Token::LOOP => self.parse_loop()?,
+            Token::FOR => self.parse_for()?,
+            _ => return Err(self.error("loop after label")),
+        };
+        child._push(label);
+        self.indent_decrement();
+
+        Ok(child)
     }
 
-    pub fn parse_print(&mut self) -> MTree {
+    pub fn parse_loop(&mut self) -> ParseResult {
+        self.indent_print("parse_loop()");
+        self.indent_increment();
+
+        let mut child = self.node(Token::LOOP_STMT);
+
+        {
+            self.expect(Token::LOOP)?;
+            child._push(self.parse_block_nest()?);
+        }
+        self.indent_decrement();
+
+        Ok(child)
+    }
+
+    // for <id> in <expr> .. <expr> <block>
+    pub fn parse_for(&mut self) -> ParseResult {
+        self.indent_print("parse_for()");
+        self.indent_increment();
+
+        let mut child = self.node(Token::FOR_STMT);
+
+        {
+            self.expect(Token::FOR)?;
+
+            child._push(self.expect_leaf(Token::id())?);
+
+            self.expect(Token::IN)?;
+            child._push(self.parse_expr()?);
+            self.expect(Token::RANGE)?;
+            child._push(self.parse_condition()?);
+            child._push(self.parse_block_nest()?);
+        }
+        self.indent_decrement();
+
+        Ok(child)
+    }
+
+    // a condition is followed directly by a block, so `[` there opens
+    // the block instead of indexing the condition
+    pub fn parse_condition(&mut self) -> ParseResult {
+        let index_allowed = self.index_allowed;
+        self.index_allowed = false;
+        let tree = self.parse_expr()?;
+        self.index_allowed = index_allowed;
+        Ok(tree)
+    }
+
+    pub fn parse_print(&mut self) -> ParseResult {
         self.indent_print("parse_print()");
         self.indent_increment();
 
-        let mut child = MTree::new(Token::PRINT);
+        let mut child = self.node(Token::PRINT);
 
         {
-            self.expect(Token::PRINT);
-            child._push(self.parse_expr());
-            self.expect(Token::SEMICOLON);
+            self.expect(Token::PRINT)?;
+            child._push(self.parse_expr()?);
+            self.expect(Token::SEMICOLON)?;
         }
         self.indent_decrement();
 
-        child
+        Ok(child)
     }
 
-    pub fn parse_return(&mut self) -> MTree {
+    pub fn parse_return(&mut self) -> ParseResult {
         self.indent_print("parse_return()");
         self.indent_increment();
 
-        let mut child = MTree::new(Token::RTRN_STMT);
+        let mut child = self.node(Token::RTRN_STMT);
+        {
+            self.expect(Token::RETURN)?;
+            // bare `return;` leaves a void function early
+            if !self.peek(Token::SEMICOLON) {
+                child._push(self.parse_expr()?);
+            }
+            self.expect(Token::SEMICOLON)?;
+        }
+        self.indent_decrement();
+
+        Ok(child)
+    }
+
+    pub fn parse_break(&mut self) -> ParseResult {
+        self.indent_print("parse_break()");
+        self.indent_increment();
+
+        let mut child = self.node(Token::BREAK);
         {
-            self.expect(Token::RETURN);
-            child._push(self.parse_expr());
-            self.expect(Token::SEMICOLON);
+            self.expect(Token::BREAK)?;
+            if self.peek(Token::label()) {
+                child._push(self.curr_node());
+                self.advance();
+            }
+            self.expect(Token::SEMICOLON)?;
+        }
+        self.indent_decrement();
+
+        Ok(child)
+    }
+
+    pub fn parse_continue(&mut self) -> ParseResult {
+        self.indent_print("parse_continue()");
+        self.indent_increment();
+
+        let mut child = self.node(Token::CONTINUE);
+        {
+            self.expect(Token::CONTINUE)?;
+            if self.peek(Token::label()) {
+                child._push(self.curr_node());
+                self.advance();
+            }
+            self.expect(Token::SEMICOLON)?;
         }
         self.indent_decrement();
 
-        child
+        Ok(child)
     }
 }
@@ -1,179 +1,429 @@
 use crate::token::Token;
-use crate::parser::Parser;
+use crate::parser::{ParseError, ParseErrorKind, ParseResult, Parser};
 use crate::mtree::MTree;
+use std::collections::HashMap;
+use std::mem::{discriminant, Discriminant};
 use std::rc::Rc;
+use std::sync::LazyLock;
 
+#[derive(Clone, Copy)]
 pub struct BindingPower {
     pub left: isize,
     pub right: isize,
     pub unary: isize,
+    pub assoc: Assoc,
 }
 
+// which way a chain of equal-precedence operators groups
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Assoc {
+    Left,
+    Right,
+    // doesn't chain at all: `a < b < c` is an error, not (a < b) < c
+    None,
+}
+
+// one row of the operator table: precedence and grouping as an infix (or
+// postfix) operator, and binding power as a prefix operator, 0 if none
+pub struct Operator {
+    pub token: Token,
+    pub precedence: isize,
+    pub assoc: Assoc,
+    pub unary: isize,
+}
+
+const fn op(token: Token, precedence: isize, assoc: Assoc, unary: isize) -> Operator {
+    Operator { token, precedence, assoc, unary }
+}
+
+// every operator the Pratt parser knows; a new operator only needs a row
+// here (and a case in the lexer), tokens without one bind nothing
+pub static OPERATORS: &[Operator] = &[
+    op(Token::ASSIGN, 5, Assoc::Right, 0),
+    // so `a ? b : c ? d : e` nests in the else arm
+    op(Token::QUESTION, 7, Assoc::Right, 0),
+
+    op(Token::OR, 10, Assoc::Left, 0),
+    op(Token::AND, 11, Assoc::Left, 0),
+    op(Token::NOT, 0, Assoc::Left, 100),
+
+    // comparisons don't chain, as in Rust; write a < b && b < c
+    op(Token::LT, 20, Assoc::None, 0),
+    op(Token::GT, 20, Assoc::None, 0),
+    op(Token::NLT, 20, Assoc::None, 0),
+    op(Token::NGT, 20, Assoc::None, 0),
+    op(Token::EQ, 20, Assoc::None, 0),
+    op(Token::NEQ, 20, Assoc::None, 0),
+
+    // bitwise ops sit between comparisons and arithmetic, as in Rust
+    op(Token::BIT_OR, 22, Assoc::Left, 0),
+    op(Token::BIT_XOR, 24, Assoc::Left, 0),
+    op(Token::BIT_AND, 26, Assoc::Left, 0),
+    op(Token::SHL, 28, Assoc::Left, 0),
+    op(Token::SHR, 28, Assoc::Left, 0),
+
+    op(Token::ADD, 30, Assoc::Left, 0),
+    op(Token::SUB, 30, Assoc::Left, 100),
+    op(Token::MUL, 31, Assoc::Left, 0),
+    op(Token::DIV, 31, Assoc::Left, 0),
+    op(Token::MOD, 31, Assoc::Left, 0),
+    // 2 ** 3 ** 2 is 2 ** 9
+    op(Token::POW, 34, Assoc::Right, 0),
+    // postfix cast, looser than prefix operators: -x as f32 is (-x) as f32
+    op(Token::AS, 40, Assoc::Left, 0),
+    // postfix index and member access, tighter than prefix operators:
+    // -a[0] is -(a[0]) and -p.x is -(p.x)
+    op(Token::BRACKET_L, 110, Assoc::Left, 0),
+    op(Token::POINT, 110, Assoc::Left, 0),
+];
+
+// the table keyed by token kind; the right power is one step past the
+// precedence, in the direction that makes the operator group its way
+static BINDING_POWERS: LazyLock<HashMap<Discriminant<Token>, BindingPower>> = LazyLock::new(|| {
+    OPERATORS
+        .iter()
+        .map(|op| {
+            let right = match op.assoc {
+                Assoc::Left | Assoc::None => op.precedence + 1,
+                Assoc::Right => op.precedence - 1,
+            };
+            (discriminant(&op.token), BindingPower { left: op.precedence, right, unary: op.unary, assoc: op.assoc })
+        })
+        .collect()
+});
+
 impl Token {
     pub fn is_prefix_operator(&self) -> bool {
-        match self {
-            | Token::NOT
-            | Token::SUB
-            | Token::DIV => true,
-            _ => false
-        }
+        self.binding_power().unary > 0
     }
 
     pub fn is_id(&self) -> bool {
-        match self {
-            Token::ID { .. } => true,
-            _ => false
-        }
+        matches!(self, Token::ID { .. })
     }
 
     pub fn is_value_atom(&self) -> bool {
-        match self {
+        matches!(
+            self,
             Token::LIT_INT32 { .. }
-            | Token::LIT_FLT32 { .. }
-            | Token::LIT_CHAR { .. }
-            | Token::LIT_BOOL { .. }
-            | Token::LIT_STRING { .. } => true,
-            _ => false
-        }
+                | Token::LIT_FLT32 { .. }
+                | Token::LIT_CHAR { .. }
+                | Token::LIT_BOOL { .. }
+                | Token::LIT_STRING { .. }
+                | Token::LIT_NONE
+        )
     }
 
     pub fn binding_power(&self) -> BindingPower {
-        match self {
-            Token::EOI => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::ID { .. } => BindingPower { left: 0, right: 0, unary: 0 },
-
-            Token::LIT_CHAR { .. } => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::LIT_INT32 { .. } => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::LIT_FLT32 { .. } => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::LIT_BOOL { .. } => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::LIT_STRING { .. } => BindingPower { left: 0, right: 0, unary: 0 },
-
-            Token::ASSIGN => BindingPower { left: 5, right: 4, unary: 0 },
-
-
-            Token::OR => BindingPower { left: 10, right: 11, unary: 0 },
-            Token::AND => BindingPower { left: 11, right: 12, unary: 0 }, 
-            Token::NOT => BindingPower { left: 18, right: 19, unary: 100 },
-
-            Token::LT => BindingPower { left: 30, right: 30, unary: 0 },
-            Token::GT => BindingPower { left: 30, right: 30, unary: 0 },
-            Token::NLT => BindingPower { left: 30, right: 30, unary: 0 },
-            Token::NGT => BindingPower { left: 30, right: 30, unary: 0 },
-            Token::EQ => BindingPower { left: 30, right: 30, unary: 0 },
-            Token::NEQ => BindingPower { left: 30, right: 30, unary: 0 },
-
-            Token::ADD =>  BindingPower { left: 30, right: 31, unary: 0 },
-            Token::SUB =>  BindingPower { left: 30, right: 31, unary: 100 }, 
-            Token::MUL =>  BindingPower { left: 31, right: 32, unary: 0 },           
-            Token::DIV =>  BindingPower { left: 31, right: 32, unary: 100 },
-
-
-            Token::PARENS_L => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::PARENS_R => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::BRACKET_L => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::BRACKET_R => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::BRACE_L => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::BRACE_R => BindingPower { left: 0, right: 0, unary: 0 },
-
-            Token::POINT => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::COMMA => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::COLON => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::SEMICOLON => BindingPower { left: 0, right: 0, unary: 0 },
-            Token::ARROW_R => BindingPower { left: 0, right: 0, unary: 0 },
-
-            // others: keywords, meta
-            _ => BindingPower { left: 0, right: 0, unary: 0 }
-            
-        }
+        BINDING_POWERS
+            .get(&discriminant(self))
+            .copied()
+            .unwrap_or(BindingPower { left: 0, right: 0, unary: 0, assoc: Assoc::Left })
     }
 }
 
 impl Parser {
-    pub fn parse_expr(&mut self) -> MTree {
+    pub fn parse_expr(&mut self) -> ParseResult {
         self.indent_print("parse_expr()");
         self.indent_increment();
-        let tree = self.parse_expr_tok(1);
+        let tree = self.parse_expr_tok(1)?;
         self.indent_decrement();
-        tree
+        Ok(tree)
+    }
+
+    pub fn parse_expr_tok(&mut self, rbl: isize) -> ParseResult {
+        self.nested(|parser| parser.parse_expr_tok_inner(rbl))
     }
 
-    pub fn parse_expr_tok(&mut self, rbl: isize) -> MTree {
+    fn parse_expr_tok_inner(&mut self, rbl: isize) -> ParseResult {
         let token = self.curr();
 
         if token.is_prefix_operator() {
-            let tree_prefix = self.parse_expr_prefix();
+            let tree_prefix = self.parse_expr_prefix()?;
             self.parse_expr_infix(tree_prefix, rbl)
         } else if token == Token::PARENS_L {
-            let tree_parens = self.parse_expr_parentheses();
+            let tree_parens = self.parse_expr_parentheses()?;
             self.parse_expr_infix(tree_parens, rbl)
+        } else if token == Token::BRACE_L {
+            let tree_array = self.parse_expr_array()?;
+            self.parse_expr_infix(tree_array, rbl)
         } else if token.is_id() || token.is_value_atom() {
-            let tree_atom = self.parse_expr_atom();
+            let tree_atom = self.parse_expr_atom()?;
             self.parse_expr_infix(tree_atom, rbl)
         } else {
-            MTree::new(Token::ERROR)
+            Err(self.error("expression"))
         }
     }
 
-    pub fn parse_expr_prefix(&mut self) -> MTree {
-        let token = self.curr();
+    pub fn parse_expr_prefix(&mut self) -> ParseResult {
+        let mut prefix = self.curr_node();
         self.advance();
-        let tree = self.parse_expr_tok(token.binding_power().unary );
-        MTree {
-            token,
-            children: vec![Rc::new(tree)]
+        // fold a minus into the integer literal it precedes, which is the
+        // only way to write i32::MIN
+        if prefix.token == Token::SUB && self.peek_nth(1) != Token::PARENS_L
+            && let Token::LIT_INT32 { value } = self.curr()
+        {
+            self.advance();
+            return Ok(MTree::new(Token::LIT_INT32 { value: value.wrapping_neg() }, prefix.span));
         }
+        let tree = self.parse_expr_tok(prefix.token.binding_power().unary )?;
+        prefix._push(tree);
+        Ok(prefix)
     }
 
 
-    pub fn parse_expr_parentheses(&mut self) -> MTree {
-        self.expect(Token::PARENS_L);
-        let tree = self.parse_expr();
-        self.expect(Token::PARENS_R);
-        tree
+    // parse an expression nested inside delimiters, where `[` always indexes
+    pub fn parse_expr_nested(&mut self) -> ParseResult {
+        let index_allowed = self.index_allowed;
+        self.index_allowed = true;
+        let tree = self.parse_expr()?;
+        self.index_allowed = index_allowed;
+        Ok(tree)
+    }
+
+    pub fn parse_expr_parentheses(&mut self) -> ParseResult {
+        self.expect(Token::PARENS_L)?;
+        let tree = self.parse_expr_nested()?;
+        self.expect(Token::PARENS_R)?;
+        Ok(tree)
+    }
+
+    pub fn parse_expr_array(&mut self) -> ParseResult {
+        let mut tree = self.node(Token::ARRAY_LIT);
+        self.expect(Token::BRACE_L)?;
+        if !self.peek(Token::BRACE_R) {
+            tree._push(self.parse_expr_nested()?);
+            while self.accept(Token::COMMA) {
+                tree._push(self.parse_expr_nested()?);
+            }
+        }
+        self.expect(Token::BRACE_R)?;
+        Ok(tree)
+    }
+
+    pub fn parse_expr_index(&mut self, base: MTree) -> ParseResult {
+        self.expect(Token::BRACKET_L)?;
+        let index = self.parse_expr_nested()?;
+        self.expect(Token::BRACKET_R)?;
+        let mut tree = MTree::new(Token::INDEX, base.span);
+        tree._push(base);
+        tree._push(index);
+        Ok(tree)
     }
 
-    pub fn parse_expr_atom(&mut self) -> MTree {
-        let atom = self.curr();
+    pub fn parse_expr_atom(&mut self) -> ParseResult {
+        let atom = self.curr_node();
+        // literals lex non-negative, so a negative one is an unfolded 2147483648
+        if let Token::LIT_INT32 { value } = atom.token
+            && value < 0
+        {
+            return Err(ParseError {
+                kind: ParseErrorKind::Expected(vec!["integer literal in i32 range".to_string()]),
+                found: Token::LIT_INT32 { value },
+                span: atom.span,
+            });
+        }
+        let is_call = self.peek_nth(1) == Token::PARENS_L;
         self.advance();
-        if self.peek(Token::PARENS_L) {
+        if is_call {
             self.parse_expr_call(atom)
         } else {
-            MTree::new(atom)
+            Ok(atom)
         }
     }
 
 
-    pub fn parse_expr_call(&mut self, token: Token) -> MTree {
-        let mut tree = MTree::new(token);
-        self.expect(Token::PARENS_L);
+    // call node: [ ID(callee), args... ], so zero-argument calls are
+    // distinguishable from plain identifiers
+    pub fn parse_expr_call(&mut self, callee: MTree) -> ParseResult {
+        let mut tree = MTree::new(Token::CALL, callee.span);
+        tree._push(callee);
+        self.expect(Token::PARENS_L)?;
         if ! self.peek(Token::PARENS_R) {
-            tree.children.push(Rc::new(self.parse_expr()) );
+            tree.children.push(Rc::new(self.parse_expr_nested()?) );
             while self.accept(Token::COMMA) {
-                tree.children.push(Rc::new(self.parse_expr()) );
+                tree.children.push(Rc::new(self.parse_expr_nested()?) );
             }
         }
-        self.expect(Token::PARENS_R);
-        return tree;
+        self.expect(Token::PARENS_R)?;
+        Ok(tree)
     }
 
 
-    pub fn parse_expr_infix(&mut self, mut left: MTree, rbl: isize) -> MTree {
+    // conditional: [ cond, then, else ]; the then arm is delimited by `?`
+    // and `:`, so it may be any expression
+    pub fn parse_expr_ternary(&mut self, cond: MTree) -> ParseResult {
+        self.expect(Token::QUESTION)?;
+        let then_expr = self.parse_expr_nested()?;
+        self.expect(Token::COLON)?;
+        let else_expr = self.parse_expr_tok(Token::QUESTION.binding_power().right)?;
+        let mut tree = MTree::new(Token::QUESTION, cond.span);
+        tree._push(cond);
+        tree._push(then_expr);
+        tree._push(else_expr);
+        Ok(tree)
+    }
+
+    // cast: [ expr, type ]
+    pub fn parse_expr_cast(&mut self, expr: MTree) -> ParseResult {
+        self.expect(Token::AS)?;
+        let ty = self.parse_type()?;
+        let mut tree = MTree::new(Token::CAST, expr.span);
+        tree._push(expr);
+        tree._push(ty);
+        Ok(tree)
+    }
+
+    // whether the `[` at the current token opens an index rather than the
+    // block after a condition: an index holds one expression, which never
+    // contains a `;` or a statement keyword, while a block is empty or
+    // holds statements, which always do
+    fn bracket_is_index(&mut self) -> bool {
+        let mut depth = 0;
+        for k in 1.. {
+            match self.peek_nth(k) {
+                Token::BRACKET_L => depth += 1,
+                Token::BRACKET_R if depth > 0 => depth -= 1,
+                Token::BRACKET_R => return k > 1,
+                Token::SEMICOLON | Token::EOI | Token::FUNC | Token::ENUM
+                | Token::LET | Token::IF | Token::WHILE | Token::LOOP | Token::FOR | Token::LABEL { .. }
+                | Token::PRINT | Token::RETURN | Token::BREAK | Token::CONTINUE => return false,
+                _ => {}
+            }
+        }
+        unreachable!()
+    }
+
+    // member access: [ base, ID(field) ]
+    pub fn parse_expr_member(&mut self, base: MTree) -> ParseResult {
+        self.expect(Token::POINT)?;
+        let mut tree = MTree::new(Token::MEMBER, base.span);
+        tree._push(base);
+        tree._push(self.expect_leaf(Token::id())?);
+        Ok(tree)
+    }
+
+    pub fn parse_expr_infix(&mut self, mut left: MTree, rbl: isize) -> ParseResult {
         loop {
             let op_infix = self.curr();
             if rbl > op_infix.binding_power().left {
-                return left;
+                self.consider("an operator".to_string());
+                return Ok(left);
+            }
+            if op_infix == Token::BRACKET_L {
+                if !self.index_allowed && !self.bracket_is_index() {
+                    self.consider("an operator".to_string());
+                    return Ok(left);
+                }
+                left = self.parse_expr_index(left)?;
+                continue;
+            }
+            if op_infix == Token::QUESTION {
+                left = self.parse_expr_ternary(left)?;
+                continue;
+            }
+            if op_infix == Token::AS {
+                left = self.parse_expr_cast(left)?;
+                continue;
+            }
+            if op_infix == Token::POINT {
+                left = self.parse_expr_member(left)?;
+                continue;
             }
             self.advance();
-            let right = self.parse_expr_tok(op_infix.binding_power().right);
-            left = MTree {
-                token: op_infix,
-                children: vec![
-                    Rc::new(left),
-                    Rc::new(right),
-                ]
+            let power = op_infix.binding_power();
+            let right = self.parse_expr_tok(power.right)?;
+            let mut tree = MTree::new(op_infix, left.span);
+            tree._push(left);
+            tree._push(right);
+            left = tree;
+            // the right power stopped the operand before another operator
+            // of the same rank; a non-associative one can't take it either
+            let next = self.curr().binding_power();
+            if power.assoc == Assoc::None && next.assoc == Assoc::None && next.left == power.left {
+                let span = self.curr_node().span;
+                return Err(ParseError { kind: ParseErrorKind::Chained, found: self.curr(), span });
             }
         }
     }
 
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn sexpr(source: &str) -> String {
+        Parser::new(Lexer::new(source.to_string()))
+            .parse_single_expr()
+            .unwrap_or_else(|error| panic!("{}: {}", source, error))
+            .to_sexpr()
+    }
+
+    fn parse_error(source: &str) -> ParseError {
+        match Parser::new(Lexer::new(source.to_string())).parse_single_expr() {
+            Ok(tree) => panic!("{} parsed as {}", source, tree.to_sexpr()),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn multiplication_binds_tighter_than_addition() {
+        assert_eq!(sexpr("a + b * c"), r#"(ADD (ID "a") (MUL (ID "b") (ID "c")))"#);
+        assert_eq!(sexpr("a - b - c"), r#"(SUB (SUB (ID "a") (ID "b")) (ID "c"))"#);
+    }
+
+    #[test]
+    fn power_groups_right() {
+        assert_eq!(sexpr("2 ** 3 ** 2"), "(POW (LIT_INT32 2) (POW (LIT_INT32 3) (LIT_INT32 2)))");
+    }
+
+    #[test]
+    fn cast_is_looser_than_prefix_minus() {
+        assert_eq!(sexpr("-x as f32"), r#"(CAST (SUB (ID "x")) (TYPE_FLT32))"#);
+        assert_eq!(sexpr("1 + 2 as f32"), "(ADD (LIT_INT32 1) (CAST (LIT_INT32 2) (TYPE_FLT32)))");
+    }
+
+    #[test]
+    fn index_is_tighter_than_prefix_minus() {
+        assert_eq!(sexpr("-a[0]"), r#"(SUB (INDEX (ID "a") (LIT_INT32 0)))"#);
+        assert_eq!(sexpr("-p.x"), r#"(SUB (MEMBER (ID "p") (ID "x")))"#);
+    }
+
+    #[test]
+    fn bitwise_and_binds_tighter_than_or() {
+        assert_eq!(sexpr("a | b & c"), r#"(BIT_OR (ID "a") (BIT_AND (ID "b") (ID "c")))"#);
+    }
+
+    #[test]
+    fn arithmetic_binds_tighter_than_comparison() {
+        assert_eq!(sexpr("a < b + c"), r#"(LT (ID "a") (ADD (ID "b") (ID "c")))"#);
+        assert_eq!(sexpr("!a < b"), r#"(LT (NOT (ID "a")) (ID "b"))"#);
+    }
+
+    #[test]
+    fn ternaries_nest() {
+        assert_eq!(
+            sexpr("a ? b : c ? d : e"),
+            r#"(QUESTION (ID "a") (ID "b") (QUESTION (ID "c") (ID "d") (ID "e")))"#
+        );
+        assert_eq!(
+            sexpr("a ? b ? c : d : e"),
+            r#"(QUESTION (ID "a") (QUESTION (ID "b") (ID "c") (ID "d")) (ID "e"))"#
+        );
+    }
+
+    #[test]
+    fn comparisons_do_not_chain() {
+        assert!(matches!(parse_error("a < b < c").kind, ParseErrorKind::Chained));
+        assert!(matches!(parse_error("a == b != c").kind, ParseErrorKind::Chained));
+        assert_eq!(sexpr("(a < b) == c"), r#"(EQ (LT (ID "a") (ID "b")) (ID "c"))"#);
+    }
+
+    #[test]
+    fn only_minus_and_not_are_prefix_operators() {
+        assert_eq!(parse_error("/x").found, Token::DIV);
+        assert_eq!(parse_error("a ! b").found, Token::NOT);
+    }
+}
@@ -0,0 +1,111 @@
+// Counting for `execute --profile`: the interpreter reports each node it
+// evaluates and each call it enters and leaves, and the counts come out as
+// a report sorted by where the time went.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::intern::Symbol;
+
+// what one function has done so far
+#[derive(Default)]
+struct Counts {
+    calls: u64,
+    nodes: u64,
+    time: Duration,
+    // calls to it under way; only the outermost is timed, since its time
+    // already covers the recursive ones
+    active: usize,
+}
+
+/// Counts kept while a program runs.
+#[derive(Default)]
+pub struct Profiler {
+    nodes: u64,
+    counts: HashMap<Symbol, Counts>,
+    // calls under way, innermost last: the function, when it began and the
+    // nodes it has evaluated itself
+    calls: Vec<(Symbol, Instant, u64)>,
+}
+
+impl Profiler {
+    // one more node evaluated, by the innermost call under way
+    pub fn node(&mut self) {
+        self.nodes += 1;
+        if let Some((_, _, nodes)) = self.calls.last_mut() {
+            *nodes += 1;
+        }
+    }
+
+    pub fn enter(&mut self, name: Symbol) {
+        let counts = self.counts.entry(name).or_default();
+        counts.calls += 1;
+        counts.active += 1;
+        self.calls.push((name, Instant::now(), 0));
+    }
+
+    // the innermost call is over, returned from or abandoned by an error
+    pub fn leave(&mut self) {
+        if let Some((name, start, nodes)) = self.calls.pop() {
+            let counts = self.counts.entry(name).or_default();
+            counts.nodes += nodes;
+            counts.active -= 1;
+            if counts.active == 0 {
+                counts.time += start.elapsed();
+            }
+        }
+    }
+
+    /// The counts so far, the function that took longest first.
+    pub fn report(&self) -> Profile {
+        let mut functions: Vec<FunctionProfile> = self
+            .counts
+            .iter()
+            .map(|(name, counts)| FunctionProfile {
+                name: name.to_string(),
+                calls: counts.calls,
+                nodes: counts.nodes,
+                time_ms: counts.time.as_secs_f64() * 1000.0,
+            })
+            .collect();
+        functions.sort_by(|a, b| b.time_ms.total_cmp(&a.time_ms).then_with(|| a.name.cmp(&b.name)));
+        Profile { nodes: self.nodes, functions }
+    }
+}
+
+/// What a profiled run did: every node it evaluated, and per function the
+/// calls made to it, the nodes evaluated in its own body and the time its
+/// calls took, callees included.
+#[derive(Debug, Serialize)]
+pub struct Profile {
+    pub nodes: u64,
+    pub functions: Vec<FunctionProfile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FunctionProfile {
+    pub name: String,
+    pub calls: u64,
+    pub nodes: u64,
+    pub time_ms: f64,
+}
+
+impl Profile {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("profile serializes to JSON")
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} node(s) evaluated", self.nodes)?;
+        writeln!(f, "{:<20} {:>10} {:>12} {:>12}", "function", "calls", "nodes", "time (ms)")?;
+        for function in &self.functions {
+            writeln!(f, "{:<20} {:>10} {:>12} {:>12.3}", function.name, function.calls, function.nodes, function.time_ms)?;
+        }
+        Ok(())
+    }
+}
@@ -1,81 +1,720 @@
-use std::collections::HashMap;
+#![allow(non_camel_case_types)]
+#![allow(clippy::upper_case_acronyms)]
 
-use crate::token::Token;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::{Index, IndexMut};
+
+use crate::diagnostics::{self, Allow, Diagnostic};
+use crate::arena::{Arena, Id};
+use crate::stack;
+use crate::dot;
+use crate::intern::Symbol;
+use crate::token::{Span, Token};
 use crate::mtree::MTree as ParseTree; // parse-tree type
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Int,
+    Float,
     Bool,
+    Char,
+    Str,
+    Array(Box<Type>, usize),
+    Vec(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    Option(Box<Type>),
+    Enum(String),
     Unknown,
 }
 
+impl Type {
+    // map a type keyword token to its semantic type
+    pub fn from_token(token: &Token) -> Option<Type> {
+        match token {
+            Token::TYPE_INT32 => Some(Type::Int),
+            Token::TYPE_FLT32 => Some(Type::Float),
+            Token::TYPE_BOOL => Some(Type::Bool),
+            Token::TYPE_CHAR => Some(Type::Char),
+            Token::TYPE_STRING => Some(Type::Str),
+            _ => None,
+        }
+    }
+
+    // map a type node (keyword or ARRAY_TYPE) from the parse tree
+    pub fn from_node(node: &ParseTree) -> Option<Type> {
+        match &node.token {
+            Token::ARRAY_TYPE => {
+                let elem = Type::from_node(node.children.first()?)?;
+                match node.children.get(1)?.token {
+                    Token::LIT_INT32 { value } if value >= 0 => {
+                        Some(Type::Array(Box::new(elem), value as usize))
+                    }
+                    _ => None,
+                }
+            }
+            Token::NAMED_TYPE => match &node.children.first()?.token {
+                Token::ID { name } => Some(Type::Enum(name.to_string())),
+                _ => None,
+            },
+            Token::MAP_TYPE => {
+                let key = Type::from_node(node.children.first()?)?;
+                let value = Type::from_node(node.children.get(1)?)?;
+                Some(Type::Map(Box::new(key), Box::new(value)))
+            }
+            Token::OPTION_TYPE => {
+                let inner = Type::from_node(node.children.first()?)?;
+                Some(Type::Option(Box::new(inner)))
+            }
+            Token::VEC_TYPE => {
+                let elem = Type::from_node(node.children.first()?)?;
+                Some(Type::Vec(Box::new(elem)))
+            }
+            token => Type::from_token(token),
+        }
+    }
+
+    // whether a value of type `value` may be stored where `self` is expected;
+    // array literals initialize vectors of the same element type
+    pub fn accepts(&self, value: &Type) -> bool {
+        match (self, value) {
+            (Type::Unknown, _) | (_, Type::Unknown) => true,
+            (Type::Vec(elem), Type::Array(value_elem, _)) => elem.accepts(value_elem),
+            (Type::Vec(elem), Type::Vec(value_elem)) => elem.accepts(value_elem),
+            (Type::Array(elem, n), Type::Array(value_elem, m)) => n == m && elem.accepts(value_elem),
+            (Type::Option(inner), Type::Option(value_inner)) => inner.accepts(value_inner),
+            (Type::Map(key, value), Type::Map(other_key, other_value)) => {
+                key.accepts(other_key) && value.accepts(other_value)
+            }
+            _ => self == value,
+        }
+    }
+
+    // the type both `self` and `other` fit, preferring whichever is more
+    // complete, as Option(Int) over the Option(Unknown) of `none`
+    pub fn unify(&self, other: &Type) -> Option<Type> {
+        if self.accepts(other) && !self.is_partial() {
+            Some(self.clone())
+        } else if other.accepts(self) {
+            Some(other.clone())
+        } else if self.accepts(other) {
+            Some(self.clone())
+        } else {
+            None
+        }
+    }
+
+    // element type of an indexable collection
+    pub fn element(&self) -> Option<Type> {
+        match self {
+            Type::Array(elem, _) | Type::Vec(elem) => Some((**elem).clone()),
+            _ => None,
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+
+    // conversion matrix for `as`: numeric and char conversions, bool and
+    // enum to int, and any type to itself
+    pub fn can_cast(from: &Type, to: &Type) -> bool {
+        match (from, to) {
+            (Type::Unknown, _) => true,
+            (Type::Int | Type::Float, Type::Int | Type::Float) => true,
+            (Type::Int, Type::Char) | (Type::Char, Type::Int) => true,
+            (Type::Bool, Type::Int) | (Type::Enum(_), Type::Int) => true,
+            _ => to.accepts(from),
+        }
+    }
+
+    // whether any part of the type is still Unknown, as for `none` or `{}`
+    pub fn is_partial(&self) -> bool {
+        match self {
+            Type::Unknown => true,
+            Type::Array(elem, _) | Type::Vec(elem) | Type::Option(elem) => elem.is_partial(),
+            Type::Map(key, value) => key.is_partial() || value.is_partial(),
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SymbolTable {
-    vars: HashMap<String, Type>,
+    // one map per open block, innermost last, giving each variable's type
+    // and where it was declared; a function's parameters share the first
+    // with the locals of its outermost block
+    scopes: Vec<HashMap<Symbol, (Type, Span)>>,
+    // program-scope variables, visible unless a local shadows them; None
+    // when this is the program scope itself
+    globals: Option<HashMap<Symbol, (Type, Span)>>,
+    // the type and location of each `return <expr>` checked in this function
+    returns: Vec<(Type, Span)>,
+    // functions the host provides beyond the language's builtins, and how
+    // many arguments each takes; their values are not typed
+    natives: HashMap<Symbol, usize>,
 }
 
 impl SymbolTable {
     pub fn new() -> Self {
         Self {
-            vars: HashMap::new(),
+            scopes: vec![HashMap::new()],
+            globals: None,
+            returns: Vec::new(),
+            natives: HashMap::new(),
         }
     }
 
-    pub fn declare(&mut self, name: &str, ty: Type) -> Result<(), String> {
-        if self.vars.contains_key(name) {
-            Err(format!("Variable '{}' already declared", name))
-        } else {
-            self.vars.insert(name.to_string(), ty);
-            Ok(())
+    // a table for a function body, seeing this table's variables as globals
+    pub fn function_scope(&self) -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            globals: Some(self.scopes[0].clone()),
+            returns: Vec::new(),
+            natives: self.natives.clone(),
+        }
+    }
+
+    // accept calls to the host's native functions, given with their arity
+    pub fn declare_natives(&mut self, natives: impl IntoIterator<Item = (Symbol, usize)>) {
+        self.natives.extend(natives);
+    }
+
+    fn is_native(&self, name: Symbol) -> bool {
+        self.natives.contains_key(&name)
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    fn local(&self, name: Symbol) -> Option<&(Type, Span)> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name))
+    }
+
+    // whether `name` refers to a program-scope variable here
+    pub fn is_global(&self, name: Symbol) -> bool {
+        match &self.globals {
+            None => self.local(name).is_some(),
+            Some(globals) => self.local(name).is_none() && globals.contains_key(&name),
+        }
+    }
+
+    // declare `name` in the innermost scope, shadowing any outer variable of
+    // the same name; fails with where it was first declared if that scope
+    // already has it
+    pub fn declare(&mut self, name: Symbol, ty: Type, span: Span) -> Result<(), Span> {
+        let scope = self.scopes.last_mut().expect("symbol table always has a scope");
+        match scope.entry(name) {
+            Entry::Occupied(slot) => Err(slot.get().1),
+            Entry::Vacant(slot) => {
+                slot.insert((ty, span));
+                Ok(())
+            }
         }
     }
 
-    pub fn check(&self, name: &str) -> Result<Type, String> {
-        self.vars
-            .get(name)
-            .cloned()
+    // where the variable `name` visible here was declared
+    pub fn declared_at(&self, name: Symbol) -> Option<Span> {
+        self.local(name).or_else(|| self.globals.as_ref()?.get(&name)).map(|(_, span)| *span)
+    }
+
+    // every variable visible here, for suggesting a name that was misspelled
+    fn variables(&self) -> impl Iterator<Item = &str> {
+        self.scopes.iter().chain(self.globals.iter()).flat_map(|scope| scope.keys().map(|name| name.as_str()))
+    }
+
+    pub fn check(&self, name: Symbol) -> Result<Type, String> {
+        self.local(name)
+            .or_else(|| self.globals.as_ref()?.get(&name))
+            .map(|(ty, _)| ty.clone())
             .ok_or_else(|| format!("Variable '{}' not declared", name))
     }
 }
 
-/// Semantic AST used by analyzer.
+/// The operator of a binary EXPR node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+}
+
+impl BinOp {
+    pub fn from_token(token: &Token) -> Option<BinOp> {
+        Some(match token {
+            Token::ADD => BinOp::Add,
+            Token::SUB => BinOp::Sub,
+            Token::MUL => BinOp::Mul,
+            Token::DIV => BinOp::Div,
+            Token::MOD => BinOp::Rem,
+            Token::POW => BinOp::Pow,
+            Token::EQ => BinOp::Eq,
+            Token::NEQ => BinOp::Ne,
+            Token::LT => BinOp::Lt,
+            Token::GT => BinOp::Gt,
+            Token::NLT => BinOp::Ge,
+            Token::NGT => BinOp::Le,
+            Token::AND => BinOp::And,
+            Token::OR => BinOp::Or,
+            Token::BIT_AND => BinOp::BitAnd,
+            Token::BIT_OR => BinOp::BitOr,
+            Token::BIT_XOR => BinOp::BitXor,
+            Token::SHL => BinOp::Shl,
+            Token::SHR => BinOp::Shr,
+            _ => return None,
+        })
+    }
+
+    // the operator as written in the source
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Rem => "%",
+            BinOp::Pow => "**",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Gt => ">",
+            BinOp::Le => "<=",
+            BinOp::Ge => ">=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+            BinOp::BitAnd => "&",
+            BinOp::BitOr => "|",
+            BinOp::BitXor => "^",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+        }
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+/// The operator of a UNARY node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Not,
+    Neg,
+}
+
+impl fmt::Display for UnOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnOp::Not => write!(f, "!"),
+            UnOp::Neg => write!(f, "-"),
+        }
+    }
+}
+
+/// Where a variable lives while the program runs: an index into the frame
+/// of the running call, or into the program-scope variables. Nodes start
+/// out Unresolved, and `resolve_slots` fills them in on the finished tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Slot {
+    #[default]
+    Unresolved,
+    Local(usize),
+    Global(usize),
+}
+
+/// A node of the semantic tree, by its place in the tree's arena.
+pub type NodeId = Id<MTree>;
+
+/// Semantic AST used by analyzer. Nodes refer to their children by id, and
+/// live in the arena of the `Ast` they belong to.
 #[derive(Debug,Clone)]
 pub enum MTree {
-    START { funcs: Vec<MTree> },
-    FUNC_DECL { name: String, params: Vec<(String, Type)>, ret_type: Type, body: Box<MTree> },
-    BLOCK { stmts: Vec<MTree> },
-    LET_STMT { id: String, ty: Type, expr: Option<Box<MTree>> },
-    ASSIGN { id: String, expr: Box<MTree> },
-    RTRN_STMT { expr: Box<MTree> },
-    WHILE_STMT { cond: Box<MTree>, body: Box<MTree> },
-    IF_STMT { cond: Box<MTree>, then_block: Box<MTree>, else_block: Option<Box<MTree>> },
-    PRINT_STMT { expr: Box<MTree> },
-    EXPR { left: Box<MTree>, op: String, right: Box<MTree> },
-    CALL { name: String, args: Vec<MTree> },
-    ID { name: String },
-    LIT_INT { value: i32 },
-    LIT_BOOL { value: bool },
+    // the whole program, so it has no one position of its own
+    START { funcs: Vec<NodeId> },
+    // `frame` is how many slots a call needs, once resolved
+    FUNC_DECL { name: Symbol, params: Vec<(Symbol, Type, Span)>, ret_type: Type, body: NodeId, frame: usize, span: Span },
+    ENUM_DECL { name: String, variants: Vec<String>, span: Span },
+    BLOCK { stmts: Vec<NodeId>, span: Span },
+    LET_STMT { id: Symbol, slot: Slot, ty: Type, expr: Option<NodeId>, span: Span },
+    CONST_DECL { id: Symbol, slot: Slot, ty: Type, expr: NodeId, span: Span },
+    ASSIGN { id: Symbol, slot: Slot, expr: NodeId, span: Span },
+    INDEX_ASSIGN { id: Symbol, slot: Slot, index: NodeId, expr: NodeId, span: Span },
+    RTRN_STMT { expr: Option<NodeId>, span: Span },
+    BREAK_STMT { label: Option<Symbol>, span: Span },
+    CONTINUE_STMT { label: Option<Symbol>, span: Span },
+    WHILE_STMT { cond: NodeId, body: NodeId, label: Option<Symbol>, span: Span },
+    LOOP_STMT { body: NodeId, label: Option<Symbol>, span: Span },
+    FOR_STMT { var: Symbol, slot: Slot, start: NodeId, end: NodeId, body: NodeId, label: Option<Symbol>, span: Span },
+    IF_STMT { cond: NodeId, then_block: NodeId, else_block: Option<NodeId>, span: Span },
+    PRINT_STMT { expr: NodeId, span: Span },
+    EXPR { left: NodeId, op: BinOp, right: NodeId, span: Span },
+    UNARY { op: UnOp, expr: NodeId, span: Span },
+    TERNARY { cond: NodeId, then_expr: NodeId, else_expr: NodeId, span: Span },
+    CAST { expr: NodeId, ty: Type, span: Span },
+    CALL { name: Symbol, args: Vec<NodeId>, span: Span },
+    ARRAY_LIT { elems: Vec<NodeId>, span: Span },
+    INDEX { base: NodeId, index: NodeId, span: Span },
+    MEMBER { base: NodeId, field: Symbol, span: Span },
+    ID { name: Symbol, slot: Slot, span: Span },
+    LIT_INT { value: i32, span: Span },
+    LIT_FLOAT { value: f32, span: Span },
+    LIT_BOOL { value: bool, span: Span },
+    LIT_CHAR { value: char, span: Span },
+    LIT_STRING { value: String, span: Span },
+    LIT_NONE { span: Span },
 }
 
+#[allow(dead_code)]
 impl MTree {
     // small helper constructors
-    pub fn lit_int(i: i32) -> Self { MTree::LIT_INT { value: i } }
-    pub fn lit_bool(b: bool) -> Self { MTree::LIT_BOOL { value: b } }
-    pub fn id(name: String) -> Self { MTree::ID { name } }
+    pub fn lit_int(i: i32, span: Span) -> Self { MTree::LIT_INT { value: i, span } }
+    pub fn lit_bool(b: bool, span: Span) -> Self { MTree::LIT_BOOL { value: b, span } }
+    pub fn id(name: Symbol, span: Span) -> Self { MTree::ID { name, slot: Slot::Unresolved, span } }
+}
+
+impl MTree {
+    // a node's label for the DOT graph
+    fn label(&self) -> String {
+        let labeled = |kind: &str, label: &Option<Symbol>| match label {
+            Some(label) => format!("{} '{}", kind, label),
+            None => kind.to_string(),
+        };
+        match self {
+            MTree::START { .. } => "START".to_string(),
+            MTree::FUNC_DECL { name, params, ret_type, .. } => {
+                let params: Vec<String> = params.iter().map(|(p, t, _)| format!("{}: {:?}", p, t)).collect();
+                let mut label = format!("FUNC_DECL {}({})", name, params.join(", "));
+                if *ret_type != Type::Unknown {
+                    label.push_str(&format!(" -> {:?}", ret_type));
+                }
+                label
+            }
+            MTree::ENUM_DECL { name, variants, .. } => format!("ENUM_DECL {} {{ {} }}", name, variants.join(", ")),
+            MTree::BLOCK { .. } => "BLOCK".to_string(),
+            MTree::CONST_DECL { id, ty, .. } => {
+                if *ty == Type::Unknown { format!("CONST_DECL {}", id) } else { format!("CONST_DECL {}: {:?}", id, ty) }
+            }
+            MTree::LET_STMT { id, ty, .. } => {
+                if *ty == Type::Unknown { format!("LET_STMT {}", id) } else { format!("LET_STMT {}: {:?}", id, ty) }
+            }
+            MTree::ASSIGN { id, .. } => format!("ASSIGN {}", id),
+            MTree::INDEX_ASSIGN { id, .. } => format!("INDEX_ASSIGN {}", id),
+            MTree::RTRN_STMT { .. } => "RTRN_STMT".to_string(),
+            MTree::BREAK_STMT { label, .. } => labeled("BREAK_STMT", label),
+            MTree::CONTINUE_STMT { label, .. } => labeled("CONTINUE_STMT", label),
+            MTree::WHILE_STMT { label, .. } => labeled("WHILE_STMT", label),
+            MTree::LOOP_STMT { label, .. } => labeled("LOOP_STMT", label),
+            MTree::FOR_STMT { var, label, .. } => labeled(&format!("FOR_STMT {}", var), label),
+            MTree::IF_STMT { .. } => "IF_STMT".to_string(),
+            MTree::PRINT_STMT { .. } => "PRINT_STMT".to_string(),
+            MTree::EXPR { op, .. } => format!("EXPR {}", op),
+            MTree::UNARY { op, .. } => format!("UNARY {}", op),
+            MTree::TERNARY { .. } => "TERNARY".to_string(),
+            MTree::CAST { ty, .. } => format!("CAST as {:?}", ty),
+            MTree::CALL { name, .. } => format!("CALL {}", name),
+            MTree::ARRAY_LIT { .. } => "ARRAY_LIT".to_string(),
+            MTree::INDEX { .. } => "INDEX".to_string(),
+            MTree::MEMBER { field, .. } => format!("MEMBER .{}", field),
+            MTree::ID { name, .. } => format!("ID {}", name),
+            MTree::LIT_INT { value, .. } => format!("LIT_INT {}", value),
+            MTree::LIT_FLOAT { value, .. } => format!("LIT_FLOAT {:?}", value),
+            MTree::LIT_BOOL { value, .. } => format!("LIT_BOOL {}", value),
+            MTree::LIT_CHAR { value, .. } => format!("LIT_CHAR {:?}", value),
+            MTree::LIT_STRING { value, .. } => format!("LIT_STRING {:?}", value),
+            MTree::LIT_NONE { .. } => "LIT_NONE".to_string(),
+        }
+    }
+
+    // the nodes directly under this one, in source order
+    pub fn children(&self) -> Vec<NodeId> {
+        match self {
+            MTree::START { funcs: nodes }
+            | MTree::BLOCK { stmts: nodes, .. }
+            | MTree::CALL { args: nodes, .. }
+            | MTree::ARRAY_LIT { elems: nodes, .. } => nodes.clone(),
+            MTree::FUNC_DECL { body: node, .. }
+            | MTree::CONST_DECL { expr: node, .. }
+            | MTree::ASSIGN { expr: node, .. }
+            | MTree::LOOP_STMT { body: node, .. }
+            | MTree::PRINT_STMT { expr: node, .. }
+            | MTree::CAST { expr: node, .. }
+            | MTree::UNARY { expr: node, .. }
+            | MTree::MEMBER { base: node, .. } => vec![*node],
+            MTree::LET_STMT { expr, .. } | MTree::RTRN_STMT { expr, .. } => expr.iter().copied().collect(),
+            MTree::INDEX_ASSIGN { index: first, expr: second, .. }
+            | MTree::WHILE_STMT { cond: first, body: second, .. }
+            | MTree::EXPR { left: first, right: second, .. }
+            | MTree::INDEX { base: first, index: second, .. } => vec![*first, *second],
+            MTree::FOR_STMT { start, end, body, .. } => vec![*start, *end, *body],
+            MTree::TERNARY { cond, then_expr, else_expr, .. } => vec![*cond, *then_expr, *else_expr],
+            MTree::IF_STMT { cond, then_block, else_block, .. } => {
+                let mut children = vec![*cond, *then_block];
+                children.extend(*else_block);
+                children
+            }
+            MTree::ENUM_DECL { .. }
+            | MTree::BREAK_STMT { .. }
+            | MTree::CONTINUE_STMT { .. }
+            | MTree::ID { .. }
+            | MTree::LIT_INT { .. }
+            | MTree::LIT_FLOAT { .. }
+            | MTree::LIT_BOOL { .. }
+            | MTree::LIT_CHAR { .. }
+            | MTree::LIT_STRING { .. }
+            | MTree::LIT_NONE { .. } => vec![],
+        }
+    }
+
+    // the same node with each child id passed through `f`
+    fn map_children(&self, mut f: impl FnMut(NodeId) -> NodeId) -> MTree {
+        let mut node = self.clone();
+        match &mut node {
+            MTree::START { funcs: nodes }
+            | MTree::BLOCK { stmts: nodes, .. }
+            | MTree::CALL { args: nodes, .. }
+            | MTree::ARRAY_LIT { elems: nodes, .. } => nodes.iter_mut().for_each(|n| *n = f(*n)),
+            MTree::FUNC_DECL { body: node, .. }
+            | MTree::CONST_DECL { expr: node, .. }
+            | MTree::ASSIGN { expr: node, .. }
+            | MTree::LOOP_STMT { body: node, .. }
+            | MTree::PRINT_STMT { expr: node, .. }
+            | MTree::CAST { expr: node, .. }
+            | MTree::UNARY { expr: node, .. }
+            | MTree::MEMBER { base: node, .. } => *node = f(*node),
+            MTree::LET_STMT { expr, .. } | MTree::RTRN_STMT { expr, .. } => {
+                if let Some(expr) = expr {
+                    *expr = f(*expr);
+                }
+            }
+            MTree::INDEX_ASSIGN { index: first, expr: second, .. }
+            | MTree::WHILE_STMT { cond: first, body: second, .. }
+            | MTree::EXPR { left: first, right: second, .. }
+            | MTree::INDEX { base: first, index: second, .. } => {
+                *first = f(*first);
+                *second = f(*second);
+            }
+            MTree::FOR_STMT { start, end, body, .. } => {
+                *start = f(*start);
+                *end = f(*end);
+                *body = f(*body);
+            }
+            MTree::TERNARY { cond, then_expr, else_expr, .. } => {
+                *cond = f(*cond);
+                *then_expr = f(*then_expr);
+                *else_expr = f(*else_expr);
+            }
+            MTree::IF_STMT { cond, then_block, else_block, .. } => {
+                *cond = f(*cond);
+                *then_block = f(*then_block);
+                if let Some(else_block) = else_block {
+                    *else_block = f(*else_block);
+                }
+            }
+            _ => {}
+        }
+        node
+    }
+
+    // where the node starts in the source; the program as a whole has none
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            MTree::FUNC_DECL { span, .. }
+            | MTree::ENUM_DECL { span, .. }
+            | MTree::LET_STMT { span, .. }
+            | MTree::CONST_DECL { span, .. }
+            | MTree::ASSIGN { span, .. }
+            | MTree::INDEX_ASSIGN { span, .. }
+            | MTree::RTRN_STMT { span, .. }
+            | MTree::BREAK_STMT { span, .. }
+            | MTree::CONTINUE_STMT { span, .. }
+            | MTree::WHILE_STMT { span, .. }
+            | MTree::LOOP_STMT { span, .. }
+            | MTree::FOR_STMT { span, .. }
+            | MTree::IF_STMT { span, .. }
+            | MTree::PRINT_STMT { span, .. }
+            | MTree::EXPR { span, .. }
+            | MTree::UNARY { span, .. }
+            | MTree::TERNARY { span, .. }
+            | MTree::CAST { span, .. }
+            | MTree::CALL { span, .. }
+            | MTree::ARRAY_LIT { span, .. }
+            | MTree::INDEX { span, .. }
+            | MTree::MEMBER { span, .. }
+            | MTree::ID { span, .. }
+            | MTree::BLOCK { span, .. }
+            | MTree::LIT_INT { span, .. }
+            | MTree::LIT_FLOAT { span, .. }
+            | MTree::LIT_BOOL { span, .. }
+            | MTree::LIT_CHAR { span, .. }
+            | MTree::LIT_STRING { span, .. }
+            | MTree::LIT_NONE { span } => Some(*span),
+            MTree::START { .. } => None,
+        }
+    }
+}
+
+/// A converted program: every node of its semantic tree, in one arena
+/// shared by the analyzer, the optimizer and the interpreter, and the
+/// START node at its root. Passes that rewrite the tree replace nodes in
+/// place or add new ones; a node cut out of the tree stays in the arena,
+/// unreachable, until the whole tree is dropped.
+#[derive(Debug, Clone)]
+pub struct Ast {
+    nodes: Arena<MTree>,
+    pub root: NodeId,
+    // statements and expressions that came from inlining a call: the
+    // function called and where, so a runtime error inside one can still
+    // name the call in its trace
+    inlined: HashMap<NodeId, (Symbol, Span)>,
+}
+
+impl Ast {
+    pub fn alloc(&mut self, node: MTree) -> NodeId {
+        self.nodes.alloc(node)
+    }
+
+    pub fn mark_inlined(&mut self, node: NodeId, function: Symbol, site: Span) {
+        self.inlined.insert(node, (function, site));
+    }
+
+    // the inlined call `node` runs the body of, if any
+    pub fn inlined(&self, node: NodeId) -> Option<(Symbol, Span)> {
+        self.inlined.get(&node).copied()
+    }
+
+    pub fn span(&self, node: NodeId) -> Option<Span> {
+        self.nodes[node].span()
+    }
+
+    // a copy of the subtree under `node` made of new nodes, so rewriting
+    // one leaves the other alone
+    pub fn deep_copy(&mut self, node: NodeId) -> NodeId {
+        let copy = self.nodes[node].clone().map_children(|child| stack::guarded(|| self.deep_copy(child)));
+        let copy = self.alloc(copy);
+        if let Some(call) = self.inlined.get(&node).copied() {
+            self.inlined.insert(copy, call);
+        }
+        copy
+    }
+
+    // how many nodes make up the subtree under `node`
+    pub fn size(&self, node: NodeId) -> usize {
+        1 + self.nodes[node].children().into_iter().map(|child| stack::guarded(|| self.size(child))).sum::<usize>()
+    }
+
+    pub fn to_dot(&self) -> String {
+        dot::render("semantic_tree", &self.nodes[self.root], &|node: &MTree| {
+            (node.label(), node.children().into_iter().map(|child| &self.nodes[child]).collect())
+        })
+    }
+
+    // the tree indented by depth, a node per line
+    pub fn print(&self) {
+        fn print_node(ast: &Ast, node: NodeId, level: usize) {
+            println!("{:1$}{2}", "", 2 * level, ast[node].label());
+            for child in ast[node].children() {
+                stack::guarded(|| print_node(ast, child, level + 1));
+            }
+        }
+        print_node(self, self.root, 0);
+    }
+}
+
+impl Index<NodeId> for Ast {
+    type Output = MTree;
+
+    fn index(&self, node: NodeId) -> &MTree {
+        &self.nodes[node]
+    }
+}
+
+impl IndexMut<NodeId> for Ast {
+    fn index_mut(&mut self, node: NodeId) -> &mut MTree {
+        &mut self.nodes[node]
+    }
+}
+
+// a parse tree the ast cannot be built from, pointing at the offending node
+fn malformed(pt: &ParseTree, message: impl Into<String>) -> Diagnostic {
+    Diagnostic::error(diagnostics::MALFORMED, message).at(pt.span)
+}
+
+// the name, declared type, and initializer of a let or const
+fn binding_parts(pt: &ParseTree, nodes: &mut Arena<MTree>) -> Result<(Symbol, Type, Option<NodeId>), Diagnostic> {
+    let id_node = pt.children.first().ok_or_else(|| malformed(pt, "let missing id"))?;
+    let id = match &id_node.token {
+        Token::ID { name } => *name,
+        _ => return Err(malformed(pt, "Expected id in let")),
+    };
+
+    let mut ty = Type::Unknown;
+    let mut expr: Option<NodeId> = None;
+
+    if pt.children.len() >= 2 {
+        let second = &pt.children[1];
+        if second.token.is_type() {
+            ty = Type::from_node(second).unwrap_or(Type::Unknown);
+            if pt.children.len() >= 3 {
+                let expr_node = &pt.children[2];
+                expr = Some(lower(expr_node, nodes)?);
+            }
+        } else {
+            // no type, second is expression
+            expr = Some(lower(second, nodes)?);
+        }
+    }
+
+    Ok((id, ty, expr))
 }
 
 /// Convert parse-tree
-pub fn from_parse_tree(pt: &ParseTree) -> Result<MTree, String> {
+pub fn from_parse_tree(pt: &ParseTree) -> Result<Ast, Diagnostic> {
+    let mut nodes = Arena::new();
+    let root = lower(pt, &mut nodes)?;
+    Ok(Ast { nodes, root, inlined: HashMap::new() })
+}
+
+// convert the parse tree under `pt`, adding its nodes to `nodes`
+fn lower(pt: &ParseTree, nodes: &mut Arena<MTree>) -> Result<NodeId, Diagnostic> {
+    stack::guarded(|| lower_node(pt, nodes))
+}
+
+fn lower_node(pt: &ParseTree, nodes: &mut Arena<MTree>) -> Result<NodeId, Diagnostic> {
     match &pt.token {
-        // program root: children are FUNC_DECL nodes
+        // program root: children are FUNC_DECL, ENUM_DECL, and global
+        // LET_STMT and CONST_DECL nodes
         Token::START => {
             let mut funcs = Vec::new();
             for c in &pt.children {
-                let child = from_parse_tree(c)?;
+                let child = lower(c, nodes)?;
                 funcs.push(child);
             }
-            Ok(MTree::START { funcs })
+            Ok(nodes.alloc(MTree::START { funcs }))
         }
 
         // function declaration node: expected children:
@@ -83,30 +722,26 @@ pub fn from_parse_tree(pt: &ParseTree) -> Result<MTree, String> {
         Token::FUNC_DECL => {
             let mut iter = pt.children.iter();
             // name
-            let name_node = iter.next().ok_or("Missing function name")?;
+            let name_node = iter.next().ok_or_else(|| malformed(pt, "Missing function name"))?;
             let name = match &name_node.token {
-                Token::ID { name } => name.clone(),
-                _ => return Err("Expected ID in FUNC_DECL".into()),
+                Token::ID { name } => *name,
+                _ => return Err(malformed(pt, "Expected ID in FUNC_DECL")),
             };
 
             // params
-            let params_node = iter.next().ok_or("Missing param list")?;
-            let mut params: Vec<(String, Type)> = Vec::new();
+            let params_node = iter.next().ok_or_else(|| malformed(pt, "Missing param list"))?;
+            let mut params: Vec<(Symbol, Type, Span)> = Vec::new();
             // PARAM_LIST children are PARAM nodes
             for p in &params_node.children {
                 // param node: [ ID, TYPE ]
-                let id_node = p.children.get(0).ok_or("Param missing id")?;
-                let type_node = p.children.get(1).ok_or("Param missing type")?;
+                let id_node = p.children.first().ok_or_else(|| malformed(pt, "Param missing id"))?;
+                let type_node = p.children.get(1).ok_or_else(|| malformed(pt, "Param missing type"))?;
                 let pname = match &id_node.token {
-                    Token::ID { name } => name.clone(),
-                    _ => return Err("Expected ID in param".into()),
+                    Token::ID { name } => *name,
+                    _ => return Err(malformed(pt, "Expected ID in param")),
                 };
-                let ptype = match &type_node.token {
-                    Token::TYPE_INT32 => Type::Int,
-                    Token::TYPE_BOOL => Type::Bool,
-                    _ => Type::Unknown,
-                };
-                params.push((pname, ptype));
+                let ptype = Type::from_node(type_node).unwrap_or(Type::Unknown);
+                params.push((pname, ptype, id_node.span));
             }
 
             // next child:
@@ -114,12 +749,8 @@ pub fn from_parse_tree(pt: &ParseTree) -> Result<MTree, String> {
             let mut block_node_opt = None;
             if let Some(next) = iter.next() {
                 match &next.token {
-                    Token::TYPE_INT32 => {
-                        ret_type = Type::Int;
-                        block_node_opt = iter.next();
-                    }
-                    Token::TYPE_BOOL => {
-                        ret_type = Type::Bool;
+                    token if token.is_type() => {
+                        ret_type = Type::from_node(next).unwrap_or(Type::Unknown);
                         block_node_opt = iter.next();
                     }
                     Token::BRACKET_L | Token::BLOCK => {
@@ -132,469 +763,1734 @@ pub fn from_parse_tree(pt: &ParseTree) -> Result<MTree, String> {
                     }
                 }
             }
-            let block_node = block_node_opt.ok_or("Missing function block")?;
-            let body = from_parse_tree(block_node)?;
-            Ok(MTree::FUNC_DECL {
+            let block_node = block_node_opt.ok_or_else(|| malformed(pt, "Missing function block"))?;
+            let body = lower(block_node, nodes)?;
+            Ok(nodes.alloc(MTree::FUNC_DECL {
                 name,
                 params,
                 ret_type,
-                body: Box::new(body),
-            })
+                body,
+                frame: 0,
+                span: pt.span,
+            }))
+        }
+
+        // enum declaration: [ ID(name), ID(variant)... ]
+        Token::ENUM_DECL => {
+            let mut names = Vec::new();
+            for c in &pt.children {
+                match &c.token {
+                    Token::ID { name } => names.push(name.to_string()),
+                    _ => return Err(malformed(pt, "Expected ID in ENUM_DECL")),
+                }
+            }
+            if names.is_empty() {
+                return Err(malformed(pt, "Missing enum name"));
+            }
+            let name = names.remove(0);
+            Ok(nodes.alloc(MTree::ENUM_DECL { name, variants: names, span: pt.span }))
         }
 
         // block: children are statements
         Token::BLOCK => {
             let mut stmts = Vec::new();
             for c in &pt.children {
-                let stmt = from_parse_tree(c)?;
+                let stmt = lower(c, nodes)?;
                 stmts.push(stmt);
             }
-            Ok(MTree::BLOCK { stmts })
+            Ok(nodes.alloc(MTree::BLOCK { stmts, span: pt.span }))
         }
 
         
         // [ ID, optional TYPE, optional expr ]
         Token::LET_STMT => {
-            let id_node = pt.children.get(0).ok_or("let missing id")?;
-            let id = match &id_node.token {
-                Token::ID { name } => name.clone(),
-                _ => return Err("Expected id in let".into()),
-            };
-
-            
-            let mut ty = Type::Unknown;
-            let mut expr: Option<Box<MTree>> = None;
-
-            if pt.children.len() >= 2 {
-                let second = &pt.children[1];
-                match &second.token {
-                    Token::TYPE_INT32 => {
-                        ty = Type::Int;
-                        if pt.children.len() >= 3 {
-                            let expr_node = &pt.children[2];
-                            expr = Some(Box::new(from_parse_tree(expr_node)?));
-                        }
-                    }
-                    Token::TYPE_BOOL => {
-                        ty = Type::Bool;
-                        if pt.children.len() >= 3 {
-                            let expr_node = &pt.children[2];
-                            expr = Some(Box::new(from_parse_tree(expr_node)?));
-                        }
-                    }
-                    Token::ASSIGN | Token::LIT_INT32 { .. } | Token::PARENS_L | Token::ID { .. } => {
-                        // no type, second is expression
-                        expr = Some(Box::new(from_parse_tree(second)?));
-                    }
-                    _ => {
-                        
-                    }
-                }
-            }
+            let (id, ty, expr) = binding_parts(pt, nodes)?;
+            Ok(nodes.alloc(MTree::LET_STMT { id, slot: Slot::Unresolved, ty, expr, span: pt.span }))
+        }
 
-            Ok(MTree::LET_STMT { id, ty, expr })
+        // [ ID, optional TYPE, expr ]
+        Token::CONST_DECL => {
+            let (id, ty, expr) = binding_parts(pt, nodes)?;
+            let expr = expr.ok_or_else(|| malformed(pt, "const missing value"))?;
+            Ok(nodes.alloc(MTree::CONST_DECL { id, slot: Slot::Unresolved, ty, expr, span: pt.span }))
         }
 
         // (token = Token::ASSIGN)
+        // assignment is an expression, so it may also appear nested, as in
+        // `a = b = 3`; its left side must still name a place to store into
         Token::ASSIGN => {
             // children: left (ID) and right (expr)
             if pt.children.len() != 2 {
-                return Err("Assign must have two children".into());
+                return Err(malformed(pt, "Assign must have two children"));
             }
             let left = &pt.children[0];
-            let id = match &left.token {
-                Token::ID { name } => name.clone(),
-                _ => return Err("Left side of assign must be ID".into()),
-            };
-            let right = from_parse_tree(&pt.children[1])?;
-            Ok(MTree::ASSIGN { id, expr: Box::new(right) })
+            let right = lower(&pt.children[1], nodes)?;
+            match &left.token {
+                Token::ID { name } => Ok(nodes.alloc(MTree::ASSIGN { id: *name, slot: Slot::Unresolved, expr: right, span: pt.span })),
+                // element assignment: [ ID, index ]
+                Token::INDEX => {
+                    let id = match left.children.first().map(|c| &c.token) {
+                        Some(Token::ID { name }) => *name,
+                        _ => return Err(malformed(left, "Indexed assignment target must be a variable")),
+                    };
+                    let index_node = left.children.get(1).ok_or_else(|| malformed(pt, "index missing expr"))?;
+                    let index = lower(index_node, nodes)?;
+                    Ok(nodes.alloc(MTree::INDEX_ASSIGN { id, slot: Slot::Unresolved, index, expr: right, span: pt.span }))
+                }
+                _ => Err(malformed(left, "Left side of assignment must be a variable or an indexed variable")),
+            }
         }
 
-        // return statement: first child is expression
+        // return statement: optional expression child
         Token::RTRN_STMT => {
-            let expr_node = pt.children.get(0).ok_or("return missing expr")?;
-            let e = from_parse_tree(expr_node)?;
-            Ok(MTree::RTRN_STMT { expr: Box::new(e) })
+            let expr = match pt.children.first() {
+                Some(expr_node) => Some(lower(expr_node, nodes)?),
+                None => None,
+            };
+            Ok(nodes.alloc(MTree::RTRN_STMT { expr, span: pt.span }))
         }
 
+        Token::BREAK => Ok(nodes.alloc(MTree::BREAK_STMT { label: loop_label(pt), span: pt.span })),
+        Token::CONTINUE => Ok(nodes.alloc(MTree::CONTINUE_STMT { label: loop_label(pt), span: pt.span })),
+
         // while statement: condition and body
         Token::WHILE_STMT => {
-            let cond_node = pt.children.get(0).ok_or("while missing condition")?;
-            let body_node = pt.children.get(1).ok_or("while missing body")?;
-            let cond = from_parse_tree(cond_node)?;
-            let body = from_parse_tree(body_node)?;
-            Ok(MTree::WHILE_STMT { 
-                cond: Box::new(cond), 
-                body: Box::new(body) 
-            })
+            let cond_node = pt.children.first().ok_or_else(|| malformed(pt, "while missing condition"))?;
+            let body_node = pt.children.get(1).ok_or_else(|| malformed(pt, "while missing body"))?;
+            let cond = lower(cond_node, nodes)?;
+            let body = lower(body_node, nodes)?;
+            Ok(nodes.alloc(MTree::WHILE_STMT { 
+                cond, 
+                body,
+                label: loop_label(pt),
+                span: pt.span,
+            }))
+        }
+
+        // loop: [ body ]
+        Token::LOOP_STMT => {
+            let body_node = pt.children.first().ok_or_else(|| malformed(pt, "loop missing body"))?;
+            let body = lower(body_node, nodes)?;
+            Ok(nodes.alloc(MTree::LOOP_STMT { body, label: loop_label(pt), span: pt.span }))
+        }
+
+        // for: [ ID, start, end, body ]
+        Token::FOR_STMT => {
+            let var = match pt.children.first().map(|c| &c.token) {
+                Some(Token::ID { name }) => *name,
+                _ => return Err(malformed(pt, "for missing loop variable")),
+            };
+            let start_node = pt.children.get(1).ok_or_else(|| malformed(pt, "for missing range start"))?;
+            let end_node = pt.children.get(2).ok_or_else(|| malformed(pt, "for missing range end"))?;
+            let body_node = pt.children.get(3).ok_or_else(|| malformed(pt, "for missing body"))?;
+            let start = lower(start_node, nodes)?;
+            let end = lower(end_node, nodes)?;
+            let body = lower(body_node, nodes)?;
+            Ok(nodes.alloc(MTree::FOR_STMT {
+                var,
+                slot: Slot::Unresolved,
+                start,
+                end,
+                body,
+                label: loop_label(pt),
+                span: pt.span,
+            }))
         }
 
         // if 
         Token::IF_STMT => {
-            let cond_node = pt.children.get(0).ok_or("if missing condition")?;
-            let then_node = pt.children.get(1).ok_or("if missing then block")?;
-            let cond = from_parse_tree(cond_node)?;
-            let then_block = from_parse_tree(then_node)?;
+            let cond_node = pt.children.first().ok_or_else(|| malformed(pt, "if missing condition"))?;
+            let then_node = pt.children.get(1).ok_or_else(|| malformed(pt, "if missing then block"))?;
+            let cond = lower(cond_node, nodes)?;
+            let then_block = lower(then_node, nodes)?;
             
             let else_block = if pt.children.len() > 2 {
-                Some(Box::new(from_parse_tree(&pt.children[2])?))
+                Some(lower(&pt.children[2], nodes)?)
             } else {
                 None
             };
             
-            Ok(MTree::IF_STMT { 
-                cond: Box::new(cond), 
-                then_block: Box::new(then_block),
-                else_block
-            })
+            Ok(nodes.alloc(MTree::IF_STMT { 
+                cond, 
+                then_block,
+                else_block,
+                span: pt.span,
+            }))
         }
 
         // print 
         Token::PRINT => {
-            let expr_node = pt.children.get(0).ok_or("print missing expr")?;
-            let e = from_parse_tree(expr_node)?;
-            Ok(MTree::PRINT_STMT { expr: Box::new(e) })
+            let expr_node = pt.children.first().ok_or_else(|| malformed(pt, "print missing expr"))?;
+            let e = lower(expr_node, nodes)?;
+            Ok(nodes.alloc(MTree::PRINT_STMT { expr: e, span: pt.span }))
         }
 
         // Unary operators 
         Token::NOT => {
             if pt.children.len() != 1 {
-                return Err("unary NOT must have one child".into());
+                return Err(malformed(pt, "unary NOT must have one child"));
             }
-            let child = from_parse_tree(&pt.children[0])?;
-            // Represent unary NOT as a special expression with only right operand
-            Ok(MTree::EXPR { 
-                left: Box::new(MTree::LIT_BOOL { value: false }), // dummy
-                op: "!".to_string(), 
-                right: Box::new(child) 
-            })
+            let child = lower(&pt.children[0], nodes)?;
+            Ok(nodes.alloc(MTree::UNARY { op: UnOp::Not, expr: child, span: pt.span }))
         }
 
         // expression nodes (binary ops)
-        Token::ADD | Token::SUB | Token::MUL | Token::DIV
+        Token::ADD | Token::SUB | Token::MUL | Token::DIV | Token::MOD | Token::POW
         | Token::EQ | Token::NEQ | Token::LT | Token::GT | Token::NLT | Token::NGT
-        | Token::AND | Token::OR => {
+        | Token::AND | Token::OR
+        | Token::BIT_AND | Token::BIT_OR | Token::BIT_XOR | Token::SHL | Token::SHR => {
             // Could be unary or binary
             if pt.children.len() == 1 {
                 // Unary minus
-                let child = from_parse_tree(&pt.children[0])?;
-                let op = match &pt.token {
-                    Token::SUB => "-",
-                    _ => return Err("Only SUB can be unary in this position".into()),
-                };
-                Ok(MTree::EXPR { 
-                    left: Box::new(MTree::LIT_INT { value: 0 }), // dummy
-                    op: format!("unary{}", op), 
-                    right: Box::new(child) 
-                })
+                let child = lower(&pt.children[0], nodes)?;
+                if !matches!(pt.token, Token::SUB) {
+                    return Err(malformed(pt, "Only SUB can be unary in this position"));
+                }
+                Ok(nodes.alloc(MTree::UNARY { op: UnOp::Neg, expr: child, span: pt.span }))
             } else if pt.children.len() == 2 {
-                let l = from_parse_tree(&pt.children[0])?;
-                let r = from_parse_tree(&pt.children[1])?;
-                let op = match &pt.token {
-                    Token::ADD => "+",
-                    Token::SUB => "-",
-                    Token::MUL => "*",
-                    Token::DIV => "/",
-                    Token::EQ => "==",
-                    Token::NEQ => "!=",
-                    Token::LT => "<",
-                    Token::GT => ">",
-                    Token::NLT => ">=",
-                    Token::NGT => "<=",
-                    Token::AND => "&&",
-                    Token::OR => "||",
-                    _ => "?",
-                };
-                Ok(MTree::EXPR { left: Box::new(l), op: op.to_string(), right: Box::new(r) })
+                let l = lower(&pt.children[0], nodes)?;
+                let r = lower(&pt.children[1], nodes)?;
+                let op = BinOp::from_token(&pt.token).ok_or_else(|| malformed(pt, "Unknown binary operator"))?;
+                Ok(nodes.alloc(MTree::EXPR { left: l, op, right: r, span: pt.span }))
             } else {
-                return Err("operator must have one or two children".into());
+                Err(malformed(pt, "operator must have one or two children"))
+            }
+        }
+
+        // conditional expression: [ cond, then, else ]
+        Token::QUESTION => {
+            if pt.children.len() != 3 {
+                return Err(malformed(pt, "conditional must have three children"));
             }
+            let cond = lower(&pt.children[0], nodes)?;
+            let then_expr = lower(&pt.children[1], nodes)?;
+            let else_expr = lower(&pt.children[2], nodes)?;
+            Ok(nodes.alloc(MTree::TERNARY { cond, then_expr, else_expr, span: pt.span }))
+        }
+
+        // cast: [ expr, type ]
+        Token::CAST => {
+            let expr_node = pt.children.first().ok_or_else(|| malformed(pt, "cast missing expr"))?;
+            let type_node = pt.children.get(1).ok_or_else(|| malformed(pt, "cast missing type"))?;
+            let ty = Type::from_node(type_node).ok_or_else(|| malformed(pt, "Invalid cast target type"))?;
+            let expr = lower(expr_node, nodes)?;
+            Ok(nodes.alloc(MTree::CAST { expr, ty, span: pt.span }))
         }
 
         // parentheses wrap
         Token::PARENS_L => {
             
-            if pt.children.len() >= 1 {
-                from_parse_tree(&pt.children[0])
+            if !pt.children.is_empty() {
+                lower(&pt.children[0], nodes)
             } else {
-                Err("empty parens".into())
+                Err(malformed(pt, "empty parens"))
             }
         }
 
-        // identifiers - could be variable or function call
-        Token::ID { name } => {
-            
-            if pt.children.len() > 0 {
-                
-                let mut args = Vec::new();
-                for arg_node in &pt.children {
-                    args.push(from_parse_tree(arg_node)?);
-                }
-                Ok(MTree::CALL { name: name.clone(), args })
-            } else {
-                
-                Ok(MTree::ID { name: name.clone() })
+        // function call: [ ID(callee), args... ]
+        Token::CALL => {
+            let name = match pt.children.first().map(|c| &c.token) {
+                Some(Token::ID { name }) => *name,
+                _ => return Err(malformed(pt, "Expected ID as call target")),
+            };
+            let mut args = Vec::new();
+            for arg_node in &pt.children[1..] {
+                args.push(lower(arg_node, nodes)?);
             }
+            Ok(nodes.alloc(MTree::CALL { name, args, span: pt.span }))
         }
 
-        Token::LIT_INT32 { value } => Ok(MTree::LIT_INT { value: *value }),
-        Token::LIT_BOOL { value } => Ok(MTree::LIT_BOOL { value: *value }),
+        // identifiers
+        Token::ID { name } => Ok(nodes.alloc(MTree::id(*name, pt.span))),
+
+        // array literal: children are elements
+        Token::ARRAY_LIT => {
+            let mut elems = Vec::new();
+            for c in &pt.children {
+                elems.push(lower(c, nodes)?);
+            }
+            Ok(nodes.alloc(MTree::ARRAY_LIT { elems, span: pt.span }))
+        }
+
+        // index: [ base, index ]
+        Token::INDEX => {
+            let base_node = pt.children.first().ok_or_else(|| malformed(pt, "index missing base"))?;
+            let index_node = pt.children.get(1).ok_or_else(|| malformed(pt, "index missing expr"))?;
+            let base = lower(base_node, nodes)?;
+            let index = lower(index_node, nodes)?;
+            Ok(nodes.alloc(MTree::INDEX { base, index, span: pt.span }))
+        }
+
+        // member access: [ base, ID(field) ]
+        Token::MEMBER => {
+            let base_node = pt.children.first().ok_or_else(|| malformed(pt, "member access missing base"))?;
+            let field = match pt.children.get(1).map(|c| &c.token) {
+                Some(Token::ID { name }) => *name,
+                _ => return Err(malformed(pt, "Expected ID as member name")),
+            };
+            let base = lower(base_node, nodes)?;
+            Ok(nodes.alloc(MTree::MEMBER { base, field, span: pt.span }))
+        }
+
+        Token::LIT_INT32 { value } => Ok(nodes.alloc(MTree::LIT_INT { value: *value, span: pt.span })),
+        Token::LIT_FLT32 { value } => Ok(nodes.alloc(MTree::LIT_FLOAT { value: *value, span: pt.span })),
+        Token::LIT_BOOL { value } => Ok(nodes.alloc(MTree::LIT_BOOL { value: *value, span: pt.span })),
+        Token::LIT_CHAR { value } => Ok(nodes.alloc(MTree::LIT_CHAR { value: *value, span: pt.span })),
+        Token::LIT_STRING { value } => Ok(nodes.alloc(MTree::LIT_STRING { value: value.clone(), span: pt.span })),
+        Token::LIT_NONE => Ok(nodes.alloc(MTree::LIT_NONE { span: pt.span })),
 
         // unexpected / unhandled tokens
-        other => Err(format!("Unhandled token in converter: {:?}", other)),
+        other => Err(malformed(pt, format!("Unhandled token in converter: {:?}", other))),
+    }
+}
+
+// loops, break and continue carry an optional trailing LABEL child
+fn loop_label(pt: &ParseTree) -> Option<Symbol> {
+    match pt.children.last().map(|c| &c.token) {
+        Some(Token::LABEL { name }) => Some(*name),
+        _ => None,
     }
 }
 
 /// Semantic analyzer
 use std::collections::hash_map::Entry;
 
-pub fn analyze(tree: &MTree, symbols: &mut SymbolTable) -> Result<Type, Vec<String>> {
-    let mut errors: Vec<String> = Vec::new();
-    // collect function signatures up front for call checks
-    let mut function_sigs: HashMap<String, (Vec<Type>, Type)> = HashMap::new();
-    if let MTree::START { funcs } = tree {
-        for f in funcs {
-            if let MTree::FUNC_DECL { name, params, ret_type, .. } = f {
-                // param types
-                let ptypes: Vec<Type> = params.iter().map(|(_, t)| t.clone()).collect();
-                match function_sigs.entry(name.clone()) {
-                    Entry::Occupied(_) => {
-                        errors.push(format!("Function '{}' already declared", name));
-                    }
-                    Entry::Vacant(v) => { v.insert((ptypes, ret_type.clone())); }
+// give errors that have no location yet the span of `node`, the innermost
+// spanned node they arose under
+fn locate(errors: &mut [Diagnostic], node: &MTree) {
+    if let Some(span) = node.span() {
+        for error in errors {
+            error.span.get_or_insert(span);
+        }
+    }
+}
+
+// the globals a function's body names, by slot, and the calls it makes
+type GlobalUses = (HashSet<usize>, Vec<(Symbol, Span)>);
+
+// the globals a subtree of a resolved tree names, by slot, and the calls it
+// makes, with where
+fn global_uses(ast: &Ast, node: NodeId, uses: &mut HashSet<usize>, calls: &mut Vec<(Symbol, Span)>) {
+    match &ast[node] {
+        MTree::ID { slot: Slot::Global(i), .. }
+        | MTree::ASSIGN { slot: Slot::Global(i), .. }
+        | MTree::INDEX_ASSIGN { slot: Slot::Global(i), .. } => {
+            uses.insert(*i);
+        }
+        MTree::CALL { name, span, .. } => calls.push((*name, *span)),
+        _ => {}
+    }
+    for child in ast[node].children() {
+        stack::guarded(|| global_uses(ast, child, uses, calls));
+    }
+}
+
+// globals are set in order, each initializer seeing only the ones before
+// it; the initializers' own references are checked where they are, and
+// this finds the ones a called function makes on their behalf, directly
+// or through the functions it calls in turn
+fn check_init_order(ast: &Ast, errors: &mut Vec<Diagnostic>) {
+    let mut resolved = ast.clone();
+    resolve_slots(&mut resolved);
+    let MTree::START { funcs } = &resolved[resolved.root] else { return };
+    let mut functions: HashMap<Symbol, GlobalUses> = HashMap::new();
+    let mut names = Vec::new();
+    for &item in funcs {
+        match &resolved[item] {
+            MTree::FUNC_DECL { name, body, .. } => {
+                let (uses, calls) = functions.entry(*name).or_default();
+                global_uses(&resolved, *body, uses, calls);
+            }
+            MTree::LET_STMT { id, .. } | MTree::CONST_DECL { id, .. } => names.push(*id),
+            _ => {}
+        }
+    }
+    let mut index = 0;
+    for &item in funcs {
+        let (id, expr) = match &resolved[item] {
+            MTree::LET_STMT { id, expr, .. } => (*id, *expr),
+            MTree::CONST_DECL { id, expr, .. } => (*id, Some(*expr)),
+            _ => continue,
+        };
+        let mut calls = Vec::new();
+        if let Some(expr) = expr {
+            global_uses(&resolved, expr, &mut HashSet::new(), &mut calls);
+        }
+        for (callee, span) in calls {
+            // every global the call can reach
+            let mut reached: HashSet<usize> = HashSet::new();
+            let mut seen = HashSet::new();
+            let mut pending = vec![callee];
+            while let Some(f) = pending.pop() {
+                if let Some((uses, calls)) = functions.get(&f)
+                    && seen.insert(f)
+                {
+                    reached.extend(uses);
+                    pending.extend(calls.iter().map(|(name, _)| *name));
                 }
             }
+            if let Some(&late) = reached.iter().filter(|&&i| i >= index).min() {
+                errors.push(
+                    Diagnostic::error(
+                        diagnostics::UNASSIGNED,
+                        format!("Global '{}' calls '{}', which uses '{}' before it is initialized", id, callee, names[late]),
+                    )
+                    .at(span),
+                );
+            }
         }
+        index += 1;
     }
+}
 
-    fn has_return(node: &MTree) -> bool {
-        match node {
-            MTree::RTRN_STMT { .. } => true,
-            MTree::BLOCK { stmts } => stmts.iter().any(|s| has_return(s)),
-            MTree::IF_STMT { then_block, else_block, .. } => {
-                let then_has = has_return(then_block);
-                let else_has = else_block.as_ref().map(|b| has_return(b)).unwrap_or(false);
-                then_has || else_has
-            }
-            MTree::FUNC_DECL { body, .. } => has_return(body),
-            MTree::START { funcs } => funcs.iter().any(|f| has_return(f)),
-            _ => false,
+/// Program-wide declarations collected before function bodies are checked.
+#[derive(Debug, Default)]
+pub struct Globals {
+    pub functions: HashMap<Symbol, (Vec<Type>, Type)>,
+    pub enums: HashMap<String, Vec<String>>,
+    // variant name -> enum it belongs to
+    pub variants: HashMap<String, String>,
+    // program-scope constants
+    pub consts: HashSet<Symbol>,
+}
+
+impl Globals {
+    // report a write to `id` when it names a program-scope constant
+    fn check_mutable(&self, id: Symbol, symbols: &SymbolTable, errors: &mut Vec<Diagnostic>) {
+        if self.consts.contains(&id) && symbols.is_global(id) {
+            errors.push(Diagnostic::error(diagnostics::CONSTANT_ASSIGNED, format!("Cannot assign to constant '{}'", id)));
         }
     }
 
-    fn helper(node: &MTree, symbols: &mut SymbolTable, errors: &mut Vec<String>, function_sigs: &HashMap<String, (Vec<Type>, Type)>) -> Type {
-        match node {
-            MTree::START { funcs } => {
-                for f in funcs {
-                    helper(f, symbols, errors, function_sigs);
-                }
-                Type::Unknown
+    // report annotations naming an enum that was never declared
+    fn check_type(&self, ty: &Type, errors: &mut Vec<Diagnostic>) {
+        match ty {
+            Type::Enum(name) if !self.enums.contains_key(name) => {
+                let error = Diagnostic::error(diagnostics::UNDECLARED, format!("Unknown type '{}'", name));
+                errors.push(did_you_mean(error, name, self.enums.keys().map(String::as_str)));
             }
-            MTree::FUNC_DECL { name, params, ret_type, body } => {
-                // new local symbol table for this function
-                let mut local = SymbolTable::new();
-                for (pname, ptype) in params {
-                    let _ = local.declare(pname, ptype.clone());
-                }
-                let body_type = helper(body, &mut local, errors, function_sigs);
-                
-                // warn if declared return type doesn't match body
-                if *ret_type != Type::Unknown && body_type != *ret_type && body_type != Type::Unknown {
-                    errors.push(format!(
-                        "Function '{}' declared return type {:?}, but body returns {:?}",
-                        name, ret_type, body_type
-                    ));
-                }
-                // warn if function declares a return type but has no return
-                if *ret_type != Type::Unknown && !has_return(body) {
-                    errors.push(format!("Function '{}' declares return type {:?} but has no return statement", name, ret_type));
+            Type::Array(elem, _) | Type::Vec(elem) | Type::Option(elem) => self.check_type(elem, errors),
+            Type::Map(key, value) => {
+                if !matches!(**key, Type::Int | Type::Bool | Type::Char | Type::Str | Type::Enum(_) | Type::Unknown) {
+                    errors.push(format!("Map keys must be Int, Bool, Char, Str or an enum, found {:?}", key).into());
                 }
-                Type::Unknown
+                self.check_type(key, errors);
+                self.check_type(value, errors);
             }
-            MTree::BLOCK { stmts } => {
-                let mut last_type = Type::Unknown;
-                for s in stmts {
-                    last_type = helper(s, symbols, errors, function_sigs);
+            _ => {}
+        }
+    }
+}
+
+/// Type-check a converted program. Unless `default_init` is set, reading a
+/// variable that may not have been assigned yet is also an error; with it,
+/// such a variable holds its type's default value.
+pub fn analyze(ast: &Ast, symbols: &mut SymbolTable, default_init: bool) -> Result<Type, Vec<Diagnostic>> {
+    let mut errors: Vec<Diagnostic> = Vec::new();
+    // collect function signatures and enums up front for call checks
+    let mut globals = Globals::default();
+    if let MTree::START { funcs } = &ast[ast.root] {
+        for f in funcs {
+            let first = errors.len();
+            if let MTree::ENUM_DECL { name, variants, .. } = &ast[*f] {
+                if globals.enums.contains_key(name) {
+                    errors.push(Diagnostic::error(diagnostics::REDECLARED, format!("Enum '{}' already declared", name)));
+                    locate(&mut errors[first..], &ast[*f]);
+                    continue;
                 }
-                last_type
-            }
-            MTree::LET_STMT { id, ty, expr } => {
-                let inferred_ty = if let Some(expr_node) = expr {
-                    let et = helper(expr_node, symbols, errors, function_sigs);
-                    if *ty != Type::Unknown && et != *ty && et != Type::Unknown {
-                        errors.push(format!("Type mismatch for '{}': expected {:?}, found {:?}", id, ty, et));
+                for v in variants {
+                    if let Some(owner) = globals.variants.get(v) {
+                        errors.push(Diagnostic::error(diagnostics::REDECLARED, format!("Variant '{}' already declared in enum '{}'", v, owner)));
+                    } else {
+                        globals.variants.insert(v.clone(), name.clone());
                     }
-                    
-                    if *ty == Type::Unknown { et } else { ty.clone() }
-                } else {
-                    ty.clone()
-                };
-                
-                
-                let _ = symbols.declare(id, inferred_ty).map_err(|e| errors.push(e)).ok();
-                Type::Unknown
+                }
+                globals.enums.insert(name.clone(), variants.clone());
             }
-            MTree::ASSIGN { id, expr } => {
-                match symbols.check(id) {
-                    Ok(var_type) => {
-                        let expr_type = helper(expr, symbols, errors, function_sigs);
-                        if var_type != expr_type && expr_type != Type::Unknown && var_type != Type::Unknown {
-                            errors.push(format!("Assignment type mismatch for '{}': {:?} vs {:?}", id, var_type, expr_type));
+            locate(&mut errors[first..], &ast[*f]);
+        }
+        for f in funcs {
+            let first = errors.len();
+            if let MTree::FUNC_DECL { name, params, ret_type, .. } = &ast[*f] {
+                // param types
+                let ptypes: Vec<Type> = params.iter().map(|(_, t, _)| t.clone()).collect();
+                if is_builtin(name) || symbols.is_native(*name) {
+                    errors.push(Diagnostic::error(diagnostics::REDECLARED, format!("Function '{}' shadows a builtin function", name)));
+                }
+                match globals.functions.entry(*name) {
+                    Entry::Occupied(_) => {
+                        errors.push(Diagnostic::error(diagnostics::REDECLARED, format!("Function '{}' already declared", name)));
+                    }
+                    Entry::Vacant(v) => { v.insert((ptypes, ret_type.clone())); }
+                }
+            }
+            locate(&mut errors[first..], &ast[*f]);
+        }
+        // execution starts at `main`, which takes no arguments and returns
+        // nothing or an Int
+        let main = funcs.iter().map(|f| &ast[*f]).find(|f| matches!(f, MTree::FUNC_DECL { name, .. } if *name == "main"));
+        match main {
+            Some(MTree::FUNC_DECL { params, ret_type, span, .. }) => {
+                if !params.is_empty() {
+                    errors.push(Diagnostic::error(diagnostics::BAD_MAIN, "Function 'main' must not take parameters").at(*span));
+                }
+                if !matches!(ret_type, Type::Unknown | Type::Int) {
+                    errors.push(Diagnostic::error(diagnostics::BAD_MAIN, format!("Function 'main' must return Int or nothing, found {:?}", ret_type)).at(*span));
+                }
+            }
+            _ => errors.push(Diagnostic::error(diagnostics::BAD_MAIN, "Program has no 'main' function")),
+        }
+        // program-scope variables, in order, so each initializer sees only
+        // the globals before it; function bodies see them all
+        for f in funcs {
+            match &ast[*f] {
+                MTree::LET_STMT { .. } => {
+                    helper(ast, *f, symbols, &mut errors, &globals, &[]);
+                }
+                MTree::CONST_DECL { id, .. } => {
+                    helper(ast, *f, symbols, &mut errors, &globals, &[]);
+                    globals.consts.insert(*id);
+                }
+                _ => {}
+            }
+        }
+        check_init_order(ast, &mut errors);
+    }
+
+    // where control can run off the end of `node` without returning, or
+    // None when every path through it returns (or loops forever); `at` is
+    // the location to blame when `node` itself has none, such as an empty block
+    fn falls_off(ast: &Ast, node: NodeId, at: Span) -> Option<Span> {
+        match &ast[node] {
+            MTree::RTRN_STMT { .. } | MTree::BREAK_STMT { .. } | MTree::CONTINUE_STMT { .. } => None,
+            MTree::BLOCK { stmts, .. } => {
+                let mut end = Some(at);
+                for s in stmts {
+                    end = falls_off(ast, *s, at);
+                    if end.is_none() {
+                        break;
+                    }
+                }
+                end
+            }
+            MTree::IF_STMT { then_block, else_block: Some(else_block), span, .. } => {
+                falls_off(ast, *then_block, *span).or_else(|| falls_off(ast, *else_block, *span))
+            }
+            // a `loop` only finishes through a break aimed at it
+            MTree::LOOP_STMT { body, span, .. } => breaks_out(ast, *body, false, &[]).then_some(*span),
+            _ => ast.span(node).or(Some(at)),
+        }
+    }
+
+    // whether a break inside a loop body leaves that loop: an unlabeled
+    // break at its depth, or a labeled one past every loop nested inside it
+    fn breaks_out(ast: &Ast, node: NodeId, nested: bool, inner: &[Option<Symbol>]) -> bool {
+        match &ast[node] {
+            MTree::BREAK_STMT { label: None, .. } => !nested,
+            MTree::BREAK_STMT { label, .. } => !inner.contains(label),
+            MTree::BLOCK { stmts, .. } => stmts.iter().any(|s| breaks_out(ast, *s, nested, inner)),
+            MTree::IF_STMT { then_block, else_block, .. } => {
+                breaks_out(ast, *then_block, nested, inner) || else_block.is_some_and(|b| breaks_out(ast, b, nested, inner))
+            }
+            MTree::WHILE_STMT { body, label, .. }
+            | MTree::LOOP_STMT { body, label, .. }
+            | MTree::FOR_STMT { body, label, .. } => {
+                let mut inner = inner.to_vec();
+                inner.push(*label);
+                breaks_out(ast, *body, true, &inner)
+            }
+            _ => false,
+        }
+    }
+
+    fn has_bare_return(ast: &Ast, node: NodeId) -> bool {
+        match &ast[node] {
+            MTree::RTRN_STMT { expr, .. } => expr.is_none(),
+            MTree::BLOCK { stmts, .. } => stmts.iter().any(|s| has_bare_return(ast, *s)),
+            MTree::IF_STMT { then_block, else_block, .. } => {
+                has_bare_return(ast, *then_block) || else_block.is_some_and(|b| has_bare_return(ast, b))
+            }
+            MTree::WHILE_STMT { body, .. } | MTree::LOOP_STMT { body, .. } | MTree::FOR_STMT { body, .. } => {
+                has_bare_return(ast, *body)
+            }
+            _ => false,
+        }
+    }
+
+    // labels of the loops enclosing `node` within the current function,
+    // innermost last; None for unlabeled loops
+    fn enter_loop(loops: &[Option<Symbol>], label: &Option<Symbol>, errors: &mut Vec<Diagnostic>) -> Vec<Option<Symbol>> {
+        if let Some(l) = label
+            && loops.contains(label)
+        {
+            errors.push(Diagnostic::error(diagnostics::LOOP_CONTROL, format!("Label '{}' shadows an enclosing loop label", l)));
+        }
+        let mut inner = loops.to_vec();
+        inner.push(*label);
+        inner
+    }
+
+    // check a node, then locate the errors it raised that no nested node
+    // already claimed
+    fn helper(ast: &Ast, node: NodeId, symbols: &mut SymbolTable, errors: &mut Vec<Diagnostic>, globals: &Globals, loops: &[Option<Symbol>]) -> Type {
+        let first = errors.len();
+        let ty = stack::guarded(|| check_node(ast, node, symbols, errors, globals, loops));
+        locate(&mut errors[first..], &ast[node]);
+        ty
+    }
+
+    // check a block's statements in the current scope
+    fn check_stmts(ast: &Ast, stmts: &[NodeId], symbols: &mut SymbolTable, errors: &mut Vec<Diagnostic>, globals: &Globals, loops: &[Option<Symbol>]) {
+        for s in stmts {
+            helper(ast, *s, symbols, errors, globals, loops);
+        }
+    }
+
+    fn check_node(ast: &Ast, node: NodeId, symbols: &mut SymbolTable, errors: &mut Vec<Diagnostic>, globals: &Globals, loops: &[Option<Symbol>]) -> Type {
+        match &ast[node] {
+            MTree::START { funcs } => {
+                // globals were checked before any function body
+                for f in funcs {
+                    if !matches!(ast[*f], MTree::LET_STMT { .. } | MTree::CONST_DECL { .. }) {
+                        helper(ast, *f, symbols, errors, globals, loops);
+                    }
+                }
+                Type::Unknown
+            }
+            MTree::FUNC_DECL { name, params, ret_type, body, span, .. } => {
+                // new local symbol table for this function
+                let mut local = symbols.function_scope();
+                globals.check_type(ret_type, errors);
+                for (pname, ptype, pspan) in params {
+                    globals.check_type(ptype, errors);
+                    if let Err(first) = local.declare(*pname, ptype.clone(), *pspan) {
+                        errors.push(
+                            Diagnostic::error(diagnostics::REDECLARED, format!("Parameter '{}' of function '{}' already declared", pname, name))
+                                .at(*pspan)
+                                .with_note(format!("first declared at {}", first)),
+                        );
+                    }
+                }
+                // the body's outermost block shares the parameters' scope,
+                // so a let there cannot shadow a parameter
+                match &ast[*body] {
+                    MTree::BLOCK { stmts, .. } => check_stmts(ast, stmts, &mut local, errors, globals, &[]),
+                    _ => {
+                        helper(ast, *body, &mut local, errors, globals, &[]);
+                    }
+                }
+
+                // the returned values must agree with each other, and then
+                // with the declared return type
+                let mut returned: Option<(Type, Span)> = None;
+                for (ty, at) in &local.returns {
+                    returned = match returned {
+                        None => Some((ty.clone(), *at)),
+                        Some((so_far, first)) => match so_far.unify(ty) {
+                            Some(unified) => Some((unified, first)),
+                            None => {
+                                errors.push(
+                                    Diagnostic::error(diagnostics::RETURN_TYPE, format!("Function '{}' returns {:?} here but {:?} elsewhere", name, ty, so_far))
+                                        .at(*at)
+                                        .with_note(format!("{:?} returned at {}", so_far, first)),
+                                );
+                                Some((so_far, first))
+                            }
+                        },
+                    };
+                }
+                if let Some((body_type, at)) = returned {
+                    if *ret_type == Type::Unknown {
+                        let message = format!("Function '{}' has no return type but returns {:?}", name, body_type);
+                        errors.push(Diagnostic::error(diagnostics::RETURN_TYPE, message).at(at));
+                    } else if !ret_type.accepts(&body_type) {
+                        let message = format!("Function '{}' declared return type {:?}, but body returns {:?}", name, ret_type, body_type);
+                        errors.push(Diagnostic::error(diagnostics::RETURN_TYPE, message).at(at));
+                    }
+                }
+                // a bare `return;` only makes sense without a return type
+                if *ret_type != Type::Unknown && has_bare_return(ast, *body) {
+                    errors.push(Diagnostic::error(diagnostics::RETURN_TYPE, format!("Function '{}' declares return type {:?} but has a bare return", name, ret_type)));
+                }
+                // every path through a function with a return type must return
+                if *ret_type != Type::Unknown
+                    && let Some(end) = falls_off(ast, *body, *span)
+                {
+                    errors.push(
+                        Diagnostic::error(
+                            diagnostics::MISSING_RETURN,
+                            format!("Function '{}' declares return type {:?} but can reach the end without returning", name, ret_type),
+                        )
+                        .at(end),
+                    );
+                }
+                Type::Unknown
+            }
+            MTree::ENUM_DECL { .. } => Type::Unknown,
+            MTree::BLOCK { stmts, .. } => {
+                symbols.push_scope();
+                check_stmts(ast, stmts, symbols, errors, globals, loops);
+                symbols.pop_scope();
+                Type::Unknown
+            }
+            MTree::LET_STMT { id, ty, expr, span, .. } => {
+                globals.check_type(ty, errors);
+                let inferred_ty = if let Some(expr_node) = expr {
+                    let et = helper(ast, *expr_node, symbols, errors, globals, loops);
+                    if !ty.accepts(&et) {
+                        errors.push(format!("Type mismatch for '{}': expected {:?}, found {:?}", id, ty, et).into());
+                    }
+                    
+                    if *ty == Type::Unknown { et } else { ty.clone() }
+                } else {
+                    ty.clone()
+                };
+
+                // also catches a let reusing the name of a parameter
+                if let Err(first) = symbols.declare(*id, inferred_ty, *span) {
+                    errors.push(
+                        Diagnostic::error(diagnostics::REDECLARED, format!("Variable '{}' already declared", id))
+                            .with_note(format!("first declared at {}", first)),
+                    );
+                }
+                Type::Unknown
+            }
+            MTree::CONST_DECL { id, ty, expr, span, .. } => {
+                globals.check_type(ty, errors);
+                let et = helper(ast, *expr, symbols, errors, globals, loops);
+                if !ty.accepts(&et) {
+                    errors.push(format!("Type mismatch for '{}': expected {:?}, found {:?}", id, ty, et).into());
+                }
+                let inferred_ty = if *ty == Type::Unknown { et } else { ty.clone() };
+                if let Err(first) = symbols.declare(*id, inferred_ty, *span) {
+                    errors.push(
+                        Diagnostic::error(diagnostics::REDECLARED, format!("Variable '{}' already declared", id))
+                            .with_note(format!("first declared at {}", first)),
+                    );
+                }
+                Type::Unknown
+            }
+            // an assignment evaluates to the value stored, so has its target's type
+            MTree::ASSIGN { id, expr, .. } => {
+                globals.check_mutable(*id, symbols, errors);
+                match symbols.check(*id) {
+                    Ok(var_type) => {
+                        let expr_type = helper(ast, *expr, symbols, errors, globals, loops);
+                        if !var_type.accepts(&expr_type) {
+                            errors.push(format!("Assignment type mismatch for '{}': {:?} vs {:?}", id, var_type, expr_type).into());
+                        }
+                        var_type
+                    }
+                    Err(e) => {
+                        errors.push(did_you_mean(Diagnostic::error(diagnostics::UNDECLARED, e), id, symbols.variables()));
+                        Type::Unknown
+                    }
+                }
+            }
+            MTree::INDEX_ASSIGN { id, index, expr, .. } => {
+                globals.check_mutable(*id, symbols, errors);
+                let index_type = helper(ast, *index, symbols, errors, globals, loops);
+                if index_type != Type::Int && index_type != Type::Unknown {
+                    errors.push(format!("Array index must be Int, found {:?}", index_type).into());
+                }
+                let expr_type = helper(ast, *expr, symbols, errors, globals, loops);
+                match symbols.check(*id) {
+                    Ok(Type::Unknown) => Type::Unknown,
+                    Ok(var_type) => match var_type.element() {
+                        Some(elem) => {
+                            if !elem.accepts(&expr_type) {
+                                errors.push(format!("Element assignment type mismatch for '{}': {:?} vs {:?}", id, elem, expr_type).into());
+                            }
+                            elem
                         }
+                        None => {
+                            errors.push(format!("Cannot index '{}' of type {:?}", id, var_type).into());
+                            Type::Unknown
+                        }
+                    },
+                    Err(e) => {
+                        errors.push(did_you_mean(Diagnostic::error(diagnostics::UNDECLARED, e), id, symbols.variables()));
+                        Type::Unknown
                     }
-                    Err(e) => errors.push(e),
                 }
+            }
+            // checked against the function's return type once its body is done
+            MTree::RTRN_STMT { expr: Some(expr), span } => {
+                let ty = helper(ast, *expr, symbols, errors, globals, loops);
+                symbols.returns.push((ty, *span));
                 Type::Unknown
             }
-            MTree::RTRN_STMT { expr } => helper(expr, symbols, errors, function_sigs),
-            MTree::WHILE_STMT { cond, body } => {
+            MTree::RTRN_STMT { expr: None, .. } => Type::Unknown,
+            MTree::BREAK_STMT { label, .. } | MTree::CONTINUE_STMT { label, .. } => {
+                let keyword = if matches!(ast[node], MTree::BREAK_STMT { .. }) { "break" } else { "continue" };
+                if loops.is_empty() {
+                    errors.push(Diagnostic::error(diagnostics::LOOP_CONTROL, format!("'{}' outside of a loop", keyword)));
+                } else if let Some(l) = label
+                    && !loops.contains(label)
+                {
+                    errors.push(Diagnostic::error(diagnostics::LOOP_CONTROL, format!("'{}' to unknown loop label '{}'", keyword, l)));
+                }
+                Type::Unknown
+            }
+            MTree::WHILE_STMT { cond, body, label, .. } => {
                 // Check condition type
-                let cond_type = helper(cond, symbols, errors, function_sigs);
+                let cond_type = helper(ast, *cond, symbols, errors, globals, loops);
                 if cond_type != Type::Bool && cond_type != Type::Unknown {
-                    errors.push(format!("While condition must be Bool, found {:?}", cond_type));
+                    errors.push(format!("While condition must be Bool, found {:?}", cond_type).into());
                 }
                 // Analyze body
-                helper(body, symbols, errors, function_sigs);
+                let inner_loops = enter_loop(loops, label, errors);
+                helper(ast, *body, symbols, errors, globals, &inner_loops);
+                Type::Unknown
+            }
+            MTree::LOOP_STMT { body, label, .. } => {
+                let inner_loops = enter_loop(loops, label, errors);
+                helper(ast, *body, symbols, errors, globals, &inner_loops);
+                Type::Unknown
+            }
+            MTree::FOR_STMT { var, start, end, body, label, span, .. } => {
+                for bound in [start, end] {
+                    let bound_type = helper(ast, *bound, symbols, errors, globals, loops);
+                    if bound_type != Type::Int && bound_type != Type::Unknown {
+                        errors.push(format!("For range bounds must be Int, found {:?}", bound_type).into());
+                    }
+                }
+                // the loop variable is only visible inside the body
+                symbols.push_scope();
+                let _ = symbols.declare(*var, Type::Int, *span);
+                let inner_loops = enter_loop(loops, label, errors);
+                helper(ast, *body, symbols, errors, globals, &inner_loops);
+                symbols.pop_scope();
                 Type::Unknown
             }
-            MTree::IF_STMT { cond, then_block, else_block } => {
+            MTree::IF_STMT { cond, then_block, else_block, .. } => {
                 // Check condition type
-                let cond_type = helper(cond, symbols, errors, function_sigs);
+                let cond_type = helper(ast, *cond, symbols, errors, globals, loops);
                 if cond_type != Type::Bool && cond_type != Type::Unknown {
-                    errors.push(format!("If condition must be Bool, found {:?}", cond_type));
+                    errors.push(format!("If condition must be Bool, found {:?}", cond_type).into());
                 }
-                // Analyze then block
-                let then_type = helper(then_block, symbols, errors, function_sigs);
-                // Analyze else block if present
-                let else_type = if let Some(else_blk) = else_block {
-                    helper(else_blk, symbols, errors, function_sigs)
-                } else {
-                    Type::Unknown
-                };
-                
-                if then_type != Type::Unknown && else_type != Type::Unknown && then_type != else_type {
-                    errors.push(format!("If branches return different types: {:?} vs {:?}", then_type, else_type));
+                helper(ast, *then_block, symbols, errors, globals, loops);
+                if let Some(else_blk) = else_block {
+                    helper(ast, *else_blk, symbols, errors, globals, loops);
                 }
-                // Return the type if both branches agree
-                if then_type != Type::Unknown { then_type } else { else_type }
+                Type::Unknown
             }
-            MTree::PRINT_STMT { expr } => {
+            MTree::PRINT_STMT { expr, .. } => {
                 // Print can take any type, just check the expression is valid
-                helper(expr, symbols, errors, function_sigs);
+                helper(ast, *expr, symbols, errors, globals, loops);
                 Type::Unknown
             }
-            MTree::EXPR { left, op, right } => {
-                let rt = helper(right, symbols, errors, function_sigs);
-                
-                // Handle unary operators
-                if op == "!" {
-                    if rt != Type::Bool && rt != Type::Unknown {
-                        errors.push(format!("Unary NOT requires Bool type, found {:?}", rt));
-                    }
-                    return Type::Bool;
-                }
-                if op == "unary-" {
-                    if rt != Type::Int && rt != Type::Unknown {
-                        errors.push(format!("Unary minus requires Int type, found {:?}", rt));
-                    }
-                    return Type::Int;
-                }
-                
-                // Binary operators
-                let lt = helper(left, symbols, errors, function_sigs);
-                match op.as_str() {
-                    "+"|"-"|"*"|"/" => {
-                        
-                        if (lt != Type::Int && lt != Type::Unknown) || (rt != Type::Int && rt != Type::Unknown) {
-                            errors.push(format!("Arithmetic op '{}' requires Int types, found {:?} and {:?}", op, lt, rt));
+            MTree::UNARY { op, expr, .. } => {
+                let ty = helper(ast, *expr, symbols, errors, globals, loops);
+                match op {
+                    UnOp::Not => {
+                        if ty != Type::Bool && ty != Type::Unknown {
+                            errors.push(format!("Unary NOT requires Bool type, found {:?}", ty).into());
+                        }
+                        Type::Bool
+                    }
+                    UnOp::Neg => {
+                        if !ty.is_numeric() && ty != Type::Unknown {
+                            errors.push(format!("Unary minus requires a numeric type, found {:?}", ty).into());
+                        }
+                        if ty == Type::Float { Type::Float } else { Type::Int }
+                    }
+                }
+            }
+            MTree::EXPR { left, op, right, .. } => {
+                let rt = helper(ast, *right, symbols, errors, globals, loops);
+                let lt = helper(ast, *left, symbols, errors, globals, loops);
+                match op {
+                    // string concatenation
+                    BinOp::Add if lt == Type::Str || rt == Type::Str => {
+                        if !Type::Str.accepts(&lt) || !Type::Str.accepts(&rt) {
+                            errors.push(format!("String concatenation requires Str operands, found {:?} and {:?}", lt, rt).into());
+                        }
+                        Type::Str
+                    }
+                    BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Pow => {
+                        let numeric = |t: &Type| t.is_numeric() || *t == Type::Unknown;
+                        if !numeric(&lt) || !numeric(&rt) || (lt != rt && lt != Type::Unknown && rt != Type::Unknown) {
+                            errors.push(format!("Arithmetic op '{}' requires matching numeric types, found {:?} and {:?}", op, lt, rt).into());
+                        }
+                        if lt == Type::Float || rt == Type::Float { Type::Float } else { Type::Int }
+                    }
+                    BinOp::Rem => {
+                        let int = |t: &Type| *t == Type::Int || *t == Type::Unknown;
+                        if !int(&lt) || !int(&rt) {
+                            errors.push(format!("Modulo requires Int types, found {:?} and {:?}", lt, rt).into());
                         }
                         Type::Int
                     }
-                    "=="|"!=" => {
-                        if lt != rt && lt != Type::Unknown && rt != Type::Unknown {
-                            errors.push(format!("Comparison '{}' requires matching types, found {:?} and {:?}", op, lt, rt));
+                    BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor | BinOp::Shl | BinOp::Shr => {
+                        let int = |t: &Type| *t == Type::Int || *t == Type::Unknown;
+                        if !int(&lt) || !int(&rt) {
+                            errors.push(format!("Bitwise op '{}' requires Int types, found {:?} and {:?}", op, lt, rt).into());
+                        }
+                        Type::Int
+                    }
+                    BinOp::Eq | BinOp::Ne => {
+                        if !lt.accepts(&rt) && !rt.accepts(&lt) {
+                            errors.push(format!("Comparison '{}' requires matching types, found {:?} and {:?}", op, lt, rt).into());
                         }
                         Type::Bool
                     }
-                    "<"|">"|">="|"<=" => {
-                        
-                        if (lt != Type::Int && lt != Type::Unknown) || (rt != Type::Int && rt != Type::Unknown) {
-                            errors.push(format!("Relational op '{}' requires Int types, found {:?} and {:?}", op, lt, rt));
+                    BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge => {
+                        // strings compare lexicographically
+                        let ordered = |t: &Type| t.is_numeric() || matches!(t, Type::Char | Type::Str | Type::Unknown);
+                        if !ordered(&lt) || !ordered(&rt) || (lt != rt && lt != Type::Unknown && rt != Type::Unknown) {
+                            errors.push(format!("Relational op '{}' requires matching numeric, Char or Str types, found {:?} and {:?}", op, lt, rt).into());
                         }
                         Type::Bool
                     }
-                    "&&"|"||" => {
+                    BinOp::And | BinOp::Or => {
                         if (lt != Type::Bool && lt != Type::Unknown) || (rt != Type::Bool && rt != Type::Unknown) {
-                            errors.push(format!("Logical op '{}' requires Bool types, found {:?} and {:?}", op, lt, rt));
+                            errors.push(format!("Logical op '{}' requires Bool types, found {:?} and {:?}", op, lt, rt).into());
                         }
                         Type::Bool
                     }
-                    _ => Type::Unknown,
                 }
             }
-            MTree::CALL { name, args } => {
+            MTree::TERNARY { cond, then_expr, else_expr, .. } => {
+                let ct = helper(ast, *cond, symbols, errors, globals, loops);
+                if ct != Type::Bool && ct != Type::Unknown {
+                    errors.push(format!("Conditional expression requires a Bool condition, found {:?}", ct).into());
+                }
+                let tt = helper(ast, *then_expr, symbols, errors, globals, loops);
+                let et = helper(ast, *else_expr, symbols, errors, globals, loops);
+                if !tt.accepts(&et) && !et.accepts(&tt) {
+                    errors.push(format!("Conditional expression arms must match, found {:?} and {:?}", tt, et).into());
+                }
+                // prefer the arm that pins the type down, e.g. some(1) over none
+                if tt.is_partial() { et } else { tt }
+            }
+            MTree::CAST { expr, ty, .. } => {
+                globals.check_type(ty, errors);
+                let from = helper(ast, *expr, symbols, errors, globals, loops);
+                if !Type::can_cast(&from, ty) {
+                    errors.push(format!("Cannot cast {:?} to {:?}", from, ty).into());
+                }
+                ty.clone()
+            }
+            MTree::CALL { name, args, .. } => {
                 // evaluate argument types
                 let mut arg_types: Vec<Type> = Vec::new();
                 for arg in args {
-                    let at = helper(arg, symbols, errors, function_sigs);
+                    let at = helper(ast, *arg, symbols, errors, globals, loops);
                     arg_types.push(at);
                 }
+                // builtins take precedence over user functions
+                if let Some(ty) = check_builtin(ast, name, args, &arg_types, errors) {
+                    if matches!(name.as_str(), "push" | "pop" | "insert" | "remove")
+                        && let Some(MTree::ID { name: var, .. }) = args.first().map(|a| &ast[*a])
+                    {
+                        globals.check_mutable(*var, symbols, errors);
+                    }
+                    return ty;
+                }
+                // then the host's, which are only checked for arity
+                if let Some(&arity) = symbols.natives.get(name) {
+                    if arity != arg_types.len() {
+                        errors.push(Diagnostic::error(diagnostics::ARGUMENT_COUNT, format!("Function '{}' expects {} args but {} provided", name, arity, arg_types.len())));
+                    }
+                    return Type::Unknown;
+                }
                 // check against known function signatures
-                if let Some((param_types, ret_type)) = function_sigs.get(name) {
+                if let Some((param_types, ret_type)) = globals.functions.get(name) {
                     if param_types.len() != arg_types.len() {
-                        errors.push(format!("Function '{}' expects {} args but {} provided", name, param_types.len(), arg_types.len()));
+                        errors.push(Diagnostic::error(diagnostics::ARGUMENT_COUNT, format!("Function '{}' expects {} args but {} provided", name, param_types.len(), arg_types.len())));
                     } else {
                         for (i, (pt, at)) in param_types.iter().zip(arg_types.iter()).enumerate() {
-                            if pt != at && *pt != Type::Unknown && *at != Type::Unknown {
-                                errors.push(format!("Argument {} of '{}' expects {:?}, found {:?}", i+1, name, pt, at));
+                            if !pt.accepts(at) {
+                                errors.push(format!("Argument {} of '{}' expects {:?}, found {:?}", i+1, name, pt, at).into());
                             }
                         }
                     }
                     ret_type.clone()
+                } else if let Some(declared) = symbols.declared_at(*name) {
+                    errors.push(
+                        Diagnostic::error(diagnostics::NOT_CALLABLE, format!("'{}' is a variable, not a function", name))
+                            .with_note(format!("'{}' declared at {}", name, declared)),
+                    );
+                    Type::Unknown
                 } else {
-                    errors.push(format!("Call to unknown function '{}'", name));
+                    let error = Diagnostic::error(diagnostics::UNDECLARED, format!("Call to unknown function '{}'", name));
+                    let natives = symbols.natives.keys().map(|f| f.as_str());
+                    let functions = globals.functions.keys().map(|f| f.as_str()).chain(BUILTINS).chain(natives);
+                    errors.push(did_you_mean(error, name, functions));
                     Type::Unknown
                 }
             }
-            MTree::ID { name } => {
-                match symbols.check(name) {
+            MTree::ARRAY_LIT { elems, .. } => {
+                let mut elem_type = Type::Unknown;
+                for e in elems {
+                    let et = helper(ast, *e, symbols, errors, globals, loops);
+                    if elem_type == Type::Unknown {
+                        elem_type = et;
+                    } else if et != elem_type && et != Type::Unknown {
+                        errors.push(format!("Array elements must share one type, found {:?} and {:?}", elem_type, et).into());
+                    }
+                }
+                Type::Array(Box::new(elem_type), elems.len())
+            }
+            MTree::INDEX { base, index, .. } => {
+                let index_type = helper(ast, *index, symbols, errors, globals, loops);
+                if index_type != Type::Int && index_type != Type::Unknown {
+                    errors.push(format!("Array index must be Int, found {:?}", index_type).into());
+                }
+                match helper(ast, *base, symbols, errors, globals, loops) {
+                    Type::Unknown => Type::Unknown,
+                    base_type => base_type.element().unwrap_or_else(|| {
+                        errors.push(format!("Cannot index a value of type {:?}", base_type).into());
+                        Type::Unknown
+                    }),
+                }
+            }
+            // no type has fields yet, so any member access is an error
+            MTree::MEMBER { base, field, .. } => {
+                let base_type = helper(ast, *base, symbols, errors, globals, loops);
+                if base_type != Type::Unknown {
+                    errors.push(format!("Type {:?} has no field '{}'", base_type, field).into());
+                }
+                Type::Unknown
+            }
+            MTree::ID { name, .. } => {
+                match symbols.check(*name) {
                     Ok(ty) => ty,
+                    Err(_) if globals.variants.contains_key(name.as_str()) => Type::Enum(globals.variants[name.as_str()].clone()),
+                    Err(_) if globals.functions.contains_key(name) || is_builtin(name) || symbols.is_native(*name) => {
+                        errors.push(Diagnostic::error(diagnostics::NOT_CALLABLE, format!("'{}' is a function, not a value; call it as {}(...)", name, name)));
+                        Type::Unknown
+                    }
                     Err(e) => {
-                        errors.push(e);
+                        let variants = globals.variants.keys().map(String::as_str);
+                        errors.push(did_you_mean(Diagnostic::error(diagnostics::UNDECLARED, e), name, symbols.variables().chain(variants)));
                         Type::Unknown
                     }
                 }
             }
             MTree::LIT_INT { .. } => Type::Int,
+            MTree::LIT_FLOAT { .. } => Type::Float,
             MTree::LIT_BOOL { .. } => Type::Bool,
+            MTree::LIT_CHAR { .. } => Type::Char,
+            MTree::LIT_STRING { .. } => Type::Str,
+            MTree::LIT_NONE { .. } => Type::Option(Box::new(Type::Unknown)),
         }
     }
 
-    let ty = helper(tree, symbols, &mut errors, &function_sigs);
+    let ty = helper(ast, ast.root, symbols, &mut errors, &globals, &[]);
+    if !default_init {
+        check_initialized(ast, &mut errors);
+    }
     if errors.is_empty() { Ok(ty) } else { Err(errors) }
 }
 
-//constant folding
-pub fn fold_constants(node: &mut MTree) {
-    match node {
-        MTree::EXPR {left, right, op} => {
-            fold_constants(left);
-            fold_constants(right);
+// definite assignment: report reads of a variable declared without an
+// initializer that can happen before anything has been stored in it
+fn check_initialized(ast: &Ast, errors: &mut Vec<Diagnostic>) {
+    // the variables that may still be unassigned at some point, or None
+    // where that point is unreachable
+    type Unset = Option<HashSet<Symbol>>;
 
-            if let (MTree::LIT_INT { value: a }, MTree::LIT_INT { value: b }) = (&**left, &**right) {
-                let v = match op.as_str() {
-                    "+" => a + b,
-                    "-" => a - b,
-                    "*" => a * b,
-                    "/" => {
-                        if *b == 0 { return; }
-                        a / b
+    fn join(a: Unset, b: Unset) -> Unset {
+        match (a, b) {
+            (Some(mut a), Some(b)) => {
+                a.extend(b);
+                Some(a)
+            }
+            (a, None) => a,
+            (None, b) => b,
+        }
+    }
+
+    fn read(name: Symbol, span: Span, unset: &mut Unset, errors: &mut Vec<Diagnostic>) {
+        // reported once; later reads would only repeat the same mistake
+        if let Some(vars) = unset
+            && vars.remove(&name)
+        {
+            errors.push(Diagnostic::error(diagnostics::UNASSIGNED, format!("Variable '{}' may be used before it is assigned", name)).at(span));
+        }
+    }
+
+    // `loops` holds, for each enclosing loop, its label and what may be
+    // unset at the breaks that leave it
+    fn flow(ast: &Ast, node: NodeId, unset: &mut Unset, loops: &mut Vec<(Option<Symbol>, Unset)>, errors: &mut Vec<Diagnostic>) {
+        stack::guarded(|| flow_node(ast, node, unset, loops, errors))
+    }
+
+    fn flow_node(ast: &Ast, node: NodeId, unset: &mut Unset, loops: &mut Vec<(Option<Symbol>, Unset)>, errors: &mut Vec<Diagnostic>) {
+        match &ast[node] {
+            MTree::START { funcs } => {
+                for f in funcs {
+                    // a function may run before or after any other, so a
+                    // global has to start out with a value
+                    if let MTree::LET_STMT { id, expr: None, span, .. } = &ast[*f] {
+                        errors.push(Diagnostic::error(diagnostics::UNASSIGNED, format!("Program-scope variable '{}' needs an initializer", id)).at(*span));
                     }
-                    _ => return,
+                    flow(ast, *f, &mut Some(HashSet::new()), &mut Vec::new(), errors);
+                }
+            }
+            MTree::LET_STMT { id, expr, .. } => match expr {
+                Some(expr) => {
+                    flow(ast, *expr, unset, loops, errors);
+                    if let Some(vars) = unset {
+                        vars.remove(id);
+                    }
+                }
+                None => {
+                    if let Some(vars) = unset {
+                        vars.insert(*id);
+                    }
+                }
+            },
+            MTree::ASSIGN { id, expr, .. } => {
+                flow(ast, *expr, unset, loops, errors);
+                if let Some(vars) = unset {
+                    vars.remove(id);
+                }
+            }
+            // storing one element needs the rest of the array already there
+            MTree::INDEX_ASSIGN { id, index, expr, span, .. } => {
+                flow(ast, *index, unset, loops, errors);
+                flow(ast, *expr, unset, loops, errors);
+                read(*id, *span, unset, errors);
+            }
+            MTree::ID { name, span, .. } => read(*name, *span, unset, errors),
+            MTree::RTRN_STMT { expr, .. } => {
+                if let Some(expr) = expr {
+                    flow(ast, *expr, unset, loops, errors);
+                }
+                *unset = None;
+            }
+            MTree::CONTINUE_STMT { .. } => *unset = None,
+            MTree::BREAK_STMT { label, .. } => {
+                let target = match label {
+                    None => loops.last_mut(),
+                    Some(_) => loops.iter_mut().rev().find(|(l, _)| l == label),
                 };
-                *node = MTree::LIT_INT { value: v };
+                if let Some((_, exits)) = target {
+                    *exits = join(exits.take(), unset.take());
+                }
+                *unset = None;
+            }
+            MTree::IF_STMT { cond, then_block, else_block, .. } => {
+                flow(ast, *cond, unset, loops, errors);
+                let mut other = unset.clone();
+                flow(ast, *then_block, unset, loops, errors);
+                if let Some(else_block) = else_block {
+                    flow(ast, *else_block, &mut other, loops, errors);
+                }
+                *unset = join(unset.take(), other);
+            }
+            MTree::TERNARY { cond, then_expr, else_expr, .. } => {
+                flow(ast, *cond, unset, loops, errors);
+                let mut other = unset.clone();
+                flow(ast, *then_expr, unset, loops, errors);
+                flow(ast, *else_expr, &mut other, loops, errors);
+                *unset = join(unset.take(), other);
+            }
+            // the right operand may not run, so what it assigns doesn't count
+            MTree::EXPR { left, op: BinOp::And | BinOp::Or, right, .. } => {
+                flow(ast, *left, unset, loops, errors);
+                flow(ast, *right, &mut unset.clone(), loops, errors);
+            }
+            // the body may run zero times, and nothing it does can unassign
+            // a variable, so what is unset afterwards is what was before
+            MTree::WHILE_STMT { cond, body, label, .. } => {
+                flow(ast, *cond, unset, loops, errors);
+                loops.push((*label, None));
+                flow(ast, *body, &mut unset.clone(), loops, errors);
+                loops.pop();
+            }
+            MTree::FOR_STMT { var, start, end, body, label, .. } => {
+                flow(ast, *start, unset, loops, errors);
+                flow(ast, *end, unset, loops, errors);
+                if let Some(vars) = unset {
+                    vars.remove(var);
+                }
+                loops.push((*label, None));
+                flow(ast, *body, &mut unset.clone(), loops, errors);
+                loops.pop();
+            }
+            // a `loop` is only left through its breaks
+            MTree::LOOP_STMT { body, label, .. } => {
+                loops.push((*label, None));
+                flow(ast, *body, unset, loops, errors);
+                *unset = loops.pop().and_then(|(_, exits)| exits);
+            }
+            // a let shadowing an outer variable hides it until the block
+            // ends, so the outer one comes back as assigned as it was then
+            MTree::BLOCK { stmts, .. } => {
+                let mut shadowed: HashMap<Symbol, bool> = HashMap::new();
+                for s in stmts {
+                    if let MTree::LET_STMT { id, .. } = &ast[*s]
+                        && let Some(vars) = unset
+                    {
+                        shadowed.entry(*id).or_insert_with(|| vars.contains(id));
+                    }
+                    flow(ast, *s, unset, loops, errors);
+                }
+                if let Some(vars) = unset {
+                    for (id, was_unset) in shadowed {
+                        if was_unset {
+                            vars.insert(id);
+                        } else {
+                            vars.remove(&id);
+                        }
+                    }
+                }
+            }
+            MTree::FUNC_DECL { body, .. } => flow(ast, *body, &mut Some(HashSet::new()), &mut Vec::new(), errors),
+            _ => {
+                for child in ast[node].children() {
+                    flow(ast, child, unset, loops, errors);
+                }
             }
         }
-        _ => {}
+    }
+
+    flow(ast, ast.root, &mut Some(HashSet::new()), &mut Vec::new(), errors);
+}
+
+/// Lint pass over a converted program; reports suspicious but legal code.
+pub fn warnings(ast: &Ast) -> Vec<Diagnostic> {
+    // whether `node` can leave the loop it sits in: an unlabeled break at
+    // this loop's depth, a labeled break past every loop nested inside it
+    // (`inner` holds their labels), or a return from anywhere inside it
+    fn exits_loop(ast: &Ast, node: NodeId, nested: bool, inner: &[Option<Symbol>]) -> bool {
+        match &ast[node] {
+            MTree::BREAK_STMT { label: None, .. } => !nested,
+            MTree::BREAK_STMT { label, .. } => !inner.contains(label),
+            MTree::RTRN_STMT { .. } => true,
+            MTree::BLOCK { stmts, .. } => stmts.iter().any(|s| exits_loop(ast, *s, nested, inner)),
+            MTree::IF_STMT { then_block, else_block, .. } => {
+                exits_loop(ast, *then_block, nested, inner)
+                    || else_block.is_some_and(|b| exits_loop(ast, b, nested, inner))
+            }
+            MTree::WHILE_STMT { body, label, .. }
+            | MTree::LOOP_STMT { body, label, .. }
+            | MTree::FOR_STMT { body, label, .. } => {
+                let mut inner = inner.to_vec();
+                inner.push(*label);
+                exits_loop(ast, *body, true, &inner)
+            }
+            _ => false,
+        }
+    }
+
+    fn walk(ast: &Ast, node: NodeId, function: &str, warnings: &mut Vec<Diagnostic>) {
+        match &ast[node] {
+            MTree::START { funcs } => funcs.iter().for_each(|f| walk(ast, *f, function, warnings)),
+            MTree::FUNC_DECL { name, body, .. } => walk(ast, *body, name, warnings),
+            MTree::BLOCK { stmts, .. } => stmts.iter().for_each(|s| walk(ast, *s, function, warnings)),
+            MTree::IF_STMT { cond, then_block, else_block, .. } => {
+                // constant folding may leave a literal condition, and the
+                // optimizer then drops the branch that cannot run
+                match (&ast[*cond], else_block) {
+                    (MTree::LIT_BOOL { value: false, span }, _) => {
+                        let message = format!("Branch in function '{}' never runs: the 'if' condition is always false", function);
+                        warnings.push(Diagnostic::warning(diagnostics::UNREACHABLE_BRANCH, message).at(*span));
+                    }
+                    (MTree::LIT_BOOL { value: true, span }, Some(_)) => {
+                        let message = format!("'else' branch in function '{}' never runs: the 'if' condition is always true", function);
+                        warnings.push(Diagnostic::warning(diagnostics::UNREACHABLE_BRANCH, message).at(*span));
+                    }
+                    _ => {}
+                }
+                walk(ast, *then_block, function, warnings);
+                if let Some(b) = else_block {
+                    walk(ast, *b, function, warnings);
+                }
+            }
+            MTree::LOOP_STMT { body, span, .. } => {
+                if !exits_loop(ast, *body, false, &[]) {
+                    let message = format!("'loop' in function '{}' has no break or return and never ends", function);
+                    warnings.push(Diagnostic::warning(diagnostics::ENDLESS_LOOP, message).at(*span));
+                }
+                walk(ast, *body, function, warnings);
+            }
+            MTree::WHILE_STMT { cond, body, .. } => {
+                if let MTree::LIT_BOOL { value: false, span } = ast[*cond] {
+                    let message = format!("Loop body in function '{}' never runs: the 'while' condition is always false", function);
+                    warnings.push(Diagnostic::warning(diagnostics::UNREACHABLE_BRANCH, message).at(span));
+                }
+                walk(ast, *body, function, warnings);
+            }
+            MTree::FOR_STMT { body, .. } => walk(ast, *body, function, warnings),
+            _ => {}
+        }
+    }
+
+    // names read as variables and names called as functions under `node`;
+    // calls a function makes to itself are left out
+    fn uses(ast: &Ast, node: NodeId, function: Symbol, reads: &mut HashSet<Symbol>, calls: &mut HashSet<Symbol>) {
+        match &ast[node] {
+            MTree::ID { name, .. } => {
+                reads.insert(*name);
+            }
+            MTree::CALL { name, .. } if *name != function => {
+                calls.insert(*name);
+            }
+            _ => {}
+        }
+        for child in ast[node].children() {
+            stack::guarded(|| uses(ast, child, function, reads, calls));
+        }
+    }
+
+    // the locals declared anywhere under `node`
+    fn locals(ast: &Ast, node: NodeId, found: &mut Vec<(Symbol, Span)>) {
+        if let MTree::LET_STMT { id, span, .. } = &ast[node] {
+            found.push((*id, *span));
+        }
+        for child in ast[node].children() {
+            stack::guarded(|| locals(ast, child, found));
+        }
+    }
+
+    let mut warnings = Vec::new();
+    walk(ast, ast.root, "", &mut warnings);
+
+    // a leading underscore marks a name as deliberately unused
+    if let MTree::START { funcs } = &ast[ast.root] {
+        let mut calls = HashSet::new();
+        for f in funcs {
+            let mut reads = HashSet::new();
+            match &ast[*f] {
+                MTree::FUNC_DECL { name, params, body, .. } => {
+                    uses(ast, *body, *name, &mut reads, &mut calls);
+                    for (param, _, span) in params {
+                        if !reads.contains(param) && !param.starts_with('_') {
+                            let message = format!("Parameter '{}' of function '{}' is never read", param, name);
+                            warnings.push(Diagnostic::warning(diagnostics::UNREAD_PARAMETER, message).at(*span));
+                        }
+                    }
+                    let mut declared = Vec::new();
+                    locals(ast, *body, &mut declared);
+                    for (var, span) in declared {
+                        if !reads.contains(&var) && !var.starts_with('_') {
+                            let message = format!("Variable '{}' in function '{}' is never read", var, name);
+                            warnings.push(Diagnostic::warning(diagnostics::UNUSED_VARIABLE, message).at(span));
+                        }
+                    }
+                }
+                _ => uses(ast, *f, Symbol::default(), &mut reads, &mut calls),
+            }
+        }
+        for f in funcs {
+            if let MTree::FUNC_DECL { name, span, .. } = &ast[*f]
+                && *name != "main"
+                && !name.starts_with('_')
+                && !calls.contains(name)
+            {
+                warnings.push(Diagnostic::warning(diagnostics::UNCALLED_FUNCTION, format!("Function '{}' is never called", name)).at(*span));
+            }
+        }
+    }
+    warnings
+}
+
+/// Read the `// lang: allow(lint, ...)` comments in front of statements and
+/// declarations. Each names lints, by name or code, to silence within the
+/// node it precedes; annotations that cannot be understood are warned about.
+pub fn lint_allows(pt: &ParseTree) -> (Vec<Allow>, Vec<Diagnostic>) {
+    fn walk(node: &ParseTree, allows: &mut Vec<Allow>, warnings: &mut Vec<Diagnostic>) {
+        for comment in &node.leading.comments {
+            let text = comment.trim_start_matches("//").trim_start_matches("/*").trim_end_matches("*/").trim();
+            let Some(annotation) = text.strip_prefix("lang:").map(str::trim) else {
+                continue;
+            };
+            let lints = annotation
+                .strip_prefix("allow")
+                .and_then(|rest| rest.trim_start().strip_prefix('('))
+                .and_then(|rest| rest.strip_suffix(')'));
+            let Some(lints) = lints else {
+                let message = format!("Annotation 'lang: {}' is not of the form 'lang: allow(lint, ...)'", annotation);
+                warnings.push(Diagnostic::warning(diagnostics::BAD_ANNOTATION, message).at(node.span));
+                continue;
+            };
+            let (from, to) = node.extent();
+            for lint in lints.split(',').map(str::trim).filter(|l| !l.is_empty()) {
+                match diagnostics::lint_code(lint) {
+                    Some(code) => allows.push(Allow { code, from, to }),
+                    None => warnings.push(Diagnostic::warning(diagnostics::BAD_ANNOTATION, format!("Unknown lint '{}'", lint)).at(node.span)),
+                }
+            }
+        }
+        for child in &node.children {
+            stack::guarded(|| walk(child, allows, warnings));
+        }
+    }
+
+    let mut allows = Vec::new();
+    let mut warnings = Vec::new();
+    walk(pt, &mut allows, &mut warnings);
+    (allows, warnings)
+}
+
+/// Give every variable in the program its slot: program-scope variables
+/// an index into the globals, in declaration order, and each function's
+/// parameters and locals an index into its call's frame, parameters first.
+/// A block's slots are free again once it ends, so a frame only needs room
+/// for the locals live at once. This runs on the tree as it will be run,
+/// after the optimizer has renamed and inlined; a name that is no variable,
+/// such as an enum variant, stays Unresolved.
+pub fn resolve_slots(ast: &mut Ast) {
+    let MTree::START { funcs } = ast[ast.root].clone() else { return };
+    let mut resolver = Resolver::default();
+    // globals first, so every function sees all of them
+    for &item in &funcs {
+        if let MTree::LET_STMT { .. } | MTree::CONST_DECL { .. } = ast[item] {
+            resolver.visit(ast, item);
+        }
+    }
+    for &item in &funcs {
+        if let MTree::FUNC_DECL { params, body, .. } = &ast[item] {
+            let body = *body;
+            resolver.scopes = vec![params.iter().enumerate().map(|(i, (name, _, _))| (*name, i)).collect()];
+            resolver.next = params.len();
+            resolver.most = params.len();
+            resolver.visit(ast, body);
+            resolver.scopes.clear();
+            if let MTree::FUNC_DECL { frame, .. } = &mut ast[item] {
+                *frame = resolver.most;
+            }
+        }
+    }
+}
+
+// the slots declared so far: every global, and the locals of the function
+// being resolved by block, innermost last
+#[derive(Default)]
+struct Resolver {
+    globals: HashMap<Symbol, usize>,
+    scopes: Vec<HashMap<Symbol, usize>>,
+    // the next free frame slot, and the most the frame has needed
+    next: usize,
+    most: usize,
+}
+
+impl Resolver {
+    fn lookup(&self, name: Symbol) -> Slot {
+        match self.scopes.iter().rev().find_map(|scope| scope.get(&name)) {
+            Some(&i) => Slot::Local(i),
+            None => self.globals.get(&name).map_or(Slot::Unresolved, |&i| Slot::Global(i)),
+        }
+    }
+
+    // outside any function, declarations are program-scope
+    fn declare(&mut self, name: Symbol) -> Slot {
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                scope.insert(name, self.next);
+                self.next += 1;
+                self.most = self.most.max(self.next);
+                Slot::Local(self.next - 1)
+            }
+            None => {
+                let i = self.globals.len();
+                self.globals.insert(name, i);
+                Slot::Global(i)
+            }
+        }
+    }
+
+    fn visit(&mut self, ast: &mut Ast, node: NodeId) {
+        stack::guarded(|| self.visit_node(ast, node))
+    }
+
+    fn visit_node(&mut self, ast: &mut Ast, node: NodeId) {
+        match ast[node] {
+            // the initializer cannot see the variable it initializes
+            MTree::LET_STMT { id, expr, .. } => {
+                if let Some(expr) = expr {
+                    self.visit(ast, expr);
+                }
+                if let MTree::LET_STMT { slot, .. } = &mut ast[node] {
+                    *slot = self.declare(id);
+                }
+            }
+            MTree::CONST_DECL { id, expr, .. } => {
+                self.visit(ast, expr);
+                if let MTree::CONST_DECL { slot, .. } = &mut ast[node] {
+                    *slot = self.declare(id);
+                }
+            }
+            MTree::BLOCK { .. } => {
+                let next = self.next;
+                self.scopes.push(HashMap::new());
+                ast[node].children().into_iter().for_each(|stmt| self.visit(ast, stmt));
+                self.scopes.pop();
+                self.next = next;
+            }
+            MTree::FOR_STMT { var, start, end, body, .. } => {
+                self.visit(ast, start);
+                self.visit(ast, end);
+                let next = self.next;
+                self.scopes.push(HashMap::new());
+                if let MTree::FOR_STMT { slot, .. } = &mut ast[node] {
+                    *slot = self.declare(var);
+                }
+                self.visit(ast, body);
+                self.scopes.pop();
+                self.next = next;
+            }
+            MTree::ID { name, .. } => {
+                if let MTree::ID { slot, .. } = &mut ast[node] {
+                    *slot = self.lookup(name);
+                }
+            }
+            MTree::ASSIGN { .. } | MTree::INDEX_ASSIGN { .. } => {
+                ast[node].children().into_iter().for_each(|child| self.visit(ast, child));
+                if let MTree::ASSIGN { id, slot, .. } | MTree::INDEX_ASSIGN { id, slot, .. } = &mut ast[node] {
+                    *slot = self.lookup(*id);
+                }
+            }
+            _ => ast[node].children().into_iter().for_each(|child| self.visit(ast, child)),
+        }
+    }
+}
+
+const BUILTINS: [&str; 30] = [
+    "push", "pop", "len", "map", "insert", "get", "contains", "remove", "some", "is_some", "is_none", "unwrap",
+    "int_to_string", "read_int", "read_line", "read_file", "write_file", "arg_count", "arg_int", "arg_str", "rand", "exit", "abs", "min", "max", "pow", "sqrt", "floor", "ceil",
+    "checkpoint",
+];
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.contains(&name)
+}
+
+// how many single-character insertions, deletions, substitutions and swaps
+// of neighbours turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // d[i][j] is the distance between the first i of a and first j of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+// note the candidate closest to the unknown `name`, if it is near enough to
+// be a likely typo (and not a different name altogether, as 'a' is to 'y');
+// ties go to the alphabetically first, so output is stable
+fn did_you_mean<'a>(diagnostic: Diagnostic, name: &str, candidates: impl Iterator<Item = &'a str>) -> Diagnostic {
+    let length = name.chars().count();
+    let limit = (length / 3).max(1).min(length.saturating_sub(1));
+    let closest = candidates
+        .filter(|c| *c != name)
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(distance, _)| *distance <= limit)
+        .min();
+    match closest {
+        Some((_, candidate)) => diagnostic.with_note(format!("did you mean '{}'?", candidate)),
+        None => diagnostic,
+    }
+}
+
+/// Type-check a call to a builtin function, returning its result type, or
+/// None when `name` is not a builtin.
+fn check_builtin(ast: &Ast, name: &str, args: &[NodeId], arg_types: &[Type], errors: &mut Vec<Diagnostic>) -> Option<Type> {
+    let expected_args = match name {
+        "map" | "read_int" | "read_line" | "arg_count" | "checkpoint" => 0,
+        "pop" | "len" | "some" | "is_some" | "is_none" | "unwrap" | "int_to_string" | "read_file" | "arg_int" | "arg_str" | "exit" | "abs" | "sqrt" | "floor" | "ceil" => 1,
+        "push" | "get" | "contains" | "remove" | "write_file" | "rand" | "min" | "max" | "pow" => 2,
+        "insert" => 3,
+        _ => return None,
+    };
+    if arg_types.len() != expected_args {
+        errors.push(Diagnostic::error(diagnostics::ARGUMENT_COUNT, format!("Function '{}' expects {} args but {} provided", name, expected_args, arg_types.len())));
+        return Some(Type::Unknown);
+    }
+
+    // the collection is updated in place, so it must be a variable
+    if matches!(name, "push" | "pop" | "insert" | "remove") && !matches!(ast[args[0]], MTree::ID { .. }) {
+        errors.push(format!("'{}' expects a variable as its first argument", name).into());
+    }
+
+    match name {
+        "push" | "pop" => {
+            let elem = match &arg_types[0] {
+                Type::Vec(elem) => (**elem).clone(),
+                Type::Unknown => Type::Unknown,
+                other => {
+                    errors.push(format!("'{}' expects a vector, found {:?}", name, other).into());
+                    Type::Unknown
+                }
+            };
+            if name == "pop" {
+                return Some(elem);
+            }
+            if !elem.accepts(&arg_types[1]) {
+                errors.push(format!("Cannot push {:?} onto a vector of {:?}", arg_types[1], elem).into());
+            }
+            Some(Type::Unknown)
+        }
+        "len" => {
+            match &arg_types[0] {
+                Type::Array(..) | Type::Vec(_) | Type::Map(..) | Type::Str | Type::Unknown => {}
+                other => errors.push(format!("'len' expects an array, vector, map or string, found {:?}", other).into()),
+            }
+            Some(Type::Int)
+        }
+        "map" => Some(Type::Map(Box::new(Type::Unknown), Box::new(Type::Unknown))),
+        "read_int" => Some(Type::Int),
+        "read_line" => Some(Type::Str),
+        "arg_count" => Some(Type::Int),
+        "checkpoint" => Some(Type::Unknown),
+        "arg_int" | "arg_str" => {
+            if !Type::Int.accepts(&arg_types[0]) {
+                errors.push(format!("'{}' expects an Int index, found {:?}", name, arg_types[0]).into());
+            }
+            if name == "arg_int" { Some(Type::Int) } else { Some(Type::Str) }
+        }
+        "read_file" | "write_file" => {
+            for arg in arg_types {
+                if !Type::Str.accepts(arg) {
+                    errors.push(format!("'{}' expects Str, found {:?}", name, arg).into());
+                }
+            }
+            if name == "read_file" { Some(Type::Str) } else { Some(Type::Unknown) }
+        }
+        "exit" => {
+            if !Type::Int.accepts(&arg_types[0]) {
+                errors.push(format!("'exit' expects an Int status, found {:?}", arg_types[0]).into());
+            }
+            Some(Type::Unknown)
+        }
+        "rand" => {
+            for arg in arg_types {
+                if !Type::Int.accepts(arg) {
+                    errors.push(format!("'rand' expects Int bounds, found {:?}", arg).into());
+                }
+            }
+            Some(Type::Int)
+        }
+        "int_to_string" => {
+            if !Type::Int.accepts(&arg_types[0]) {
+                errors.push(format!("'int_to_string' expects Int, found {:?}", arg_types[0]).into());
+            }
+            Some(Type::Str)
+        }
+        "abs" => match &arg_types[0] {
+            ty @ (Type::Int | Type::Float | Type::Unknown) => Some(ty.clone()),
+            other => {
+                errors.push(format!("'abs' expects Int or Float, found {:?}", other).into());
+                Some(Type::Unknown)
+            }
+        },
+        // both operands the same numeric type, which is also the result's
+        "min" | "max" | "pow" => match (&arg_types[0], &arg_types[1]) {
+            (Type::Unknown, ty) | (ty, Type::Unknown) if matches!(ty, Type::Int | Type::Float | Type::Unknown) => Some(ty.clone()),
+            (l, r) if l == r && matches!(l, Type::Int | Type::Float) => Some(l.clone()),
+            (l, r) => {
+                errors.push(format!("'{}' expects two Ints or two Floats, found {:?} and {:?}", name, l, r).into());
+                Some(Type::Unknown)
+            }
+        },
+        "sqrt" | "floor" | "ceil" => {
+            if !Type::Float.accepts(&arg_types[0]) {
+                errors.push(format!("'{}' expects Float, found {:?}", name, arg_types[0]).into());
+            }
+            Some(Type::Float)
+        }
+        "some" => Some(Type::Option(Box::new(arg_types[0].clone()))),
+        "is_some" | "is_none" | "unwrap" => {
+            let inner = match &arg_types[0] {
+                Type::Option(inner) => (**inner).clone(),
+                Type::Unknown => Type::Unknown,
+                other => {
+                    errors.push(format!("'{}' expects an option, found {:?}", name, other).into());
+                    Type::Unknown
+                }
+            };
+            if name == "unwrap" { Some(inner) } else { Some(Type::Bool) }
+        }
+        _ => {
+            let (key, value) = match &arg_types[0] {
+                Type::Map(key, value) => ((**key).clone(), (**value).clone()),
+                Type::Unknown => (Type::Unknown, Type::Unknown),
+                other => {
+                    errors.push(format!("'{}' expects a map, found {:?}", name, other).into());
+                    (Type::Unknown, Type::Unknown)
+                }
+            };
+            if !key.accepts(&arg_types[1]) {
+                errors.push(format!("'{}' expects a {:?} key, found {:?}", name, key, arg_types[1]).into());
+            }
+            match name {
+                "insert" => {
+                    if !value.accepts(&arg_types[2]) {
+                        errors.push(format!("Cannot insert {:?} into a map of {:?}", arg_types[2], value).into());
+                    }
+                    Some(Type::Unknown)
+                }
+                "contains" => Some(Type::Bool),
+                _ => Some(value),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    // the errors analysis finds in `source`, which must parse
+    fn errors(source: &str) -> Vec<Diagnostic> {
+        let parse_tree = Parser::new(Lexer::new(source.to_string())).analyze().unwrap_or_else(|_| panic!("{} parses", source));
+        let ast = from_parse_tree(&parse_tree).unwrap_or_else(|e| panic!("{}: {}", source, e.message));
+        analyze(&ast, &mut SymbolTable::new(), false).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn initializer_cannot_call_a_function_reading_a_later_global() {
+        let errors = errors(
+            "let a: i32 = f();
+            let b: i32 = 5;
+            func f() -> i32 [ return b + 1; ]
+            func main() [ print a; ]",
+        );
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0].code, diagnostics::UNASSIGNED);
+        assert_eq!(errors[0].message, "Global 'a' calls 'f', which uses 'b' before it is initialized");
+        assert_eq!(errors[0].span.map(|span| span.line), Some(1));
+    }
+
+    #[test]
+    fn initializer_cannot_reach_its_own_global_through_calls() {
+        let errors = errors(
+            "let c: i32 = g();
+            func g() -> i32 [ return h(); ]
+            func h() -> i32 [ c = 2; return 1; ]
+            func main() [ print c; ]",
+        );
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0].message, "Global 'c' calls 'g', which uses 'c' before it is initialized");
+    }
+
+    #[test]
+    fn initializer_may_call_functions_using_earlier_globals() {
+        let errors = errors(
+            "let b: i32 = 5;
+            let a: i32 = f();
+            func f() -> i32 [ let a = 1; return a + b; ]
+            func main() [ print a; ]",
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
     }
 }
@@ -0,0 +1,16 @@
+// Tree walks recurse once per level of nesting, and a chain of operators
+// such as `x + x + ... + x` nests as deep as it is long, so a valid program
+// could outgrow any fixed stack. Each walk passes through `guarded` on its
+// way down, which moves onto a fresh stack segment whenever the current one
+// runs low; how deep a program may nest is then bounded by memory alone.
+
+// below this much stack left, the next level starts a new segment; enough
+// for the largest frames of an unoptimized build
+const RED_ZONE: usize = 256 * 1024;
+
+const SEGMENT: usize = 4 * 1024 * 1024;
+
+/// Run `f`, first moving onto a new stack segment if little is left.
+pub fn guarded<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, SEGMENT, f)
+}
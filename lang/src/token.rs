@@ -1,10 +1,46 @@
 #![allow(warnings)]
 
 use std::clone;
+use std::fmt;
 use std::mem::discriminant;
+use serde::{Deserialize, Serialize};
+use crate::intern::Symbol;
 use strum_macros::EnumIter;
 
-#[derive(Debug, Clone, EnumIter)]
+// 1-based source location of a token; ordered by position in the file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+// the comments and blank lines between a token and the one before it
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Leading {
+    pub comments: Vec<String>,
+    pub blank_lines: usize,
+}
+
+impl Leading {
+    pub fn is_empty(&self) -> bool {
+        self.comments.is_empty() && self.blank_lines == 0
+    }
+}
+
+// a token together with where it starts, as produced by lexer::tokenize
+#[derive(Debug, Clone, Serialize)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, EnumIter, Serialize)]
 pub enum Token {
     // Brackets
     PARENS_L,
@@ -20,14 +56,25 @@ pub enum Token {
     POINT,
     COMMA,
     COLON,
+    QUESTION,
     SEMICOLON,
     ARROW_R, // (->)
+    RANGE,   // (..)
 
     // Arithmetic Operators
     ADD,
     SUB,
     MUL,
     DIV,
+    MOD,
+    POW,
+
+    // Bitwise Operators
+    BIT_AND,
+    BIT_OR,
+    BIT_XOR,
+    SHL,
+    SHR,
 
     // Relational Operators
     EQ,
@@ -48,20 +95,31 @@ pub enum Token {
     // Keywords
     FUNC,
     LET,
+    CONST,
     IF,
     ELSE,
+    ENUM,
+    OPTION,
     WHILE,
+    LOOP,
+    FOR,
+    IN,
+    AS,
     PRINT,
+    BREAK,
+    CONTINUE,
     RETURN,
 
     // Identifiers
-    ID { name: String },
+    ID { name: Symbol },
+    LABEL { name: Symbol }, // loop label ('outer)
 
     // Basic Types
     TYPE_INT32,
     TYPE_FLT32,
     TYPE_CHAR,
     TYPE_BOOL,
+    TYPE_STRING,
 
     // Literals
     LIT_INT32 { value: i32 },
@@ -69,8 +127,14 @@ pub enum Token {
     LIT_CHAR { value: char },
     LIT_BOOL { value: bool },
     LIT_STRING { value: String },
+    LIT_NONE,
+
+    // trivia, only produced by a lexer built with_trivia
+    WHITESPACE { text: String },
+    COMMENT { text: String },
 
-    ERROR,
+    // lexical error: what went wrong and where
+    ERROR { message: String, span: Span },
 
     // End-of-Input
     EOI,
@@ -78,14 +142,28 @@ pub enum Token {
     // Metadata Nonterminals
     START,
     FUNC_DECL,
+    ENUM_DECL,
     PARAM_LIST,
     PARAM,
     BLOCK,
     IF_STMT,
     WHILE_STMT,
+    LOOP_STMT,
+    FOR_STMT,
     LET_STMT,
+    CONST_DECL,
     RTRN_STMT,
     EXPR,
+    ARRAY_TYPE,
+    VEC_TYPE,
+    MAP_TYPE,
+    OPTION_TYPE,
+    NAMED_TYPE,
+    ARRAY_LIT,
+    INDEX,
+    CALL,
+    CAST,
+    MEMBER,
 }
 
 impl PartialEq for Token {
@@ -97,7 +175,13 @@ impl PartialEq for Token {
 impl Token {
     pub fn id() -> Token {
         Token::ID {
-            name: String::new(),
+            name: Symbol::default(),
+        }
+    }
+
+    pub fn label() -> Token {
+        Token::LABEL {
+            name: Symbol::default(),
         }
     }
 
@@ -122,10 +206,105 @@ impl Token {
     pub fn lit_string() -> Token {
         Token::LIT_STRING { value: "".to_string() }
     }
+
+    pub fn error() -> Token {
+        Token::ERROR {
+            message: String::new(),
+            span: Span::default(),
+        }
+    }
+}
+
+// renders a token the way it is spelled in source; nonterminals and
+// EOI, which have no spelling, fall back to their variant name
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Token::PARENS_L => "(",
+            Token::PARENS_R => ")",
+            Token::BRACKET_L => "[",
+            Token::BRACKET_R => "]",
+            Token::BRACE_L => "{",
+            Token::BRACE_R => "}",
+            Token::POINT => ".",
+            Token::COMMA => ",",
+            Token::COLON => ":",
+            Token::QUESTION => "?",
+            Token::SEMICOLON => ";",
+            Token::ARROW_R => "->",
+            Token::RANGE => "..",
+            Token::ADD => "+",
+            Token::SUB => "-",
+            Token::MUL => "*",
+            Token::DIV => "/",
+            Token::MOD => "%",
+            Token::POW => "**",
+            Token::BIT_AND => "&",
+            Token::BIT_OR => "|",
+            Token::BIT_XOR => "^",
+            Token::SHL => "<<",
+            Token::SHR => ">>",
+            Token::EQ => "==",
+            Token::LT => "<",
+            Token::GT => ">",
+            Token::NEQ => "!=",
+            Token::NLT => ">=",
+            Token::NGT => "<=",
+            Token::NOT => "!",
+            Token::AND => "&&",
+            Token::OR => "||",
+            Token::ASSIGN => "=",
+            Token::FUNC => "func",
+            Token::LET => "let",
+            Token::CONST => "const",
+            Token::IF => "if",
+            Token::ELSE => "else",
+            Token::ENUM => "enum",
+            Token::OPTION => "option",
+            Token::WHILE => "while",
+            Token::LOOP => "loop",
+            Token::FOR => "for",
+            Token::IN => "in",
+            Token::AS => "as",
+            Token::PRINT => "print",
+            Token::BREAK => "break",
+            Token::CONTINUE => "continue",
+            Token::RETURN => "return",
+            Token::TYPE_INT32 => "i32",
+            Token::TYPE_FLT32 => "f32",
+            Token::TYPE_CHAR => "char",
+            Token::TYPE_BOOL => "bool",
+            Token::TYPE_STRING => "str",
+            Token::LIT_NONE => "none",
+            Token::ID { name } => return write!(f, "{name}"),
+            Token::LABEL { name } => return write!(f, "'{name}"),
+            Token::LIT_INT32 { value } => return write!(f, "{value}"),
+            Token::LIT_FLT32 { value } => return write!(f, "{value:?}"),
+            Token::LIT_CHAR { value } => return write!(f, "{value:?}"),
+            Token::LIT_BOOL { value } => return write!(f, "{value}"),
+            Token::LIT_STRING { value } => return write!(f, "{value:?}"),
+            Token::WHITESPACE { text } | Token::COMMENT { text } => return write!(f, "{text}"),
+            Token::ERROR { message, .. } => return write!(f, "error: {message}"),
+            other => return write!(f, "{other:?}"),
+        };
+        write!(f, "{text}")
+    }
 }
 
 impl Token {
     pub fn is_type(&self) -> bool {
-        matches!(self, Token::TYPE_INT32 | Token::TYPE_FLT32 | Token::TYPE_CHAR | Token::TYPE_BOOL)
+        matches!(
+            self,
+            Token::TYPE_INT32
+                | Token::TYPE_FLT32
+                | Token::TYPE_CHAR
+                | Token::TYPE_BOOL
+                | Token::TYPE_STRING
+                | Token::ARRAY_TYPE
+                | Token::VEC_TYPE
+                | Token::MAP_TYPE
+                | Token::OPTION_TYPE
+                | Token::NAMED_TYPE
+        )
     }
 }
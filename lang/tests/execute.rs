@@ -0,0 +1,306 @@
+// Runs programs through `lang execute` and checks what they print, the
+// errors they stop with and the status they exit with.
+
+use std::env;
+use std::fs;
+use std::process::Command;
+
+// what a run printed, to stdout and stderr, and the status it exited with
+struct Run {
+    stdout: String,
+    stderr: String,
+    status: i32,
+}
+
+impl Run {
+    // the program's own output, between the header the run prints first
+    // and the line saying how it ended
+    fn output(&self) -> &str {
+        let output = self.stdout.split_once("=== Program Execution ===\n").map_or("", |(_, output)| output);
+        output.rsplit_once("\n✓ ").map_or(output, |(output, _)| output)
+    }
+
+    // the `in 'f', called at ...` notes of a runtime error, innermost first
+    fn trace(&self) -> Vec<&str> {
+        self.stderr.lines().filter_map(|line| line.trim().strip_prefix("= note: ")).collect()
+    }
+}
+
+fn execute(name: &str, flags: &[&str], source: &str) -> Run {
+    let path = env::temp_dir().join(format!("lang-test-{}-{}.txt", std::process::id(), name));
+    fs::write(&path, source).expect("can write the program");
+    let output = Command::new(env!("CARGO_BIN_EXE_lang"))
+        .arg("execute")
+        .args(flags)
+        .arg(&path)
+        .output()
+        .expect("lang runs");
+    fs::remove_file(&path).ok();
+    Run {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        status: output.status.code().expect("lang exits with a status"),
+    }
+}
+
+// the same program under both engines, unoptimized and fully optimized
+fn execute_everywhere(name: &str, source: &str) -> Vec<Run> {
+    let mut runs = Vec::new();
+    for engine in ["interp", "vm"] {
+        for level in ["-O0", "-O2"] {
+            runs.push(execute(&format!("{}-{}{}", name, engine, level), &["--engine", engine, level], source));
+        }
+    }
+    runs
+}
+
+#[test]
+fn inlined_calls_stay_in_runtime_traces() {
+    let source = "
+func h(x: i32) -> i32 [
+    return 100 / x;
+]
+
+func g(x: i32) -> i32 [
+    let y = h(x) + 1;
+    return y;
+]
+
+func f(x: i32) -> i32 [
+    let a = g(x);
+    return a;
+]
+
+func main() [
+    print f(0);
+]
+";
+    let explained = execute("inlined-trace-explained", &["-O2", "--explain-opt"], source);
+    for function in ["f", "g"] {
+        assert!(explained.stdout.contains(&format!("inlined call to '{}'", function)), "{}", explained.stdout);
+    }
+    for run in execute_everywhere("inlined-trace", source) {
+        assert_eq!(run.status, 1);
+        assert_eq!(
+            run.trace(),
+            ["in 'h', called at 7:13", "in 'g', called at 12:13", "in 'f', called at 17:11", "in 'main'"],
+            "{}",
+            run.stderr
+        );
+    }
+}
+
+#[test]
+fn exit_sets_the_status_without_an_error() {
+    let source = "
+func stop(code: i32) -> i32 [
+    exit(code);
+    return 0;
+]
+
+func main() [
+    print 1;
+    let x = 2 + stop(3);
+    print x;
+]
+";
+    for run in execute_everywhere("exit", source) {
+        assert_eq!(run.status, 3);
+        assert_eq!(run.output(), "1\n");
+        assert!(run.stdout.ends_with("exited with status 3\n"), "{}", run.stdout);
+        assert!(!run.stderr.contains("error"), "{}", run.stderr);
+        assert!(run.trace().is_empty(), "{}", run.stderr);
+    }
+}
+
+#[test]
+fn callees_see_globals_not_the_callers_locals() {
+    let source = "
+let x: i32 = 1;
+
+func peek() -> i32 [
+    return x;
+]
+
+func poke() [
+    x = 5;
+]
+
+func main() [
+    let x = 2;
+    print peek();
+    poke();
+    print x;
+    print peek();
+]
+";
+    for run in execute_everywhere("frames-globals", source) {
+        assert_eq!(run.output(), "1\n2\n5\n", "{}", run.stderr);
+    }
+}
+
+#[test]
+fn callees_cannot_name_the_callers_locals() {
+    let read = "
+func peek() -> i32 [
+    return secret;
+]
+
+func main() [
+    let secret = 2;
+    print peek();
+]
+";
+    let assign = "
+func poke() [
+    secret = 5;
+]
+
+func main() [
+    let secret = 2;
+    poke();
+    print secret;
+]
+";
+    for (name, source) in [("frames-read", read), ("frames-assign", assign)] {
+        let run = execute(name, &[], source);
+        assert_eq!(run.status, 1);
+        assert!(run.stdout.contains("Variable 'secret' not declared"), "{}", run.stdout);
+    }
+}
+
+#[test]
+fn each_recursive_call_gets_a_fresh_frame() {
+    let source = "
+func depth(n: i32) -> i32 [
+    let mine = n * 10;
+    if n > 0 [
+        let below = depth(n - 1);
+        print below;
+    ]
+    mine = mine + 1;
+    return mine;
+]
+
+func main() [
+    print depth(3);
+]
+";
+    for run in execute_everywhere("frames-recursion", source) {
+        assert_eq!(run.output(), "1\n11\n21\n31\n", "{}", run.stderr);
+    }
+}
+
+#[test]
+fn assigning_an_array_copies_it() {
+    let source = "
+func main() [
+    let a = {1, 2, 3};
+    let b = a;
+    b[0] = 9;
+    print a;
+    print b;
+    let c = {0, 0, 0};
+    c = a;
+    a[2] = 7;
+    print a;
+    print c;
+]
+";
+    for run in execute_everywhere("copy-assign", source) {
+        assert_eq!(run.output(), "{1, 2, 3}\n{9, 2, 3}\n{1, 2, 7}\n{1, 2, 3}\n", "{}", run.stderr);
+    }
+}
+
+#[test]
+fn passing_an_array_copies_it() {
+    let source = "
+let g: [i32; 3] = {1, 2, 3};
+
+func scribble(a: [i32; 3]) -> i32 [
+    a[0] = 100;
+    return a[0];
+]
+
+func watch(a: [i32; 3]) -> i32 [
+    g[1] = 200;
+    return a[1];
+]
+
+func main() [
+    let a = {1, 2, 3};
+    print scribble(a);
+    print a;
+    print watch(g);
+    print g;
+]
+";
+    for run in execute_everywhere("copy-param", source) {
+        assert_eq!(run.output(), "100\n{1, 2, 3}\n2\n{1, 200, 3}\n", "{}", run.stderr);
+    }
+}
+
+#[test]
+fn assignments_to_non_variables_point_at_the_target() {
+    let literal = "
+func main() [
+    1 = 2;
+]
+";
+    let nested = "
+func main() [
+    let a = {1, 2};
+    a[0][1] = 9;
+]
+";
+    for (name, source, message, at) in [
+        ("assign-literal", literal, "Left side of assignment must be a variable or an indexed variable", "3:5"),
+        ("assign-nested", nested, "Indexed assignment target must be a variable", "4:5"),
+    ] {
+        let run = execute(name, &[], source);
+        assert_eq!(run.status, 1);
+        assert!(run.stderr.contains(&format!("error[E0300]: {}\n", message)), "{}", run.stderr);
+        assert!(run.stderr.contains(&format!(".txt:{}\n", at)), "{}", run.stderr);
+    }
+}
+
+#[test]
+fn resuming_checks_saved_globals_against_their_types() {
+    let counted = "
+let total: i32 = 0;
+
+func main() [
+    total = total + 5;
+    checkpoint();
+    print total;
+]
+";
+    let retyped = "
+let total: str = \"\";
+
+func main() [
+    print total;
+]
+";
+    let snapshot = env::temp_dir().join(format!("lang-test-{}-resume.json", std::process::id()));
+    let snapshot = snapshot.to_str().expect("temp paths are UTF-8");
+    assert_eq!(execute("resume-save", &["--snapshot", snapshot], counted).output(), "5\n");
+    assert_eq!(execute("resume-again", &["--resume", snapshot], counted).output(), "10\n");
+    let run = execute("resume-retyped", &["--resume", snapshot], retyped);
+    fs::remove_file(snapshot).ok();
+    assert_eq!(run.status, 1);
+    assert_eq!(run.output(), "");
+    assert!(run.stderr.contains("error[E0400]: Snapshot saved 'total' as 5, which is not a Str\n"), "{}", run.stderr);
+}
+
+#[test]
+fn number_literals_out_of_range_are_lexical_errors() {
+    for (name, kind, literal) in [("literal-int", "integer", "99999999999"), ("literal-float", "float", "1e99")] {
+        let run = execute(name, &[], &format!("func main() [\n    print {};\n]\n", literal));
+        assert_eq!(run.status, 1);
+        assert!(run.stderr.contains(&format!("error[E0100]: {} literal {} out of range\n", kind, literal)), "{}", run.stderr);
+        assert!(run.stderr.contains(".txt:2:11\n"), "{}", run.stderr);
+    }
+    let run = execute("literal-float-max", &[], "func main() [\n    print 3.4e38;\n]\n");
+    assert_eq!(run.output(), "3.4e38\n", "{}", run.stderr);
+}